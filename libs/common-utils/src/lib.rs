@@ -3,25 +3,246 @@ use rust_decimal::Decimal;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
+    // Assigned by the engine when the order is accepted; unique across engines and
+    // restarts, laid out like MatchResult::trade_id
+    #[serde(default)]
+    pub order_id: u64,
+    // Correlation ID set by the API router; the engine echoes it back in the OrderAck
+    #[serde(default)]
+    pub request_id: Option<String>,
     pub user_id: String,
+    #[serde(default = "default_market")]
+    pub market: String,
     pub price: Decimal,
     pub quantity: Decimal,
     pub side: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct OrderRequest {
     pub user_id: String,
+    #[serde(default = "default_market")]
+    pub market: String,
     pub price: String,
     pub quantity: String,
     pub side: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BatchOrderRequest {
+    pub orders: Vec<OrderRequest>,
+}
+
+pub fn default_market() -> String {
+    "SOL_USDC".into()
+}
+
+/// Markets with a running matching engine, from MARKETS (comma-separated), or just
+/// the default market if it isn't set.
+pub fn markets_from_env() -> Vec<String> {
+    let markets = std::env::var("MARKETS").unwrap_or_default();
+    let markets: Vec<String> = markets.split(',').map(str::trim).filter(|m| !m.is_empty()).map(String::from).collect();
+    if markets.is_empty() { vec![default_market()] } else { markets }
+}
+
+/// Candle intervals kept for every market, with their length in milliseconds.
+pub const CANDLE_INTERVALS: [(&str, i64); 4] = [("1m", 60_000), ("5m", 300_000), ("1h", 3_600_000), ("1d", 86_400_000)];
+
+/// Queue the matching engine for `market` consumes its commands from.
+pub fn order_queue(market: &str) -> String {
+    format!("ORDER_QUEUE:{}", market)
+}
+
+/// Everything an engine consumes from its market's `order_queue`. Each command is
+/// applied as a unit, so a batch or mass cancel can't be interleaved with other orders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineCommand {
+    PlaceOrder(Order),
+    // All orders are validated up front; one bad order rejects the whole batch
+    PlaceBatch {
+        request_id: String,
+        orders: Vec<Order>,
+    },
+    // Cancels the user's resting orders, optionally narrowed to a market and side
+    MassCancel {
+        request_id: String,
+        user_id: String,
+        market: Option<String>,
+        side: Option<String>,
+    },
+    // Cancels all of the user's orders unless re-armed within `timeout_ms`; 0 disarms
+    ArmDeadManSwitch {
+        request_id: String,
+        user_id: String,
+        timeout_ms: u64,
+    },
+}
+
+impl EngineCommand {
+    /// Markets whose engine must apply the command; empty for user-wide commands,
+    /// which every engine applies.
+    pub fn markets(&self) -> Vec<&str> {
+        let mut markets: Vec<&str> = match self {
+            EngineCommand::PlaceOrder(order) => vec![&order.market],
+            EngineCommand::PlaceBatch { orders, .. } => orders.iter().map(|o| o.market.as_str()).collect(),
+            EngineCommand::MassCancel { market, .. } => market.iter().map(String::as_str).collect(),
+            EngineCommand::ArmDeadManSwitch { .. } => Vec::new(),
+        };
+        markets.sort_unstable();
+        markets.dedup();
+        markets
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct MatchResult {
+    /// Unique across engines and restarts: the engine's epoch in the high bits, a
+    /// counter in the low 40. Trades from before epochs have small counter IDs
     pub trade_id: u64,
+    /// Market of the engine that matched it; trades from before markets were recorded are SOL_USDC
+    #[serde(default = "default_market")]
+    pub market: String,
     pub price: Decimal,
     pub quantity: Decimal,
     pub buyer_id: String,
     pub seller_id: String,
-}
\ No newline at end of file
+    /// Exchange-wide event sequence number, shared by every engine, assigned when the trade was matched
+    #[serde(default)]
+    pub sequence: u64,
+    /// When the engine matched the trade, Unix nanoseconds
+    #[serde(default)]
+    pub timestamp_ns: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Resting,
+    PartiallyFilled,
+    Filled,
+    Rejected,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Resting => "resting",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Rejected => "rejected",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderEventType {
+    Placed,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+impl OrderEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderEventType::Placed => "placed",
+            OrderEventType::Filled => "filled",
+            OrderEventType::Cancelled => "cancelled",
+            OrderEventType::Rejected => "rejected",
+        }
+    }
+}
+
+/// Pushed by the engine to `ORDER_EVENT_QUEUE` whenever an order changes. Every
+/// event carries the order's full state after the change, so the latest one alone
+/// describes the order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub order_id: u64,
+    pub event_type: OrderEventType,
+    pub status: OrderStatus,
+    pub user_id: String,
+    pub market: String,
+    pub side: String,
+    pub price: Decimal,
+    /// Quantity the order was placed with
+    pub quantity: Decimal,
+    pub remaining_quantity: Decimal,
+    // Only set on fills
+    pub trade_id: Option<u64>,
+    pub fill_price: Option<Decimal>,
+    pub fill_quantity: Option<Decimal>,
+    /// Why the order was rejected or cancelled
+    pub reason: Option<String>,
+    pub sequence: u64,
+    pub timestamp_ns: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OrderAck {
+    pub request_id: String,
+    pub order_id: Option<u64>,
+    pub status: OrderStatus,
+    pub fills: Vec<MatchResult>,
+    pub remaining_quantity: Decimal,
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub timestamp_ns: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CancelAck {
+    pub request_id: String,
+    pub cancelled_order_ids: Vec<u64>,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub timestamp_ns: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeadManSwitchAck {
+    pub request_id: String,
+    pub user_id: String,
+    pub armed: bool,
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub timestamp_ns: u64,
+}
+
+/// Published by the engine on `ORDER_ACKS` once a command has been processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineAck {
+    Order(OrderAck),
+    Batch { request_id: String, orders: Vec<OrderAck> },
+    Cancel(CancelAck),
+    DeadManSwitch(DeadManSwitchAck),
+}
+
+impl EngineAck {
+    pub fn request_id(&self) -> &str {
+        match self {
+            EngineAck::Order(ack) => &ack.request_id,
+            EngineAck::Batch { request_id, .. } => request_id,
+            EngineAck::Cancel(ack) => &ack.request_id,
+            EngineAck::DeadManSwitch(ack) => &ack.request_id,
+        }
+    }
+}
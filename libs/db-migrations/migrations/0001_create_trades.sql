@@ -0,0 +1,13 @@
+-- Fills persisted by db-processor. Price and quantity are 6-decimal fixed point
+-- (value * 1_000_000) to avoid NUMERIC overhead; timestamp is Unix milliseconds.
+CREATE TABLE IF NOT EXISTS trades (
+    trade_id BIGINT PRIMARY KEY,
+    market VARCHAR(16) NOT NULL,
+    buyer_id VARCHAR(44) NOT NULL,
+    seller_id VARCHAR(44) NOT NULL,
+    price BIGINT NOT NULL,
+    quantity BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS trades_market_timestamp_idx ON trades (market, timestamp);
@@ -0,0 +1,17 @@
+-- Latest state of every order accepted by the matching engine.
+-- Fixed point and millisecond conventions follow `trades`.
+CREATE TABLE orders (
+    order_id BIGINT PRIMARY KEY,
+    market VARCHAR(16) NOT NULL,
+    user_id VARCHAR(44) NOT NULL,
+    side VARCHAR(4) NOT NULL,
+    price BIGINT NOT NULL,
+    quantity BIGINT NOT NULL,
+    filled_quantity BIGINT NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX orders_user_idx ON orders (user_id, created_at);
+CREATE INDEX orders_market_status_idx ON orders (market, status);
@@ -0,0 +1,13 @@
+-- Append-only lifecycle of each order: accepted, filled, cancelled, rejected, ...
+CREATE TABLE order_events (
+    event_id BIGSERIAL PRIMARY KEY,
+    order_id BIGINT NOT NULL,
+    event_type VARCHAR(16) NOT NULL,
+    quantity BIGINT,
+    price BIGINT,
+    trade_id BIGINT,
+    reason TEXT,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX order_events_order_idx ON order_events (order_id, event_id);
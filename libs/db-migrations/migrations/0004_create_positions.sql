@@ -0,0 +1,11 @@
+-- Off-chain view of each user's position per market, derived from fills.
+-- size is signed (+ long, - short); pnl columns share the 6-decimal fixed point.
+CREATE TABLE positions (
+    user_id VARCHAR(44) NOT NULL,
+    market VARCHAR(16) NOT NULL,
+    size BIGINT NOT NULL DEFAULT 0,
+    avg_entry_price BIGINT NOT NULL DEFAULT 0,
+    realized_pnl BIGINT NOT NULL DEFAULT 0,
+    updated_at BIGINT NOT NULL,
+    PRIMARY KEY (user_id, market)
+);
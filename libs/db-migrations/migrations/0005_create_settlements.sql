@@ -0,0 +1,15 @@
+-- On-chain settlement status per trade, written by settlement-worker:
+-- pending -> submitted (signature) -> confirmed (slot), or failed once dead-lettered.
+-- IF NOT EXISTS adopts tables the worker created itself before migrations existed.
+CREATE TABLE IF NOT EXISTS settlements (
+    trade_id BIGINT PRIMARY KEY,
+    status VARCHAR(16) NOT NULL,
+    signature VARCHAR(88),
+    slot BIGINT,
+    attempts INT NOT NULL DEFAULT 0,
+    error TEXT,
+    updated_at BIGINT NOT NULL
+);
+
+ALTER TABLE settlements ADD COLUMN IF NOT EXISTS last_valid_block_height BIGINT;
+CREATE INDEX IF NOT EXISTS settlements_status_idx ON settlements (status);
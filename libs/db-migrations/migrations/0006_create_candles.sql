@@ -0,0 +1,14 @@
+-- OHLCV bars per market and interval ('1m', '5m', '1h', ...), keyed by the
+-- bar's open time in Unix milliseconds. Prices and volume are 6-decimal fixed point.
+CREATE TABLE candles (
+    market VARCHAR(16) NOT NULL,
+    interval VARCHAR(8) NOT NULL,
+    open_time BIGINT NOT NULL,
+    open BIGINT NOT NULL,
+    high BIGINT NOT NULL,
+    low BIGINT NOT NULL,
+    close BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    trade_count INT NOT NULL,
+    PRIMARY KEY (market, interval, open_time)
+);
@@ -0,0 +1,10 @@
+-- Funding rate per market and funding period. rate is 6-decimal fixed point
+-- per period (100 = 0.01%); prices follow the `trades` convention.
+CREATE TABLE funding_rates (
+    market VARCHAR(16) NOT NULL,
+    timestamp BIGINT NOT NULL,
+    rate BIGINT NOT NULL,
+    mark_price BIGINT NOT NULL,
+    index_price BIGINT NOT NULL,
+    PRIMARY KEY (market, timestamp)
+);
@@ -0,0 +1,6 @@
+-- Engine-assigned event sequence number and match time (Unix nanoseconds).
+-- `timestamp` stays in milliseconds and is now derived from timestamp_ns.
+ALTER TABLE trades ADD COLUMN sequence BIGINT;
+ALTER TABLE trades ADD COLUMN timestamp_ns BIGINT;
+
+CREATE UNIQUE INDEX trades_sequence_idx ON trades (sequence);
@@ -0,0 +1,5 @@
+-- Sequence numbers of the trades that set a candle's open and close, so trades
+-- arriving late or out of order can be merged into an existing candle correctly.
+ALTER TABLE candles
+    ADD COLUMN open_sequence BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN close_sequence BIGINT NOT NULL DEFAULT 0;
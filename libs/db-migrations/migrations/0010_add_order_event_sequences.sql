@@ -0,0 +1,6 @@
+-- Engine sequence numbers on order history. `orders` keeps the sequence of the
+-- event it reflects so a replayed or older event never overwrites a newer state;
+-- (order_id, sequence) identifies an event so a redelivered batch isn't recorded twice.
+ALTER TABLE orders ADD COLUMN last_sequence BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE order_events ADD COLUMN sequence BIGINT NOT NULL DEFAULT 0;
+CREATE UNIQUE INDEX order_events_order_sequence_idx ON order_events (order_id, sequence);
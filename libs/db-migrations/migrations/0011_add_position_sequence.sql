@@ -0,0 +1,3 @@
+-- Sequence of the last trade folded into each position, so the ledger can tell
+-- when a trade arrives out of order and the position needs rebuilding.
+ALTER TABLE positions ADD COLUMN last_sequence BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,26 @@
+-- Anchor events decoded from the program's transaction logs by the indexer,
+-- keyed by transaction and the event's position in its logs.
+CREATE TABLE program_events (
+    signature VARCHAR(88) NOT NULL,
+    event_index INT NOT NULL,
+    slot BIGINT NOT NULL,
+    -- Unix seconds, if the RPC node reported one
+    block_time BIGINT,
+    event_type VARCHAR(32) NOT NULL,
+    -- Set for trade_settled, to join with trades and settlements
+    trade_id BIGINT,
+    data JSONB NOT NULL,
+    PRIMARY KEY (signature, event_index)
+);
+
+CREATE INDEX program_events_slot_idx ON program_events (slot);
+CREATE INDEX program_events_type_slot_idx ON program_events (event_type, slot);
+CREATE INDEX program_events_trade_idx ON program_events (trade_id) WHERE trade_id IS NOT NULL;
+
+-- Newest transaction the indexer has fully processed per program; it resumes after it.
+CREATE TABLE indexer_cursor (
+    program_id VARCHAR(44) PRIMARY KEY,
+    slot BIGINT NOT NULL,
+    signature VARCHAR(88) NOT NULL,
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,70 @@
+-- Range-partition `trades` by trade time: one partition per UTC day, named
+-- trades_pYYYYMMDD. db-processor creates upcoming days ahead of time and, when
+-- retention is configured, archives and drops expired ones (see partitions.rs).
+-- Unique keys on a partitioned table must include the partition key, so the
+-- primary key becomes (trade_id, timestamp) and sequence is unique per timestamp.
+ALTER TABLE trades RENAME TO trades_unpartitioned;
+ALTER INDEX trades_market_timestamp_idx RENAME TO trades_unpartitioned_market_timestamp_idx;
+ALTER INDEX trades_sequence_idx RENAME TO trades_unpartitioned_sequence_idx;
+
+CREATE TABLE trades (
+    trade_id BIGINT NOT NULL,
+    market VARCHAR(16) NOT NULL,
+    buyer_id VARCHAR(44) NOT NULL,
+    seller_id VARCHAR(44) NOT NULL,
+    price BIGINT NOT NULL,
+    quantity BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    sequence BIGINT,
+    timestamp_ns BIGINT,
+    PRIMARY KEY (trade_id, timestamp)
+) PARTITION BY RANGE (timestamp);
+
+CREATE INDEX trades_market_timestamp_idx ON trades (market, timestamp);
+CREATE INDEX trades_buyer_idx ON trades (buyer_id, timestamp);
+CREATE INDEX trades_seller_idx ON trades (seller_id, timestamp);
+CREATE UNIQUE INDEX trades_sequence_idx ON trades (sequence, timestamp);
+
+-- Catches trades outside every daily partition, so an insert never fails because
+-- maintenance fell behind. db-processor moves rows out when it creates their day.
+CREATE TABLE trades_default PARTITION OF trades DEFAULT;
+
+-- A partition for every day from the oldest existing trade through today, then copy them over
+DO $$
+DECLARE
+    day_ms CONSTANT BIGINT := 86400000;
+    today BIGINT := (EXTRACT(EPOCH FROM now())::BIGINT * 1000 / 86400000) * 86400000;
+    day BIGINT;
+BEGIN
+    SELECT LEAST((MIN(timestamp) / day_ms) * day_ms, today) INTO day FROM trades_unpartitioned;
+    day := COALESCE(day, today);
+    WHILE day <= today LOOP
+        EXECUTE format(
+            'CREATE TABLE %I PARTITION OF trades FOR VALUES FROM (%s) TO (%s)',
+            'trades_p' || to_char(to_timestamp(day / 1000) AT TIME ZONE 'UTC', 'YYYYMMDD'), day, day + day_ms
+        );
+        day := day + day_ms;
+    END LOOP;
+END $$;
+
+INSERT INTO trades SELECT trade_id, market, buyer_id, seller_id, price, quantity, timestamp, sequence, timestamp_ns
+    FROM trades_unpartitioned;
+DROP TABLE trades_unpartitioned;
+
+-- Partitions archived to files and dropped, newest range_end = the retention horizon
+CREATE TABLE trade_archives (
+    partition_name VARCHAR(63) PRIMARY KEY,
+    range_start BIGINT NOT NULL,
+    range_end BIGINT NOT NULL,
+    path TEXT NOT NULL,
+    row_count BIGINT NOT NULL,
+    archived_at BIGINT NOT NULL
+);
+
+-- Net confirmed fill quantity per account from archived trades, so the settlement
+-- reconciler can still compare margin accounts against their full fill history
+CREATE TABLE archived_fills (
+    user_id VARCHAR(44) PRIMARY KEY,
+    size BIGINT NOT NULL,
+    fills BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Statements look up a wallet's deposits among the indexed program events.
+CREATE INDEX program_events_deposit_owner_idx ON program_events ((data->>'owner'), block_time)
+    WHERE event_type = 'collateral_deposited';
@@ -0,0 +1,13 @@
+-- Trades that arrived with the ID of another trade but different content, e.g. from
+-- an engine that reused IDs. `trades` can't enforce a unique trade_id across its
+-- partitions, so db-processor checks every batch and quarantines these here, out
+-- of `trades` and everything derived from it, instead of dropping them.
+CREATE TABLE trade_conflicts (
+    trade_id BIGINT NOT NULL,
+    -- The rejected row and the stored one it collided with (NULL if it collided
+    -- with another row of the same batch)
+    incoming JSONB NOT NULL,
+    existing JSONB,
+    detected_at BIGINT NOT NULL,
+    PRIMARY KEY (trade_id, incoming)
+);
@@ -0,0 +1,11 @@
+-- archived_fills only counted trades that were already confirmed when their
+-- partition was archived, so trades confirmed afterwards were lost to the
+-- reconciler. Archiving now keeps every trade's fill here instead, joined with
+-- settlements like `trades` is. archived_fills still holds the totals of
+-- partitions archived before this migration.
+CREATE TABLE archived_trades (
+    trade_id BIGINT PRIMARY KEY,
+    buyer_id VARCHAR(44) NOT NULL,
+    seller_id VARCHAR(44) NOT NULL,
+    quantity BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Positions folded from archived trades only. db-processor adds each partition
+-- to these as it archives it, so a position (or all of them) can be recomputed
+-- from here plus what is still in `trades`.
+CREATE TABLE position_snapshots (LIKE positions INCLUDING ALL);
+
+-- Partitions archived before snapshots existed are missing from position_snapshots;
+-- while any are, positions can't be recomputed
+ALTER TABLE trade_archives ADD COLUMN snapshotted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- Market of each archived fill, so the reconciler can compare positions per
+-- market. Trades archived so far were all matched by the SOL_USDC engine.
+ALTER TABLE archived_trades ADD COLUMN market VARCHAR(16) NOT NULL DEFAULT 'SOL_USDC';
+ALTER TABLE archived_trades ALTER COLUMN market DROP DEFAULT;
@@ -0,0 +1,166 @@
+//! Versioned Postgres schema shared by db-processor and settlement-worker.
+//!
+//! Migrations are the numbered files in `migrations/`, embedded at compile time
+//! and applied in order, each in its own transaction. `schema_migrations` records
+//! the version, name and SHA-256 of every applied file; an applied migration must
+//! never be edited, so a checksum mismatch stops the runner instead of guessing.
+//! Add a new numbered file for every schema change.
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tokio_postgres::Client;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.sql.as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($version:literal, $file:literal) => {
+        Migration {
+            version: $version,
+            name: $file,
+            sql: include_str!(concat!("../migrations/", $file, ".sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_trades"),
+    migration!(2, "0002_create_orders"),
+    migration!(3, "0003_create_order_events"),
+    migration!(4, "0004_create_positions"),
+    migration!(5, "0005_create_settlements"),
+    migration!(6, "0006_create_candles"),
+    migration!(7, "0007_create_funding"),
+    migration!(8, "0008_add_trade_event_stamp"),
+    migration!(9, "0009_add_candle_sequences"),
+    migration!(10, "0010_add_order_event_sequences"),
+    migration!(11, "0011_add_position_sequence"),
+    migration!(12, "0012_create_program_events"),
+    migration!(13, "0013_partition_trades"),
+    migration!(14, "0014_index_deposits_by_owner"),
+    migration!(15, "0015_create_trade_conflicts"),
+    migration!(16, "0016_create_archived_trades"),
+    migration!(17, "0017_create_position_snapshots"),
+    migration!(18, "0018_add_archived_trade_market"),
+];
+
+// Arbitrary key for pg_advisory_lock so concurrent runners apply migrations one at a time
+const MIGRATION_LOCK: i64 = 0x5045_5250_4d49_4752;
+
+/// Schema version this build expects.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Applies every pending migration and returns the versions applied.
+pub async fn migrate(client: &mut Client) -> Result<Vec<i32>> {
+    // Taken first: two runners racing on CREATE TABLE IF NOT EXISTS can both try to create it
+    client.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK]).await?;
+    let result = apply_pending(client).await;
+    client.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK]).await?;
+    result
+}
+
+async fn apply_pending(client: &mut Client) -> Result<Vec<i32>> {
+    create_version_table(client).await?;
+    // Re-read under the lock: another runner may have applied some meanwhile
+    let applied = verify_applied(client).await?;
+    let mut newly_applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains_key(&m.version)) {
+        let tx = client.transaction().await?;
+        tx.batch_execute(migration.sql).await.map_err(|e| anyhow::anyhow!("migration {} failed: {}", migration.name, e))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES ($1, $2, $3, $4)",
+            &[&migration.version, &migration.name, &migration.checksum(), &chrono::Utc::now().timestamp_millis()],
+        ).await?;
+        tx.commit().await?;
+        println!("📜 Applied migration {}", migration.name);
+        newly_applied.push(migration.version);
+    }
+    Ok(newly_applied)
+}
+
+/// Fails unless every migration this build knows about has been applied unchanged.
+/// Services call this on startup instead of creating tables themselves.
+pub async fn ensure_current(client: &Client) -> Result<()> {
+    create_version_table(client).await?;
+    let applied = verify_applied(client).await?;
+    let pending: Vec<&str> = MIGRATIONS.iter().filter(|m| !applied.contains_key(&m.version)).map(|m| m.name).collect();
+    if !pending.is_empty() {
+        bail!("database schema is missing migrations {:?}; run `db-processor migrate`", pending);
+    }
+    Ok(())
+}
+
+/// Every known migration with the time it was applied, if it has been.
+pub async fn status(client: &Client) -> Result<Vec<(&'static Migration, Option<i64>)>> {
+    create_version_table(client).await?;
+    let rows = client.query("SELECT version, applied_at FROM schema_migrations", &[]).await?;
+    let applied: HashMap<i32, i64> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+    Ok(MIGRATIONS.iter().map(|m| (m, applied.get(&m.version).copied())).collect())
+}
+
+async fn create_version_table(client: &Client) -> Result<()> {
+    client.batch_execute("
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum CHAR(64) NOT NULL,
+            applied_at BIGINT NOT NULL
+        );
+    ").await?;
+    Ok(())
+}
+
+/// Applied versions and checksums, after checking each matches the embedded file.
+/// Versions newer than this build are tolerated so an older binary keeps running
+/// during a rolling deploy.
+async fn verify_applied(client: &Client) -> Result<HashMap<i32, String>> {
+    let rows = client.query("SELECT version, name, checksum FROM schema_migrations", &[]).await?;
+    let mut applied = HashMap::with_capacity(rows.len());
+    for row in rows {
+        let (version, name, checksum): (i32, String, String) = (row.get(0), row.get(1), row.get(2));
+        match MIGRATIONS.iter().find(|m| m.version == version) {
+            Some(known) if known.checksum() != checksum => {
+                bail!("migration {} was edited after being applied (checksum mismatch)", known.name);
+            }
+            Some(_) => {}
+            None => eprintln!("⚠️ Database has migration {} ({}) unknown to this build", version, name),
+        }
+        applied.insert(version, checksum);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_numbered_consecutively_and_all_embedded() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i32 + 1, "{} is out of sequence", m.name);
+            assert!(m.name.starts_with(&format!("{:04}_", m.version)), "{} doesn't match its version", m.name);
+        }
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter_map(|f| f.strip_suffix(".sql").map(str::to_string))
+            .collect();
+        files.sort();
+        let embedded: Vec<&str> = MIGRATIONS.iter().map(|m| m.name).collect();
+        assert_eq!(files, embedded, "every file in migrations/ must be listed in MIGRATIONS");
+    }
+}
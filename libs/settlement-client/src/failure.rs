@@ -0,0 +1,101 @@
+use hybrid_perp_dex::error::PerpError;
+use solana_client::client_error::ClientError;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::pubkey::ParsePubkeyError;
+use solana_sdk::transaction::TransactionError;
+
+use crate::types::SettlementError;
+
+/// Whether retrying a failed settlement can help.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The program rejected the trade itself; retrying will fail the same way.
+    Permanent(String),
+    /// RPC, network or cluster trouble, a nonce that was refreshed since, or a
+    /// program error that isn't about the trade (accounts, signatures).
+    Transient(String),
+}
+
+impl Failure {
+    pub fn reason(&self) -> &str {
+        match self {
+            Failure::Permanent(reason) | Failure::Transient(reason) => reason,
+        }
+    }
+}
+
+/// Classifies an error returned by `SettlementClient`. Anything not recognised as
+/// a program rejection is treated as transient, so callers bound it with retries.
+pub fn classify(err: &anyhow::Error) -> Failure {
+    if err.downcast_ref::<ParsePubkeyError>().is_some() {
+        return Failure::Permanent(format!("Invalid account in trade: {}", err));
+    }
+
+    let tx_err = match (err.downcast_ref::<SettlementError>(), err.downcast_ref::<ClientError>()) {
+        (Some(SettlementError::SimulationFailed { err, .. } | SettlementError::TransactionFailed { err, .. }), _) => {
+            Some(err.clone())
+        }
+        (_, Some(client_err)) => client_err.get_transaction_error(),
+        _ => None,
+    };
+    match tx_err {
+        Some(tx_err) => classify_transaction_error(&tx_err),
+        None => Failure::Transient(err.to_string()),
+    }
+}
+
+pub fn classify_transaction_error(err: &TransactionError) -> Failure {
+    match err {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => match PerpError::from_code(*code) {
+            Some(e) if is_business_rejection(e) => Failure::Permanent(format!("{}: {}", e.name(), e)),
+            // Signature, message and account errors point at the relayer, a rotation
+            // or a deploy rather than the trade, so they get the bounded retries
+            Some(e) => Failure::Transient(format!("{}: {}", e.name(), e)),
+            // Anchor's own constraint errors (wrong or missing accounts) and precompile failures
+            None => Failure::Transient(format!("Custom program error {}", code)),
+        },
+        other => Failure::Transient(other.to_string()),
+    }
+}
+
+/// Rejections of the trade itself, which fail the same way however often it's sent.
+fn is_business_rejection(err: PerpError) -> bool {
+    matches!(
+        err,
+        PerpError::PriceOutOfBounds
+            | PerpError::ZeroPositionSize
+            | PerpError::InsufficientCollateral
+            | PerpError::MathOverflow
+            | PerpError::MaxPositionsReached
+            | PerpError::SelfTrade
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_errors_are_decoded_from_custom_codes() {
+        let custom = |e: PerpError| TransactionError::InstructionError(1, InstructionError::Custom(e.into()));
+
+        assert_eq!(
+            classify_transaction_error(&custom(PerpError::InsufficientCollateral)),
+            Failure::Permanent("InsufficientCollateral: Insufficient collateral".into())
+        );
+        assert!(matches!(classify_transaction_error(&custom(PerpError::StaleNonce)), Failure::Transient(_)));
+        assert!(matches!(classify_transaction_error(&custom(PerpError::InvalidEngineSigner)), Failure::Transient(_)));
+        assert!(matches!(classify_transaction_error(&TransactionError::BlockhashNotFound), Failure::Transient(_)));
+        // Only rejections of the trade itself skip the retries
+        assert!(matches!(classify_transaction_error(&custom(PerpError::SelfTrade)), Failure::Permanent(_)));
+        assert!(matches!(classify_transaction_error(&custom(PerpError::BatchAccountsMismatch)), Failure::Transient(_)));
+        let anchor_constraint = TransactionError::InstructionError(1, InstructionError::Custom(2006));
+        assert!(matches!(classify_transaction_error(&anchor_constraint), Failure::Transient(_)));
+        let missing_account = TransactionError::InstructionError(1, InstructionError::NotEnoughAccountKeys);
+        assert!(matches!(classify_transaction_error(&missing_account), Failure::Transient(_)));
+
+        for (i, e) in PerpError::ALL.into_iter().enumerate() {
+            assert_eq!(u32::from(e), 6000 + i as u32, "PerpError::ALL is out of order at {}", e.name());
+        }
+    }
+}
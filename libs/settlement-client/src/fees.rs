@@ -0,0 +1,83 @@
+use crate::MAX_COMPUTE_UNITS;
+
+/// How settlement transactions bid for block space.
+#[derive(Debug, Clone)]
+pub struct PriorityFeeConfig {
+    /// Percentile (0-100) of recent non-zero prioritization fees to pay.
+    pub percentile: u8,
+    /// Never pay more than this per compute unit, in micro-lamports.
+    pub max_micro_lamports: u64,
+    /// Paid when the network reports no recent priority fees.
+    pub min_micro_lamports: u64,
+    /// Headroom added on top of the simulated compute units, in percent.
+    pub compute_unit_margin_pct: u32,
+}
+
+impl Default for PriorityFeeConfig {
+    fn default() -> Self {
+        Self {
+            percentile: 75,
+            max_micro_lamports: 1_000_000,
+            min_micro_lamports: 0,
+            compute_unit_margin_pct: 10,
+        }
+    }
+}
+
+impl PriorityFeeConfig {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            percentile: var("PRIORITY_FEE_PERCENTILE").unwrap_or(default.percentile).min(100),
+            max_micro_lamports: var("PRIORITY_FEE_MAX_MICRO_LAMPORTS").unwrap_or(default.max_micro_lamports),
+            min_micro_lamports: var("PRIORITY_FEE_MIN_MICRO_LAMPORTS").unwrap_or(default.min_micro_lamports),
+            compute_unit_margin_pct: var("COMPUTE_UNIT_MARGIN_PCT").unwrap_or(default.compute_unit_margin_pct),
+        }
+    }
+
+    /// Compute unit price to bid given the fees recently paid for the same accounts.
+    pub fn unit_price(&self, recent_fees: &[u64]) -> u64 {
+        let mut fees: Vec<u64> = recent_fees.iter().copied().filter(|f| *f > 0).collect();
+        if fees.is_empty() {
+            return self.min_micro_lamports.min(self.max_micro_lamports);
+        }
+        fees.sort_unstable();
+        let idx = ((fees.len() - 1) * self.percentile as usize).div_ceil(100);
+        fees[idx].max(self.min_micro_lamports).min(self.max_micro_lamports)
+    }
+
+    /// Compute unit limit for a transaction that consumed `simulated` units.
+    pub fn unit_limit(&self, simulated: u64) -> u32 {
+        let with_margin = simulated + simulated * self.compute_unit_margin_pct as u64 / 100;
+        with_margin.clamp(1, MAX_COMPUTE_UNITS as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_price_follows_percentile_within_bounds() {
+        let config = PriorityFeeConfig { percentile: 50, max_micro_lamports: 500, min_micro_lamports: 10, compute_unit_margin_pct: 10 };
+        assert_eq!(config.unit_price(&[]), 10);
+        assert_eq!(config.unit_price(&[0, 0, 0]), 10);
+        assert_eq!(config.unit_price(&[0, 100, 300, 200]), 200);
+        assert_eq!(config.unit_price(&[5, 5, 5]), 10);
+        assert_eq!(config.unit_price(&[1_000, 2_000, 3_000]), 500);
+
+        let top = PriorityFeeConfig { percentile: 100, ..config };
+        assert_eq!(top.unit_price(&[100, 300, 200]), 300);
+    }
+
+    #[test]
+    fn unit_limit_adds_margin_and_respects_cap() {
+        let config = PriorityFeeConfig::default();
+        assert_eq!(config.unit_limit(100_000), 110_000);
+        assert_eq!(config.unit_limit(0), 1);
+        assert_eq!(config.unit_limit(5_000_000), MAX_COMPUTE_UNITS);
+    }
+}
@@ -0,0 +1,218 @@
+//! Loading ed25519 keypairs for the engine signer and the relayer.
+//!
+//! A key can come from, in order of precedence:
+//! - `{PREFIX}_KEYSTORE_PATH`: a password-encrypted keystore (see [`encrypt`]), unlocked
+//!   with `{PREFIX}_KEYSTORE_PASSWORD` or the first line of `{PREFIX}_KEYSTORE_PASSWORD_FILE`
+//! - `{PREFIX}_KEYPAIR_PATH`: a Solana CLI JSON keypair file (64 numbers)
+//! - a hex-encoded 64-byte keypair in the legacy variable (`ENGINE_SIGNING_KEY`, `RELAYER_KEYPAIR_HEX`)
+//!
+//! Every intermediate copy of the secret (hex, JSON, password, derived key) is zeroized
+//! when dropped.
+
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::types::SettlementError;
+
+pub const KEYPAIR_LEN: usize = 64;
+pub const KEYSTORE_VERSION: u8 = 1;
+const CIPHER: &str = "aes-256-gcm-siv";
+const KDF: &str = "scrypt";
+// Refuse keystores asking for more than 2^20 * r * 128 bytes of scrypt memory (1 GiB at r = 8)
+const MAX_LOG_N: u8 = 20;
+const MAX_R: u32 = 32;
+const MAX_P: u32 = 16;
+
+/// scrypt cost parameters stored alongside each keystore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// 32 MiB and roughly 100ms per unlock.
+    fn default() -> Self {
+        Self { log_n: 15, r: 8, p: 1 }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Keystore {
+    version: u8,
+    /// Public half of the encrypted keypair, also bound to the ciphertext as associated data
+    pubkey: String,
+    kdf: String,
+    kdf_params: ScryptParams,
+    salt: String,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Encrypts a 64-byte keypair under `password`, returning the keystore JSON.
+pub fn encrypt(keypair: &[u8], password: &str, params: ScryptParams) -> Result<String, SettlementError> {
+    let pubkey = keypair_pubkey(keypair)?;
+
+    let mut salt = [0u8; 32];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key(password, &salt, params)?;
+    let pubkey = pubkey.to_string();
+    let ciphertext = cipher(&key)?
+        .encrypt(&Nonce::from(nonce), Payload { msg: keypair, aad: pubkey.as_bytes() })
+        .map_err(|_| SettlementError::Keystore("encryption failed".into()))?;
+
+    let keystore = Keystore {
+        version: KEYSTORE_VERSION,
+        pubkey,
+        kdf: KDF.into(),
+        kdf_params: params,
+        salt: hex::encode(salt),
+        cipher: CIPHER.into(),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    serde_json::to_string_pretty(&keystore).map_err(|e| SettlementError::Keystore(e.to_string()))
+}
+
+/// Decrypts keystore JSON produced by [`encrypt`] back into the 64-byte keypair.
+pub fn decrypt(json: &str, password: &str) -> Result<Zeroizing<Vec<u8>>, SettlementError> {
+    let keystore: Keystore = serde_json::from_str(json).map_err(|e| SettlementError::Keystore(e.to_string()))?;
+    if keystore.version != KEYSTORE_VERSION {
+        return Err(SettlementError::Keystore(format!("unsupported version {}", keystore.version)));
+    }
+    if keystore.kdf != KDF || keystore.cipher != CIPHER {
+        return Err(SettlementError::Keystore(format!("unsupported kdf/cipher {}/{}", keystore.kdf, keystore.cipher)));
+    }
+    let field = |name: &str, value: &str| {
+        hex::decode(value).map_err(|_| SettlementError::Keystore(format!("{} is not valid hex", name)))
+    };
+    let salt = field("salt", &keystore.salt)?;
+    let nonce = field("nonce", &keystore.nonce)?;
+    let ciphertext = field("ciphertext", &keystore.ciphertext)?;
+    let nonce: [u8; 12] = nonce.try_into().map_err(|_| SettlementError::Keystore("nonce must be 12 bytes".into()))?;
+
+    let key = derive_key(password, &salt, keystore.kdf_params)?;
+    let keypair = cipher(&key)?
+        .decrypt(&Nonce::from(nonce), Payload { msg: &ciphertext, aad: keystore.pubkey.as_bytes() })
+        .map(Zeroizing::new)
+        .map_err(|_| SettlementError::Keystore("wrong password or corrupted keystore".into()))?;
+
+    let expected = Pubkey::from_str(&keystore.pubkey).map_err(|_| SettlementError::Keystore("invalid pubkey".into()))?;
+    if keypair_pubkey(&keypair)? != expected {
+        return Err(SettlementError::Keystore("decrypted key does not match the keystore pubkey".into()));
+    }
+    Ok(keypair)
+}
+
+/// Reads a Solana CLI keypair file: a JSON array of the 64 keypair bytes.
+pub fn read_keypair_file(path: &Path) -> Result<Zeroizing<Vec<u8>>, SettlementError> {
+    let json = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| key_file_error(path, e))?);
+    let bytes: Zeroizing<Vec<u8>> =
+        Zeroizing::new(serde_json::from_str(&json).map_err(|e| key_file_error(path, e))?);
+    keypair_pubkey(&bytes)?;
+    Ok(bytes)
+}
+
+/// Loads the 64-byte keypair configured under `prefix` (`ENGINE`, `RELAYER`),
+/// falling back to the hex-encoded `hex_var`.
+pub fn keypair_bytes_from_env(prefix: &str, hex_var: &str) -> Result<Zeroizing<Vec<u8>>, SettlementError> {
+    if let Ok(path) = std::env::var(format!("{}_KEYSTORE_PATH", prefix)) {
+        let json = Zeroizing::new(std::fs::read_to_string(&path).map_err(|e| key_file_error(Path::new(&path), e))?);
+        let password = password_from_env(prefix)?;
+        return decrypt(&json, &password);
+    }
+    if let Ok(path) = std::env::var(format!("{}_KEYPAIR_PATH", prefix)) {
+        return read_keypair_file(Path::new(&path));
+    }
+    let hex_key = Zeroizing::new(std::env::var(hex_var).map_err(|_| SettlementError::MissingKeypair(prefix.into()))?);
+    let bytes = Zeroizing::new(hex::decode(hex_key.trim()).map_err(|_| SettlementError::InvalidKeypair)?);
+    keypair_pubkey(&bytes)?;
+    Ok(bytes)
+}
+
+/// The relayer's fee payer, configured under the `RELAYER` prefix.
+pub fn relayer_keypair_from_env() -> Result<Keypair, SettlementError> {
+    let bytes = keypair_bytes_from_env("RELAYER", "RELAYER_KEYPAIR_HEX")?;
+    Keypair::from_bytes(&bytes).map_err(|_| SettlementError::InvalidKeypair)
+}
+
+fn password_from_env(prefix: &str) -> Result<Zeroizing<String>, SettlementError> {
+    if let Ok(password) = std::env::var(format!("{}_KEYSTORE_PASSWORD", prefix)) {
+        return Ok(Zeroizing::new(password));
+    }
+    let var = format!("{}_KEYSTORE_PASSWORD_FILE", prefix);
+    let path = std::env::var(&var).map_err(|_| SettlementError::Keystore(format!("{} keystore needs {}_KEYSTORE_PASSWORD or {}", prefix, prefix, var)))?;
+    let contents = Zeroizing::new(std::fs::read_to_string(&path).map_err(|e| key_file_error(Path::new(&path), e))?);
+    Ok(Zeroizing::new(contents.lines().next().unwrap_or_default().to_string()))
+}
+
+fn derive_key(password: &str, salt: &[u8], params: ScryptParams) -> Result<Zeroizing<[u8; 32]>, SettlementError> {
+    if params.log_n > MAX_LOG_N || params.r > MAX_R || params.p > MAX_P {
+        return Err(SettlementError::Keystore(format!("scrypt params {:?} exceed the allowed cost", params)));
+    }
+    let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, 32)
+        .map_err(|e| SettlementError::Keystore(format!("invalid scrypt params: {}", e)))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(password.as_bytes(), salt, &scrypt_params, key.as_mut_slice())
+        .map_err(|e| SettlementError::Keystore(e.to_string()))?;
+    Ok(key)
+}
+
+fn cipher(key: &[u8; 32]) -> Result<Aes256GcmSiv, SettlementError> {
+    Aes256GcmSiv::new_from_slice(key).map_err(|_| SettlementError::Keystore("invalid key length".into()))
+}
+
+/// Validates a 64-byte secret+public keypair and returns its public key.
+fn keypair_pubkey(bytes: &[u8]) -> Result<Pubkey, SettlementError> {
+    if bytes.len() != KEYPAIR_LEN {
+        return Err(SettlementError::InvalidKeypair);
+    }
+    // Keypair::from_bytes checks the public half matches the secret; the copy is zeroized on drop
+    Keypair::from_bytes(bytes).map(|k| k.pubkey()).map_err(|_| SettlementError::InvalidKeypair)
+}
+
+fn key_file_error(path: &Path, e: impl std::fmt::Display) -> SettlementError {
+    SettlementError::KeyFile(format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the test doesn't spend a second in debug-mode scrypt
+    const TEST_PARAMS: ScryptParams = ScryptParams { log_n: 4, r: 8, p: 1 };
+
+    #[test]
+    fn keystore_round_trips_and_rejects_wrong_password_or_tampering() {
+        let keypair = Keypair::new();
+        let json = encrypt(&keypair.to_bytes(), "hunter2", TEST_PARAMS).unwrap();
+        assert!(!json.contains(&hex::encode(keypair.secret().as_bytes())));
+
+        let decrypted = decrypt(&json, "hunter2").unwrap();
+        assert_eq!(decrypted.as_slice(), keypair.to_bytes().as_slice());
+
+        assert!(matches!(decrypt(&json, "hunter3"), Err(SettlementError::Keystore(_))));
+
+        // The pubkey is authenticated, so a keystore can't be relabelled as another key
+        let mut relabelled: serde_json::Value = serde_json::from_str(&json).unwrap();
+        relabelled["pubkey"] = Pubkey::new_unique().to_string().into();
+        assert!(decrypt(&relabelled.to_string(), "hunter2").is_err());
+
+        let mut expensive: serde_json::Value = serde_json::from_str(&json).unwrap();
+        expensive["kdf_params"]["log_n"] = 40.into();
+        assert!(decrypt(&expensive.to_string(), "hunter2").is_err());
+    }
+}
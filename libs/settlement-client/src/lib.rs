@@ -1,8 +1,11 @@
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcSendTransactionConfig, RpcSimulateTransactionConfig};
 use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
     instruction::{AccountMeta, Instruction},
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
-    signature::Keypair,
+    signature::{Keypair, Signature},
     signer::Signer,
     sysvar,
     transaction::Transaction,
@@ -11,16 +14,71 @@ use anchor_lang::InstructionData;
 use common_utils::MatchResult;
 use anyhow::Result;
 use rust_decimal::prelude::ToPrimitive;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use hybrid_perp_dex::instructions::BatchTrade;
+use settlement_message::{market_name, TradeSettlementMessage};
 
+pub mod failure;
+pub mod fees;
+pub mod keystore;
+pub mod nonces;
+pub mod remote_signer;
+pub mod rotation;
+pub mod schedule;
 pub mod signer;
+pub mod submission;
 pub mod types;
 
+use crate::fees::PriorityFeeConfig;
+use crate::nonces::NonceTracker;
 use crate::signer::EngineSigner;
-use crate::types::*;
+use crate::submission::SubmissionLog;
+use crate::types::SettlementError;
+
+/// Rough compute cost of one trade in `settle_trades_batch`: the fill math plus
+/// (de)serializing both margin accounts. Used to keep batches under the limit.
+pub const COMPUTE_UNITS_PER_TRADE: u32 = 40_000;
+/// Fixed cost of a settlement transaction regardless of batch size.
+pub const BASE_COMPUTE_UNITS: u32 = 20_000;
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Fresh blockhashes tried per settlement before giving up.
+pub const MAX_SIGNING_ROUNDS: u32 = 3;
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+// Ed25519 instruction layout: 2-byte header, then 14 bytes of offsets per signature
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_SIZE: usize = 14;
 
 pub struct SettlementClient {
     pub rpc: RpcClient,
     pub relayer_fee_payer: Keypair,
+    pub fees: PriorityFeeConfig,
+    pub nonces: NonceTracker,
+    /// Records every transaction before it's broadcast. Without one, a trade whose
+    /// confirmation timed out can be settled twice when it's retried.
+    pub submissions: Option<Arc<dyn SubmissionLog>>,
+    /// Markets trades are settled for (MARKETS). A trade for any other market is
+    /// refused before it's signed.
+    pub markets: Vec<String>,
+}
+
+/// Outcome of one transaction sent by `settle_trades_batch`, or of trades it
+/// couldn't prepare.
+pub struct BatchSettlement {
+    pub trade_ids: Vec<u64>,
+    pub result: Result<String>,
+}
+
+/// A trade with its engine signature, ready to go into a settlement transaction.
+struct PreparedTrade {
+    msg: Vec<u8>,
+    signature: Vec<u8>,
+    buyer_margin: Pubkey,
+    seller_margin: Pubkey,
+    trade: BatchTrade,
 }
 
 impl SettlementClient {
@@ -30,80 +88,446 @@ impl SettlementClient {
         engine_signer: &EngineSigner,
         program_id: &Pubkey,
     ) -> Result<String> {
-        // 1. Derive necessary PDAs
+        let (buyer_margin, seller_margin) = Self::margin_accounts(match_res, program_id)?;
+        let _guard = self.nonces.lock([buyer_margin, seller_margin]).await;
+        if let Some(signature) = self.previously_settled(&[match_res.trade_id]).await?.remove(&match_res.trade_id) {
+            // Cached nonces predate that transaction
+            self.nonces.invalidate([buyer_margin, seller_margin]);
+            return Ok(signature.to_string());
+        }
+        let mut nonces = self.nonces.expected(&self.rpc, &[buyer_margin, seller_margin]).await?;
+
+        let prepared = Self::prepare_trade(match_res, &self.markets, engine_signer, program_id, &mut nonces)?;
         let (config_pda, _) = Pubkey::find_program_address(&[b"engine_config"], program_id);
-        
+
+        // Build Instructions
+        let verify_ix = Self::build_verify_ix(&engine_signer.solana_pubkey(), &[(&prepared.signature, &prepared.msg)]);
+
+        let settle_ix = Instruction {
+            program_id: *program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(config_pda, false),
+                AccountMeta::new(prepared.buyer_margin, false),
+                AccountMeta::new(prepared.seller_margin, false),
+                AccountMeta::new_readonly(sysvar::instructions::ID, false),
+            ],
+            data: hybrid_perp_dex::instruction::SettleTrade {
+                trade_id: prepared.trade.trade_id,
+                price: prepared.trade.price,
+                quantity: prepared.trade.quantity,
+                buyer_nonce: prepared.trade.buyer_nonce,
+                seller_nonce: prepared.trade.seller_nonce,
+            }.data(),
+        };
+
+        let result = self.send(&[prepared.trade.trade_id], &[verify_ix, settle_ix]).await;
+        self.record_nonces(&result, &nonces);
+        result
+    }
+
+    /// Settles `matches` with as few transactions as possible. Trades are packed in
+    /// order into `settle_trades_batch` transactions that fit both the packet size and
+    /// the compute limit; each transaction succeeds or fails as a whole. Sending stops
+    /// at the first failed transaction: trades after it have no outcome and were not
+    /// attempted.
+    pub async fn settle_trades_batch(
+        &self,
+        matches: &[MatchResult],
+        engine_signer: &EngineSigner,
+        program_id: &Pubkey,
+    ) -> Result<Vec<BatchSettlement>> {
+        let accounts = matches
+            .iter()
+            .map(|m| Self::margin_accounts(m, program_id))
+            .collect::<Result<Vec<_>>>()?;
+        let _guard = self.nonces.lock(accounts.iter().flat_map(|(b, s)| [*b, *s])).await;
+
+        // Trades an earlier transaction already settled are reported, not re-signed
+        let trade_ids: Vec<u64> = matches.iter().map(|m| m.trade_id).collect();
+        let settled = self.previously_settled(&trade_ids).await?;
+        let mut outcomes = Vec::new();
+        let mut by_signature: HashMap<Signature, Vec<u64>> = HashMap::new();
+        for (id, signature) in &settled {
+            by_signature.entry(*signature).or_default().push(*id);
+        }
+        for (signature, trade_ids) in by_signature {
+            outcomes.push(BatchSettlement { trade_ids, result: Ok(signature.to_string()) });
+        }
+        let (matches, accounts): (Vec<&MatchResult>, Vec<(Pubkey, Pubkey)>) = matches
+            .iter()
+            .zip(accounts)
+            .filter(|(m, (buyer, seller))| {
+                let done = settled.contains_key(&m.trade_id);
+                if done {
+                    // Cached nonces predate that transaction
+                    self.nonces.invalidate([*buyer, *seller]);
+                }
+                !done
+            })
+            .unzip();
+
+        let engine_pubkey = engine_signer.solana_pubkey();
+        let mut start = 0;
+        while start < matches.len() {
+            // Nonces are re-read for every transaction, so a failed batch only costs
+            // a chain lookup for its accounts instead of poisoning later batches
+            let remaining: Vec<Pubkey> = accounts[start..].iter().flat_map(|(b, s)| [*b, *s]).collect();
+            let first = match self.nonces.expected(&self.rpc, &remaining).await {
+                Ok(mut nonces) => Self::prepare_trade(matches[start], &self.markets, engine_signer, program_id, &mut nonces)
+                    .map(|prepared| (prepared, nonces)),
+                Err(e) => Err(e),
+            };
+            let (first, mut nonces) = match first {
+                Ok(first) => first,
+                Err(e) => {
+                    outcomes.push(BatchSettlement {
+                        trade_ids: matches[start..].iter().map(|m| m.trade_id).collect(),
+                        result: Err(e),
+                    });
+                    break;
+                }
+            };
+
+            let mut batch = vec![first];
+            while start + batch.len() < matches.len() {
+                let mut trial = nonces.clone();
+                let Ok(next) = Self::prepare_trade(matches[start + batch.len()], &self.markets, engine_signer, program_id, &mut trial) else {
+                    break;
+                };
+                batch.push(next);
+                if !self.fits(&engine_pubkey, program_id, &batch) {
+                    batch.pop();
+                    break;
+                }
+                nonces = trial;
+            }
+
+            let ixs = Self::build_batch_ixs(&engine_pubkey, program_id, &batch);
+            let trade_ids: Vec<u64> = batch.iter().map(|t| t.trade.trade_id).collect();
+            let result = self.send(&trade_ids, &ixs).await;
+            let failed = result.is_err();
+            if failed {
+                self.nonces.invalidate(batch.iter().flat_map(|t| [t.buyer_margin, t.seller_margin]));
+            } else {
+                self.nonces.advance(&nonces);
+            }
+            outcomes.push(BatchSettlement { trade_ids, result });
+            if failed {
+                // Later trades may share accounts with these; sending them now would
+                // settle them ahead of the failed ones
+                break;
+            }
+            start += batch.len();
+        }
+        Ok(outcomes)
+    }
+
+    /// Buyer and seller margin account PDAs for a match.
+    pub fn margin_accounts(match_res: &MatchResult, program_id: &Pubkey) -> Result<(Pubkey, Pubkey)> {
         let b_pubkey = Pubkey::try_from(match_res.buyer_id.as_str())?;
         let s_pubkey = Pubkey::try_from(match_res.seller_id.as_str())?;
 
         let (b_margin_pda, _) = Pubkey::find_program_address(&[b"margin_account", b_pubkey.as_ref()], program_id);
         let (s_margin_pda, _) = Pubkey::find_program_address(&[b"margin_account", s_pubkey.as_ref()], program_id);
+        Ok((b_margin_pda, s_margin_pda))
+    }
+
+    fn record_nonces<T>(&self, result: &Result<T>, nonces: &HashMap<Pubkey, u64>) {
+        match result {
+            Ok(_) => self.nonces.advance(nonces),
+            Err(_) => self.nonces.invalidate(nonces.keys().copied()),
+        }
+    }
+
+    /// Signs a trade at the accounts' current nonces and advances them in `nonces`,
+    /// so the next trade for the same account in a batch gets the following nonce.
+    fn prepare_trade(
+        match_res: &MatchResult,
+        markets: &[String],
+        engine_signer: &EngineSigner,
+        program_id: &Pubkey,
+        nonces: &mut HashMap<Pubkey, u64>,
+    ) -> Result<PreparedTrade> {
+        // 1. The program keeps a position per market name, so only sign for known ones
+        if !markets.contains(&match_res.market) {
+            anyhow::bail!("Refusing to sign trade {}: unknown market '{}'", match_res.trade_id, match_res.market);
+        }
+        let market = market_name(&match_res.market)
+            .ok_or_else(|| anyhow::anyhow!("Market '{}' doesn't fit in a settlement message", match_res.market))?;
+
+        // 2. Derive necessary PDAs and the nonces they expect
+        let (b_margin_pda, s_margin_pda) = Self::margin_accounts(match_res, program_id)?;
+        let buyer_nonce = *nonces.get(&b_margin_pda).ok_or_else(|| anyhow::anyhow!("No nonce known for {}", b_margin_pda))?;
+        let seller_nonce = *nonces.get(&s_margin_pda).ok_or_else(|| anyhow::anyhow!("No nonce known for {}", s_margin_pda))?;
+
+        // 3. Prepare Message for Signing
+        let message = TradeSettlementMessage {
+            program_id: program_id.to_bytes(),
+            trade_id: match_res.trade_id,
+            buyer: Pubkey::try_from(match_res.buyer_id.as_str())?.to_bytes(),
+            seller: Pubkey::try_from(match_res.seller_id.as_str())?.to_bytes(),
+            market,
+            price: (match_res.price * rust_decimal::Decimal::from(1_000_000)).to_u64().unwrap(),
+            quantity: (match_res.quantity * rust_decimal::Decimal::from(1_000_000)).to_u64().unwrap(),
+            timestamp: match_res.timestamp_ns as i64,
+            buyer_nonce,
+            seller_nonce,
+        };
 
-        // 2. Prepare Message for Signing
-        let mut msg = Vec::new();
-        msg.extend_from_slice(&match_res.trade_id.to_le_bytes());
-        msg.extend_from_slice(b_pubkey.as_ref());
-        msg.extend_from_slice(s_pubkey.as_ref());
-        msg.extend_from_slice(&[0u8; 16]); // Market placeholder
-        
-        let p_u64 = (match_res.price * rust_decimal::Decimal::from(1_000_000)).to_u64().unwrap();
-        let q_u64 = (match_res.quantity * rust_decimal::Decimal::from(1_000_000)).to_u64().unwrap();
-        
-        msg.extend_from_slice(&p_u64.to_le_bytes());
-        msg.extend_from_slice(&q_u64.to_le_bytes());
-        msg.extend_from_slice(&chrono::Utc::now().timestamp().to_le_bytes());
-
-        // 3. Generate Signatures
-        let signed_trade = engine_signer.sign_trade_raw(&msg, 0, 0)
+        // 4. Generate Signatures
+        let signed_trade = engine_signer.sign_trade(&message)
             .map_err(|e| anyhow::anyhow!("Signing failed: {:?}", e))?;
+        *nonces.get_mut(&b_margin_pda).unwrap() += 1;
+        *nonces.get_mut(&s_margin_pda).unwrap() += 1;
 
-        // 4. Build Instructions
-        let verify_ix = self.build_verify_ix(&engine_signer.solana_pubkey(), &signed_trade.signature, &msg);
+        Ok(PreparedTrade {
+            msg: message.encode().to_vec(),
+            signature: signed_trade.signature,
+            buyer_margin: b_margin_pda,
+            seller_margin: s_margin_pda,
+            trade: BatchTrade {
+                trade_id: match_res.trade_id,
+                price: message.price,
+                quantity: message.quantity,
+                buyer_nonce: signed_trade.buyer_nonce,
+                seller_nonce: signed_trade.seller_nonce,
+            },
+        })
+    }
+
+    /// Whether `batch` fits in one transaction by wire size and estimated compute.
+    fn fits(&self, engine_pubkey: &Pubkey, program_id: &Pubkey, batch: &[PreparedTrade]) -> bool {
+        let compute = BASE_COMPUTE_UNITS as u64 + COMPUTE_UNITS_PER_TRADE as u64 * batch.len() as u64;
+        if compute > MAX_COMPUTE_UNITS as u64 {
+            return false;
+        }
+        // Budget values don't change the encoded size, so placeholders are enough here
+        let ixs = Self::with_compute_budget(&Self::build_batch_ixs(engine_pubkey, program_id, batch), MAX_COMPUTE_UNITS, 0);
+        let tx = Transaction::new_with_payer(&ixs, Some(&self.relayer_fee_payer.pubkey()));
+        // Wire format: compact-u16 signature count, the signatures, then the message
+        let size = 1 + 64 * tx.message.header.num_required_signatures as usize + tx.message.serialize().len();
+        size <= PACKET_DATA_SIZE
+    }
+
+    fn build_batch_ixs(engine_pubkey: &Pubkey, program_id: &Pubkey, batch: &[PreparedTrade]) -> [Instruction; 2] {
+        let (config_pda, _) = Pubkey::find_program_address(&[b"engine_config"], program_id);
+
+        let signed: Vec<(&[u8], &[u8])> = batch.iter().map(|t| (t.signature.as_slice(), t.msg.as_slice())).collect();
+        let verify_ix = Self::build_verify_ix(engine_pubkey, &signed);
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(config_pda, false),
+            AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        ];
+        for t in batch {
+            accounts.push(AccountMeta::new(t.buyer_margin, false));
+            accounts.push(AccountMeta::new(t.seller_margin, false));
+        }
 
         let settle_ix = Instruction {
             program_id: *program_id,
-            accounts: vec![
-                AccountMeta::new_readonly(config_pda, false),
-                AccountMeta::new(b_margin_pda, false),
-                AccountMeta::new(s_margin_pda, false),
-                AccountMeta::new_readonly(sysvar::instructions::ID, false),
-            ],
-            data: hybrid_perp_dex::instruction::SettleTrade {
-                trade_id: match_res.trade_id,
-                price: p_u64,
-                quantity: q_u64,
-                buyer_nonce: 0,
-                seller_nonce: 0,
+            accounts,
+            data: hybrid_perp_dex::instruction::SettleTradesBatch {
+                trades: batch.iter().map(|t| t.trade.clone()).collect(),
             }.data(),
         };
+        [verify_ix, settle_ix]
+    }
+
+    /// Signs and broadcasts `ixs`, rebroadcasting the same transaction until it's
+    /// confirmed or its blockhash expires. Only once the old transaction can no longer
+    /// land is it re-signed with a fresh blockhash.
+    async fn send(&self, trade_ids: &[u64], ixs: &[Instruction]) -> Result<String> {
+        for _ in 0..MAX_SIGNING_ROUNDS {
+            let (blockhash, last_valid_block_height) =
+                self.rpc.get_latest_blockhash_with_commitment(self.rpc.commitment()).await?;
+            let payer = self.relayer_fee_payer.pubkey();
+
+            // Simulate at the maximum limit to learn what the transaction actually consumes
+            let probe = Transaction::new_signed_with_payer(
+                &Self::with_compute_budget(ixs, MAX_COMPUTE_UNITS, 0),
+                Some(&payer),
+                &[&self.relayer_fee_payer],
+                blockhash,
+            );
+            let sim = self
+                .rpc
+                .simulate_transaction_with_config(
+                    &probe,
+                    RpcSimulateTransactionConfig { sig_verify: false, replace_recent_blockhash: true, ..Default::default() },
+                )
+                .await?
+                .value;
+            if let Some(err) = sim.err {
+                return Err(SettlementError::SimulationFailed { err, logs: sim.logs.unwrap_or_default() }.into());
+            }
+            let unit_limit = self.fees.unit_limit(sim.units_consumed.unwrap_or(MAX_COMPUTE_UNITS as u64));
+            let unit_price = self.unit_price(ixs).await;
+
+            let tx = Transaction::new_signed_with_payer(
+                &Self::with_compute_budget(ixs, unit_limit, unit_price),
+                Some(&payer),
+                &[&self.relayer_fee_payer],
+                blockhash,
+            );
+            let signature = tx.signatures[0];
+
+            // Durable before the first broadcast, so a restart finds it and waits it out
+            if let Some(log) = &self.submissions {
+                log.submitted(trade_ids, &signature, last_valid_block_height).await?;
+            }
+
+            match self.broadcast_until_final(&tx, last_valid_block_height).await? {
+                Some(slot) => {
+                    if let Some(log) = &self.submissions {
+                        // The settlement stands even if recording the confirmation fails
+                        if let Err(e) = log.confirmed(trade_ids, &signature, slot).await {
+                            eprintln!("⚠️ Failed to record confirmation of {}: {}", signature, e);
+                        }
+                    }
+                    return Ok(signature.to_string());
+                }
+                None => eprintln!("⌛ Blockhash expired before {} landed, re-signing", signature),
+            }
+        }
+        Err(SettlementError::BlockhashExpired(MAX_SIGNING_ROUNDS).into())
+    }
+
+    /// Rebroadcasts `tx` until it reaches the client's commitment (`Some(slot)`) or
+    /// can provably never land (`None`): its blockhash has expired and no node
+    /// reports it. A transaction the cluster has seen is followed until it settles
+    /// one way or the other, even past expiry.
+    async fn broadcast_until_final(&self, tx: &Transaction, last_valid_block_height: u64) -> Result<Option<u64>> {
+        let signature = tx.signatures[0];
+        // Preflight already ran as the simulation, and rebroadcasting is done here
+        let config = RpcSendTransactionConfig { skip_preflight: true, max_retries: Some(0), ..Default::default() };
+        loop {
+            if let Err(e) = self.rpc.send_transaction_with_config(tx, config).await {
+                eprintln!("⚠️ Broadcast of {} failed: {}", signature, e);
+            }
+            tokio::time::sleep(REBROADCAST_INTERVAL).await;
+
+            let status = self.rpc.get_signature_statuses(&[signature]).await?.value.remove(0);
+            match status {
+                Some(status) if status.err.is_some() => {
+                    let err = status.err.unwrap();
+                    return Err(SettlementError::TransactionFailed { signature, err }.into());
+                }
+                Some(status) if status.satisfies_commitment(self.rpc.commitment()) => return Ok(Some(status.slot)),
+                Some(_) => {}
+                None if self.rpc.get_block_height().await? > last_valid_block_height => return Ok(None),
+                None => {}
+            }
+        }
+    }
+
+    /// Resolves earlier submissions of `trade_ids` before anything is re-signed, waiting
+    /// on any that may still land. Returns the trades that already settled, with the
+    /// signature that settled them.
+    async fn previously_settled(&self, trade_ids: &[u64]) -> Result<HashMap<u64, Signature>> {
+        let Some(log) = &self.submissions else { return Ok(HashMap::new()) };
+
+        let mut by_signature: HashMap<Signature, (u64, Vec<u64>)> = HashMap::new();
+        for sub in log.submissions(trade_ids).await? {
+            by_signature
+                .entry(sub.signature)
+                .or_insert_with(|| (sub.last_valid_block_height, Vec::new()))
+                .1
+                .push(sub.trade_id);
+        }
 
-        // 5. Broadcast
-        let blockhash = self.rpc.get_latest_blockhash().await?;
-        let tx = Transaction::new_signed_with_payer(
-            &[verify_ix, settle_ix],
-            Some(&self.relayer_fee_payer.pubkey()),
-            &[&self.relayer_fee_payer],
-            blockhash,
-        );
-
-        let sig = self.rpc.send_and_confirm_transaction(&tx).await?;
-        Ok(sig.to_string())
-    }
-
-    fn build_verify_ix(&self, pubkey: &Pubkey, sig: &[u8], msg: &[u8]) -> Instruction {
-        let mut instruction_data = Vec::with_capacity(176 + msg.len());
-        instruction_data.extend_from_slice(&[1, 0]); 
-        instruction_data.extend_from_slice(&[112u8, 0]); 
-        instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
-        instruction_data.extend_from_slice(&[48u8, 0]);  
-        instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
-        instruction_data.extend_from_slice(&[176u8, 0]); 
-        instruction_data.extend_from_slice(&(msg.len() as u16).to_le_bytes());
-        instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
-        
-        instruction_data.extend_from_slice(&pubkey.to_bytes()); 
-        instruction_data.extend_from_slice(sig);    
-        instruction_data.extend_from_slice(msg);          
+        let mut settled = HashMap::new();
+        for (signature, (last_valid_block_height, ids)) in by_signature {
+            if let Some(slot) = self.await_submission(&signature, last_valid_block_height).await? {
+                println!("♻️ Trades {:?} already settled by {}", ids, signature);
+                log.confirmed(&ids, &signature, slot).await?;
+                settled.extend(ids.into_iter().map(|id| (id, signature)));
+            }
+        }
+        Ok(settled)
+    }
+
+    /// Like `broadcast_until_final` without the transaction: waits for an earlier
+    /// submission to settle or become impossible. Failed transactions count as not landed.
+    async fn await_submission(&self, signature: &Signature, last_valid_block_height: u64) -> Result<Option<u64>> {
+        loop {
+            let status = self.rpc.get_signature_statuses_with_history(&[*signature]).await?.value.remove(0);
+            match status {
+                Some(status) if status.err.is_some() => return Ok(None),
+                Some(status) if status.satisfies_commitment(self.rpc.commitment()) => return Ok(Some(status.slot)),
+                Some(_) => {}
+                None if self.rpc.get_block_height().await? > last_valid_block_height => return Ok(None),
+                None => {}
+            }
+            tokio::time::sleep(REBROADCAST_INTERVAL).await;
+        }
+    }
+
+    /// Priority fee for a transaction writing the accounts in `ixs`, based on what
+    /// recent transactions touching the same accounts paid.
+    async fn unit_price(&self, ixs: &[Instruction]) -> u64 {
+        let mut writable: Vec<Pubkey> = ixs
+            .iter()
+            .flat_map(|ix| ix.accounts.iter())
+            .filter(|meta| meta.is_writable)
+            .map(|meta| meta.pubkey)
+            .collect();
+        writable.sort();
+        writable.dedup();
+
+        match self.rpc.get_recent_prioritization_fees(&writable).await {
+            Ok(recent) => {
+                let fees: Vec<u64> = recent.iter().map(|f| f.prioritization_fee).collect();
+                self.fees.unit_price(&fees)
+            }
+            Err(e) => {
+                eprintln!("⚠️ Could not fetch recent prioritization fees, using minimum: {}", e);
+                self.fees.unit_price(&[])
+            }
+        }
+    }
+
+    /// Prepends the compute budget instructions. They go first so the Ed25519
+    /// instruction stays directly in front of the settle instruction.
+    fn with_compute_budget(ixs: &[Instruction], unit_limit: u32, unit_price: u64) -> Vec<Instruction> {
+        let mut all = Vec::with_capacity(ixs.len() + 2);
+        all.push(ComputeBudgetInstruction::set_compute_unit_limit(unit_limit));
+        all.push(ComputeBudgetInstruction::set_compute_unit_price(unit_price));
+        all.extend_from_slice(ixs);
+        all
+    }
+
+    /// Builds one Ed25519 verify instruction covering every (signature, message) pair.
+    /// All signatures are by `pubkey`, which is stored once and shared by every entry.
+    fn build_verify_ix(pubkey: &Pubkey, signed: &[(&[u8], &[u8])]) -> Instruction {
+        let pubkey_offset = ED25519_OFFSETS_START + ED25519_OFFSETS_SIZE * signed.len();
+        let mut payload_offset = pubkey_offset + 32;
+
+        let mut instruction_data = Vec::with_capacity(payload_offset + signed.iter().map(|(s, m)| s.len() + m.len()).sum::<usize>());
+        instruction_data.extend_from_slice(&[signed.len() as u8, 0]);
+
+        for (sig, msg) in signed {
+            let signature_offset = payload_offset;
+            let message_offset = signature_offset + sig.len();
+            for field in [
+                signature_offset as u16,
+                u16::MAX, // signature lives in this instruction
+                pubkey_offset as u16,
+                u16::MAX,
+                message_offset as u16,
+                msg.len() as u16,
+                u16::MAX,
+            ] {
+                instruction_data.extend_from_slice(&field.to_le_bytes());
+            }
+            payload_offset = message_offset + msg.len();
+        }
+
+        instruction_data.extend_from_slice(&pubkey.to_bytes());
+        for (sig, msg) in signed {
+            instruction_data.extend_from_slice(sig);
+            instruction_data.extend_from_slice(msg);
+        }
 
         Instruction {
             program_id: solana_sdk::ed25519_program::ID,
@@ -111,4 +535,135 @@ impl SettlementClient {
             data: instruction_data,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::ed25519_instruction;
+    use solana_sdk::feature_set::FeatureSet;
+
+    fn engine_signer() -> EngineSigner {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7u8; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        EngineSigner::from_bytes(&ed25519_dalek::Keypair { secret, public }.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn batch_verify_ix_is_accepted_by_precompile_and_program() {
+        let signer = engine_signer();
+        let messages: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 40 + i as usize]).collect();
+        let signatures: Vec<Vec<u8>> = messages
+            .iter()
+            .map(|m| signer.sign_trade_raw(m, 0, 0).unwrap().signature)
+            .collect();
+        let signed: Vec<(&[u8], &[u8])> = signatures.iter().zip(&messages).map(|(s, m)| (s.as_slice(), m.as_slice())).collect();
+
+        let ix = SettlementClient::build_verify_ix(&signer.solana_pubkey(), &signed);
+        ed25519_instruction::verify(&ix.data, &[&ix.data], &FeatureSet::all_enabled())
+            .expect("native Ed25519 program rejected the instruction");
+
+        let verified = hybrid_perp_dex::ed25519::verified_messages(&ix.data).unwrap();
+        assert_eq!(verified.len(), messages.len());
+        for (v, m) in verified.iter().zip(&messages) {
+            assert_eq!(v.pubkey, signer.solana_pubkey());
+            assert_eq!(v.message, m.as_slice());
+        }
+    }
+
+    #[test]
+    fn tampered_message_fails_verification() {
+        let signer = engine_signer();
+        let msg = vec![1u8; 112];
+        let sig = signer.sign_trade_raw(&msg, 0, 0).unwrap().signature;
+        let mut ix = SettlementClient::build_verify_ix(&signer.solana_pubkey(), &[(&sig, &msg)]);
+        *ix.data.last_mut().unwrap() ^= 1;
+        assert!(ed25519_instruction::verify(&ix.data, &[&ix.data], &FeatureSet::all_enabled()).is_err());
+    }
+
+    fn markets() -> Vec<String> {
+        vec!["SOL_USDC".into()]
+    }
+
+    #[test]
+    fn consecutive_trades_for_an_account_sign_consecutive_nonces() {
+        let signer = engine_signer();
+        let program_id = hybrid_perp_dex::ID;
+        let (alice, bob, carol) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let trade = |trade_id, buyer: Pubkey, seller: Pubkey| MatchResult {
+            trade_id,
+            market: "SOL_USDC".into(),
+            price: rust_decimal::Decimal::from(100),
+            quantity: rust_decimal::Decimal::from(1),
+            buyer_id: buyer.to_string(),
+            seller_id: seller.to_string(),
+            sequence: 0,
+            timestamp_ns: 0,
+        };
+        let first = trade(1, alice, bob);
+        let (alice_margin, bob_margin) = SettlementClient::margin_accounts(&first, &program_id).unwrap();
+        let (_, carol_margin) = SettlementClient::margin_accounts(&trade(2, alice, carol), &program_id).unwrap();
+
+        let mut nonces = HashMap::from([(alice_margin, 5), (bob_margin, 0), (carol_margin, 9)]);
+        let a = SettlementClient::prepare_trade(&first, &markets(), &signer, &program_id, &mut nonces).unwrap();
+        let b = SettlementClient::prepare_trade(&trade(2, carol, alice), &markets(), &signer, &program_id, &mut nonces).unwrap();
+
+        assert_eq!((a.trade.buyer_nonce, a.trade.seller_nonce), (5, 0));
+        assert_eq!((b.trade.buyer_nonce, b.trade.seller_nonce), (9, 6));
+        assert_eq!(nonces, HashMap::from([(alice_margin, 7), (bob_margin, 1), (carol_margin, 10)]));
+        // The nonces are covered by the engine's signature
+        let signed = TradeSettlementMessage::decode(&b.msg).unwrap();
+        assert_eq!((signed.buyer_nonce, signed.seller_nonce), (9, 6));
+    }
+
+    #[test]
+    fn program_accepts_client_signed_message_only_for_the_same_trade() {
+        let signer = engine_signer();
+        let program_id = hybrid_perp_dex::ID;
+        let (buyer, seller) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let m = MatchResult {
+            trade_id: 7,
+            market: "SOL_USDC".into(),
+            price: rust_decimal::Decimal::new(15025, 2),
+            quantity: rust_decimal::Decimal::new(5, 1),
+            buyer_id: buyer.to_string(),
+            seller_id: seller.to_string(),
+            sequence: 0,
+            timestamp_ns: 0,
+        };
+        let (buyer_margin, seller_margin) = SettlementClient::margin_accounts(&m, &program_id).unwrap();
+        let mut nonces = HashMap::from([(buyer_margin, 3), (seller_margin, 0)]);
+        let prepared = SettlementClient::prepare_trade(&m, &markets(), &signer, &program_id, &mut nonces).unwrap();
+
+        let ix = SettlementClient::build_verify_ix(&signer.solana_pubkey(), &[(&prepared.signature, &prepared.msg)]);
+        let verified = hybrid_perp_dex::ed25519::verified_messages(&ix.data).unwrap();
+        let check = |trade: &BatchTrade, buyer: &Pubkey| {
+            hybrid_perp_dex::instructions::check_trade_message(verified[0].message, trade, buyer, &seller)
+        };
+
+        let signed = check(&prepared.trade, &buyer).unwrap();
+        assert_eq!((signed.price, signed.quantity), (150_250_000, 500_000));
+        assert_eq!(&signed.market, b"SOL_USDC\0\0\0\0\0\0\0\0");
+
+        let mismatch: anchor_lang::error::Error = hybrid_perp_dex::error::PerpError::TradeMessageMismatch.into();
+        let replayed = BatchTrade { buyer_nonce: 4, ..prepared.trade.clone() };
+        assert_eq!(check(&replayed, &buyer).unwrap_err(), mismatch);
+        let inflated = BatchTrade { quantity: 1_000_000, ..prepared.trade.clone() };
+        assert_eq!(check(&inflated, &buyer).unwrap_err(), mismatch);
+        assert_eq!(check(&prepared.trade, &Pubkey::new_unique()).unwrap_err(), mismatch);
+
+        // A message signed for another deployment of the program is rejected too
+        let other_program = Pubkey::new_unique();
+        let (other_buyer, other_seller) = SettlementClient::margin_accounts(&m, &other_program).unwrap();
+        let mut nonces = HashMap::from([(other_buyer, 3), (other_seller, 0)]);
+        let foreign = SettlementClient::prepare_trade(&m, &markets(), &signer, &other_program, &mut nonces).unwrap();
+        let foreign_check = hybrid_perp_dex::instructions::check_trade_message(&foreign.msg, &prepared.trade, &buyer, &seller);
+        assert_eq!(foreign_check.unwrap_err(), mismatch);
+
+        // Nothing is signed for a market the engines don't trade
+        let unknown = MatchResult { market: "BTC_USDC".into(), ..m };
+        let mut nonces = HashMap::from([(buyer_margin, 3), (seller_margin, 0)]);
+        assert!(SettlementClient::prepare_trade(&unknown, &markets(), &signer, &program_id, &mut nonces).is_err());
+        assert_eq!(nonces, HashMap::from([(buyer_margin, 3), (seller_margin, 0)]));
+    }
+}
@@ -0,0 +1,108 @@
+use anchor_lang::{AccountDeserialize, Discriminator};
+use hybrid_perp_dex::state::MarginAccount;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+/// Next nonce each margin account expects, as far as this relayer knows.
+///
+/// Nonces are read from chain (in bulk on startup, or lazily for accounts seen
+/// later) and advanced locally once a settlement confirms. Settlements touching
+/// an account hold its lock from signing until confirmation, so two in-flight
+/// transactions never sign the same nonce.
+#[derive(Default)]
+pub struct NonceTracker {
+    expected: Mutex<HashMap<Pubkey, u64>>,
+    locks: Mutex<HashMap<Pubkey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Held while settling; releases the accounts when dropped.
+pub struct AccountGuard {
+    _guards: Vec<OwnedMutexGuard<()>>,
+}
+
+impl NonceTracker {
+    /// Seeds the tracker with every margin account owned by `program_id`.
+    pub async fn load(rpc: &RpcClient, program_id: &Pubkey) -> anyhow::Result<Self> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                0,
+                MarginAccount::DISCRIMINATOR.to_vec(),
+            ))]),
+            account_config: RpcAccountInfoConfig { encoding: Some(UiAccountEncoding::Base64), ..Default::default() },
+            ..Default::default()
+        };
+        let accounts = rpc.get_program_accounts_with_config(program_id, config).await?;
+
+        let tracker = Self::default();
+        {
+            let mut expected = tracker.expected.lock().unwrap();
+            for (pubkey, account) in accounts {
+                match MarginAccount::try_deserialize(&mut account.data.as_slice()) {
+                    Ok(margin) => {
+                        expected.insert(pubkey, margin.nonce);
+                    }
+                    Err(e) => eprintln!("⚠️ Skipping undecodable margin account {}: {}", pubkey, e),
+                }
+            }
+        }
+        Ok(tracker)
+    }
+
+    /// Locks `accounts` for the duration of a settlement. Locks are taken in key
+    /// order so overlapping settlements can't deadlock.
+    pub async fn lock(&self, accounts: impl IntoIterator<Item = Pubkey>) -> AccountGuard {
+        let mut keys: Vec<Pubkey> = accounts.into_iter().collect();
+        keys.sort();
+        keys.dedup();
+
+        let mutexes: Vec<_> = {
+            let mut locks = self.locks.lock().unwrap();
+            keys.iter().map(|k| locks.entry(*k).or_default().clone()).collect()
+        };
+        let mut guards = Vec::with_capacity(mutexes.len());
+        for mutex in mutexes {
+            guards.push(mutex.lock_owned().await);
+        }
+        AccountGuard { _guards: guards }
+    }
+
+    /// Expected nonces for `accounts`, fetching any that aren't cached from chain.
+    /// Callers should hold the accounts' lock.
+    pub async fn expected(&self, rpc: &RpcClient, accounts: &[Pubkey]) -> anyhow::Result<HashMap<Pubkey, u64>> {
+        let mut known = HashMap::with_capacity(accounts.len());
+        for account in accounts {
+            let cached = self.expected.lock().unwrap().get(account).copied();
+            let nonce = match cached {
+                Some(nonce) => nonce,
+                None => {
+                    let data = rpc.get_account_data(account).await?;
+                    let nonce = MarginAccount::try_deserialize(&mut data.as_slice())?.nonce;
+                    self.expected.lock().unwrap().insert(*account, nonce);
+                    nonce
+                }
+            };
+            known.insert(*account, nonce);
+        }
+        Ok(known)
+    }
+
+    /// Records the nonces a confirmed settlement left the accounts at.
+    pub fn advance(&self, settled: &HashMap<Pubkey, u64>) {
+        self.expected.lock().unwrap().extend(settled);
+    }
+
+    /// Forgets cached nonces after a failed send. The transaction may still have
+    /// landed, so the next settlement re-reads them from chain.
+    pub fn invalidate(&self, accounts: impl IntoIterator<Item = Pubkey>) {
+        let mut expected = self.expected.lock().unwrap();
+        for account in accounts {
+            expected.remove(&account);
+        }
+    }
+}
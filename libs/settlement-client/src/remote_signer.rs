@@ -0,0 +1,210 @@
+//! Engine signing over a local Unix socket, so the key can live in a separate
+//! process (or a different user / container) from the relayer.
+//!
+//! Requests and responses share one frame: a tag byte, a little-endian `u32`
+//! length and the payload.
+//! - request tag 0: public key, empty payload; 1: sign, payload is an encoded `TradeSettlementMessage`
+//! - response tag 0: ok, payload is the 32-byte pubkey or 64-byte signature; 1: error, payload is the reason
+//!
+//! The server only signs payloads that decode as a settlement message (which
+//! checks the domain separator) for the program it was started for. A
+//! compromised relayer can still get any trade signed for that program; this
+//! only keeps it from signing other data, or settlements meant for another
+//! deployment, with the engine key.
+
+use solana_sdk::pubkey::Pubkey;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::signer::SigningBackend;
+use crate::types::{SettlementError, TradeSettlementMessage};
+
+const OP_PUBKEY: u8 = 0;
+const OP_SIGN: u8 = 1;
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+// Largest frame either side accepts; settlement messages are 177 bytes
+const MAX_FRAME: u32 = 4096;
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client for a signer process serving [`serve`] on a Unix socket.
+/// Reconnects once per request if the connection has dropped.
+pub struct RemoteSigner {
+    path: PathBuf,
+    pubkey: Pubkey,
+    stream: Mutex<Option<UnixStream>>,
+}
+
+impl RemoteSigner {
+    pub fn connect(path: impl Into<PathBuf>) -> Result<Self, SettlementError> {
+        let path = path.into();
+        let mut stream = open(&path)?;
+        let pubkey = request(&mut stream, OP_PUBKEY, &[])?;
+        let pubkey = Pubkey::try_from(pubkey.as_slice())
+            .map_err(|_| SettlementError::RemoteSigner("signer returned a malformed pubkey".into()))?;
+        Ok(Self { path, pubkey, stream: Mutex::new(Some(stream)) })
+    }
+
+    fn call(&self, op: u8, payload: &[u8]) -> Result<Vec<u8>, SettlementError> {
+        let mut guard = self.stream.lock().unwrap();
+        for attempt in 0..2 {
+            let stream = match guard.as_mut() {
+                Some(stream) => stream,
+                None => guard.insert(open(&self.path)?),
+            };
+            match request(stream, op, payload) {
+                Err(SettlementError::RemoteSigner(reason)) if attempt == 0 && reason.starts_with("io:") => {
+                    *guard = None;
+                }
+                result => return result,
+            }
+        }
+        unreachable!("second attempt always returns")
+    }
+}
+
+impl SigningBackend for RemoteSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<[u8; 64], SettlementError> {
+        let signature = self.call(OP_SIGN, message)?;
+        signature
+            .try_into()
+            .map_err(|_| SettlementError::RemoteSigner("signer returned a malformed signature".into()))
+    }
+}
+
+/// Serves `backend` on `listener` for settlements of `program_id`, one thread per
+/// connection. Runs until the listener fails.
+pub fn serve(listener: UnixListener, backend: Arc<dyn SigningBackend>, program_id: Pubkey) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept()?;
+        let backend = backend.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, backend.as_ref(), &program_id) {
+                eprintln!("⚠️ Signer connection closed: {}", e);
+            }
+        });
+    }
+}
+
+fn handle_connection(mut stream: UnixStream, backend: &dyn SigningBackend, program_id: &Pubkey) -> std::io::Result<()> {
+    loop {
+        let (op, payload) = match read_frame(&mut stream) {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let response = match op {
+            OP_PUBKEY => Ok(backend.pubkey().to_bytes().to_vec()),
+            OP_SIGN => match TradeSettlementMessage::decode(&payload) {
+                Ok(message) if message.program_id != program_id.to_bytes() => {
+                    Err(format!("refusing to sign: message is for program {}", Pubkey::new_from_array(message.program_id)))
+                }
+                Ok(message) => {
+                    println!("✍️ Signing trade {} (nonces {}/{})", message.trade_id, message.buyer_nonce, message.seller_nonce);
+                    backend.sign(&payload).map(|s| s.to_vec()).map_err(|e| e.to_string())
+                }
+                Err(e) => Err(format!("refusing to sign: {:?}", e)),
+            },
+            other => Err(format!("unknown op {}", other)),
+        };
+        match response {
+            Ok(body) => write_frame(&mut stream, STATUS_OK, &body)?,
+            Err(reason) => write_frame(&mut stream, STATUS_ERR, reason.as_bytes())?,
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<UnixStream, SettlementError> {
+    let stream = UnixStream::connect(path).map_err(io_error)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(io_error)?;
+    stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(io_error)?;
+    Ok(stream)
+}
+
+fn request(stream: &mut UnixStream, op: u8, payload: &[u8]) -> Result<Vec<u8>, SettlementError> {
+    write_frame(stream, op, payload).map_err(io_error)?;
+    match read_frame(stream).map_err(io_error)? {
+        (STATUS_OK, body) => Ok(body),
+        (_, reason) => Err(SettlementError::RemoteSigner(String::from_utf8_lossy(&reason).into_owned())),
+    }
+}
+
+fn write_frame(stream: &mut UnixStream, tag: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(tag);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+fn read_frame(stream: &mut UnixStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_le_bytes(header[1..].try_into().unwrap());
+    if len > MAX_FRAME {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("frame of {} bytes", len)));
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+// Prefixed so `call` can tell a dropped connection from a refusal by the signer
+fn io_error(e: std::io::Error) -> SettlementError {
+    SettlementError::RemoteSigner(format!("io: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{EngineSigner, LocalSigner};
+    use settlement_message::MARKET_LEN;
+    use solana_sdk::signature::{Keypair, Signature};
+    use solana_sdk::signer::Signer;
+
+    #[test]
+    fn remote_signer_signs_settlement_messages_only() {
+        let keypair = Keypair::new();
+        let path = std::env::temp_dir().join(format!("engine-signer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let backend: Arc<dyn SigningBackend> = Arc::new(LocalSigner::from_bytes(&keypair.to_bytes()).unwrap());
+        let program_id = Pubkey::new_from_array([7; 32]);
+        std::thread::spawn(move || serve(listener, backend, program_id));
+
+        let signer = EngineSigner::new(RemoteSigner::connect(&path).unwrap());
+        assert_eq!(signer.solana_pubkey(), keypair.pubkey());
+
+        let message = TradeSettlementMessage {
+            program_id: [7; 32],
+            trade_id: 42,
+            buyer: [1; 32],
+            seller: [2; 32],
+            market: [0; MARKET_LEN],
+            price: 100_000_000,
+            quantity: 1_000_000,
+            timestamp: 1_700_000_000,
+            buyer_nonce: 3,
+            seller_nonce: 9,
+        };
+        let signed = signer.sign_trade(&message).unwrap();
+        let signature = Signature::try_from(signed.signature.as_slice()).unwrap();
+        assert!(signature.verify(keypair.pubkey().as_ref(), &message.encode()));
+
+        let refused = signer.sign_trade_raw(b"transfer all funds", 0, 0);
+        assert!(matches!(refused, Err(SettlementError::RemoteSigner(reason)) if reason.starts_with("refusing")));
+
+        let other_program = TradeSettlementMessage { program_id: [8; 32], ..message };
+        let refused = signer.sign_trade(&other_program);
+        assert!(matches!(refused, Err(SettlementError::RemoteSigner(reason)) if reason.contains("for program")));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
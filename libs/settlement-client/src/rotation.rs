@@ -0,0 +1,119 @@
+use anchor_lang::AccountDeserialize;
+use hybrid_perp_dex::state::EngineConfig;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::{Arc, Mutex};
+
+use crate::signer::EngineSigner;
+use crate::types::SettlementError;
+
+/// The engine keys this relayer holds and which one it currently signs with.
+///
+/// During a rotation the program accepts both the current and the pending key
+/// until the activation slot. The relayer switches to the pending key as soon as
+/// it sees it staged on chain, so in-flight settlements signed with the old key
+/// still land and nothing signed afterwards is rejected at activation.
+pub struct SignerRotation {
+    signers: Vec<Arc<EngineSigner>>,
+    active: Mutex<Arc<EngineSigner>>,
+}
+
+impl SignerRotation {
+    pub fn new(signers: Vec<EngineSigner>) -> Self {
+        assert!(!signers.is_empty(), "at least one engine signer is required");
+        let signers: Vec<Arc<EngineSigner>> = signers.into_iter().map(Arc::new).collect();
+        let active = Mutex::new(signers[0].clone());
+        Self { signers, active }
+    }
+
+    /// The current key (`ENGINE_*`) plus, if configured, the key being rotated to (`ENGINE_NEXT_*`).
+    pub fn from_env() -> Result<Self, SettlementError> {
+        let mut signers = vec![EngineSigner::from_env()?];
+        match EngineSigner::from_env_prefixed("ENGINE_NEXT", "ENGINE_NEXT_SIGNING_KEY") {
+            Ok(next) => signers.push(next),
+            Err(SettlementError::MissingKeypair(_)) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self::new(signers))
+    }
+
+    /// Signer to use for the next settlement. Callers keep the returned handle for
+    /// the whole transaction so all of its messages are signed by the same key.
+    pub fn active(&self) -> Arc<EngineSigner> {
+        self.active.lock().unwrap().clone()
+    }
+
+    /// Picks the held key `config` accepts at `slot`, preferring the staged one.
+    pub fn select(&self, config: &EngineConfig, slot: u64) -> Result<Arc<EngineSigner>, SettlementError> {
+        let preferred = [config.pending_engine_signer, config.engine_signer];
+        let chosen = preferred
+            .iter()
+            .filter(|key| config.accepts_engine_signer(key, slot))
+            .find_map(|key| self.signers.iter().find(|s| s.solana_pubkey() == *key))
+            .cloned()
+            .ok_or_else(|| {
+                let held: Vec<String> = self.signers.iter().map(|s| s.solana_pubkey().to_string()).collect();
+                SettlementError::NoAcceptedSigner(held.join(", "))
+            })?;
+
+        let mut active = self.active.lock().unwrap();
+        if active.solana_pubkey() != chosen.solana_pubkey() {
+            println!("🔑 Engine signer switched {} -> {}", active.solana_pubkey(), chosen.solana_pubkey());
+            *active = chosen.clone();
+        }
+        Ok(chosen)
+    }
+
+    /// Re-reads the engine config from chain and switches keys if needed.
+    pub async fn refresh(&self, rpc: &RpcClient, program_id: &Pubkey) -> anyhow::Result<Pubkey> {
+        let (config_pda, _) = Pubkey::find_program_address(&[b"engine_config"], program_id);
+        let data = rpc.get_account_data(&config_pda).await?;
+        let config = EngineConfig::try_deserialize(&mut data.as_slice())?;
+        let slot = rpc.get_slot().await?;
+        Ok(self.select(&config, slot)?.solana_pubkey())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::signer::Signer;
+
+    fn config(current: Pubkey, pending: Pubkey, activation: u64) -> EngineConfig {
+        EngineConfig {
+            authority: Pubkey::new_unique(),
+            engine_signer: current,
+            usdc_mint: Pubkey::new_unique(),
+            maintenance_margin_bps: 500,
+            bump: 255,
+            pending_engine_signer: pending,
+            pending_activation_slot: activation,
+        }
+    }
+
+    #[test]
+    fn switches_to_the_pending_key_as_soon_as_it_is_staged() {
+        let (old, new) = (Keypair::new(), Keypair::new());
+        let rotation = SignerRotation::new(vec![
+            EngineSigner::from_bytes(&old.to_bytes()).unwrap(),
+            EngineSigner::from_bytes(&new.to_bytes()).unwrap(),
+        ]);
+
+        let before = config(old.pubkey(), Pubkey::default(), 0);
+        assert_eq!(rotation.select(&before, 50).unwrap().solana_pubkey(), old.pubkey());
+
+        // Staged for slot 100: both keys are accepted in the window, the new one is used
+        let staged = config(old.pubkey(), new.pubkey(), 100);
+        assert!(staged.accepts_engine_signer(&old.pubkey(), 99));
+        assert!(staged.accepts_engine_signer(&new.pubkey(), 99));
+        assert!(!staged.accepts_engine_signer(&old.pubkey(), 100));
+        assert_eq!(rotation.select(&staged, 60).unwrap().solana_pubkey(), new.pubkey());
+        assert_eq!(rotation.active().solana_pubkey(), new.pubkey());
+
+        // A relayer that only holds the old key is cut off at activation
+        let old_only = SignerRotation::new(vec![EngineSigner::from_bytes(&old.to_bytes()).unwrap()]);
+        assert_eq!(old_only.select(&staged, 99).unwrap().solana_pubkey(), old.pubkey());
+        assert!(matches!(old_only.select(&staged, 100), Err(SettlementError::NoAcceptedSigner(_))));
+    }
+}
@@ -0,0 +1,61 @@
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+
+/// Splits `items` into groups that share no account. Groups can be settled
+/// concurrently; within a group items keep their queue order, so every account
+/// still sees its trades (and nonces) in sequence.
+pub fn group_by_accounts<T>(items: Vec<T>, accounts: impl Fn(&T) -> Vec<Pubkey>) -> Vec<Vec<T>> {
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    let mut owner: HashMap<Pubkey, usize> = HashMap::new();
+
+    for item in items {
+        let keys = accounts(&item);
+        let mut touched: Vec<usize> = keys.iter().filter_map(|k| owner.get(k).copied()).collect();
+        touched.sort_unstable();
+        touched.dedup();
+
+        let target = match touched.first() {
+            Some(&first) => first,
+            None => {
+                groups.push(Vec::new());
+                groups.len() - 1
+            }
+        };
+        // The item links several groups: fold them together. Their items are all
+        // older than this one and share no accounts, so appending keeps each
+        // account's order intact.
+        for &other in touched.iter().skip(1) {
+            let moved = std::mem::take(&mut groups[other]);
+            groups[target].extend(moved);
+            for g in owner.values_mut() {
+                if *g == other {
+                    *g = target;
+                }
+            }
+        }
+        for key in keys {
+            owner.insert(key, target);
+        }
+        groups[target].push(item);
+    }
+
+    groups.retain(|g| !g.is_empty());
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_trades_share_a_group_in_order() {
+        let [a, b, c, d, e, f] = [(); 6].map(|_| Pubkey::new_unique());
+        let trades = vec![(1, a, b), (2, c, d), (3, e, f), (4, b, c), (5, a, b), (6, f, e)];
+
+        let groups = group_by_accounts(trades, |(_, buyer, seller)| vec![*buyer, *seller]);
+        let ids: Vec<Vec<u32>> = groups.iter().map(|g| g.iter().map(|t| t.0).collect()).collect();
+
+        // Trade 4 links {a, b} with {c, d}; {e, f} stays independent
+        assert_eq!(ids, vec![vec![1, 2, 4, 5], vec![3, 6]]);
+    }
+}
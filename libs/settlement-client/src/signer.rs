@@ -1,23 +1,81 @@
 use ed25519_dalek::{Keypair, Signer};
+use solana_sdk::pubkey::Pubkey;
+use crate::keystore;
+use crate::remote_signer::RemoteSigner;
 use crate::types::{SettlementError, SignedTradeSettlement, TradeSettlementMessage};
 
-pub struct EngineSigner {
+/// Something that holds the engine key and signs settlement messages with it:
+/// the key itself ([`LocalSigner`]) or a separate signer process ([`RemoteSigner`]).
+pub trait SigningBackend: Send + Sync {
+    fn pubkey(&self) -> Pubkey;
+    fn sign(&self, message: &[u8]) -> Result<[u8; 64], SettlementError>;
+}
+
+/// Signs in-process with a key held in memory. The secret is zeroized on drop.
+pub struct LocalSigner {
     keypair: Keypair,
 }
 
-impl EngineSigner {
+impl LocalSigner {
+    /// Loads the engine key from `ENGINE_KEYSTORE_PATH`, `ENGINE_KEYPAIR_PATH` or hex `ENGINE_SIGNING_KEY`.
     pub fn from_env() -> Result<Self, SettlementError> {
-        let hex_key = std::env::var("ENGINE_SIGNING_KEY")
-            .map_err(|_| SettlementError::MissingKeypair)?;
-        let bytes = hex::decode(&hex_key)
-            .map_err(|_| SettlementError::InvalidKeypair)?;
-        let keypair = Keypair::from_bytes(&bytes)
+        Self::from_bytes(&keystore::keypair_bytes_from_env("ENGINE", "ENGINE_SIGNING_KEY")?)
+    }
+
+    /// Builds a signer from a 64-byte secret+public keypair.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SettlementError> {
+        let keypair = Keypair::from_bytes(bytes)
             .map_err(|_| SettlementError::InvalidKeypair)?;
         Ok(Self { keypair })
     }
+}
 
-    pub fn solana_pubkey(&self) -> solana_sdk::pubkey::Pubkey {
-        solana_sdk::pubkey::Pubkey::new_from_array(self.keypair.public.to_bytes())
+impl SigningBackend for LocalSigner {
+    fn pubkey(&self) -> Pubkey {
+        Pubkey::new_from_array(self.keypair.public.to_bytes())
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<[u8; 64], SettlementError> {
+        Ok(self.keypair.sign(message).to_bytes())
+    }
+}
+
+pub struct EngineSigner {
+    backend: Box<dyn SigningBackend>,
+}
+
+impl EngineSigner {
+    pub fn new(backend: impl SigningBackend + 'static) -> Self {
+        Self { backend: Box::new(backend) }
+    }
+
+    /// Uses the signer process listening on `ENGINE_SIGNER_SOCKET` if set,
+    /// otherwise loads the key locally (see [`LocalSigner::from_env`]).
+    pub fn from_env() -> Result<Self, SettlementError> {
+        Self::from_env_prefixed("ENGINE", "ENGINE_SIGNING_KEY")
+    }
+
+    /// Like [`EngineSigner::from_env`] for the key configured under `prefix`
+    /// (`{prefix}_SIGNER_SOCKET`, `{prefix}_KEYSTORE_PATH`, ...).
+    pub fn from_env_prefixed(prefix: &str, hex_var: &str) -> Result<Self, SettlementError> {
+        match std::env::var(format!("{}_SIGNER_SOCKET", prefix)) {
+            Ok(path) => Ok(Self::new(RemoteSigner::connect(path)?)),
+            Err(_) => Self::from_bytes(&keystore::keypair_bytes_from_env(prefix, hex_var)?),
+        }
+    }
+
+    /// Builds a local signer from a 64-byte secret+public keypair.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SettlementError> {
+        Ok(Self::new(LocalSigner::from_bytes(bytes)?))
+    }
+
+    pub fn solana_pubkey(&self) -> Pubkey {
+        self.backend.pubkey()
+    }
+
+    /// Signs the canonical encoding of `message`.
+    pub fn sign_trade(&self, message: &TradeSettlementMessage) -> Result<SignedTradeSettlement, SettlementError> {
+        self.sign_trade_raw(&message.encode(), message.buyer_nonce, message.seller_nonce)
     }
 
     pub fn sign_trade_raw(
@@ -26,11 +84,11 @@ impl EngineSigner {
         buyer_nonce: u64,
         seller_nonce: u64,
     ) -> Result<SignedTradeSettlement, SettlementError> {
-        let signature = self.keypair.sign(msg);
+        let signature = self.backend.sign(msg)?;
         Ok(SignedTradeSettlement {
-            signature: signature.to_bytes().to_vec(),
+            signature: signature.to_vec(),
             buyer_nonce,
             seller_nonce,
         })
     }
-}
\ No newline at end of file
+}
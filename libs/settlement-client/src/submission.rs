@@ -0,0 +1,24 @@
+use anyhow::Result;
+use solana_sdk::signature::Signature;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type LogFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// The last transaction submitted for a trade.
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub trade_id: u64,
+    pub signature: Signature,
+    /// Past this block height the transaction can no longer land.
+    pub last_valid_block_height: u64,
+}
+
+/// Durable record of settlement transactions. `submitted` is awaited before a
+/// transaction is first broadcast, so after a crash the client can find anything
+/// that might still land and wait it out instead of settling the trade twice.
+pub trait SubmissionLog: Send + Sync {
+    fn submitted<'a>(&'a self, trade_ids: &'a [u64], signature: &'a Signature, last_valid_block_height: u64) -> LogFuture<'a, ()>;
+    fn confirmed<'a>(&'a self, trade_ids: &'a [u64], signature: &'a Signature, slot: u64) -> LogFuture<'a, ()>;
+    fn submissions<'a>(&'a self, trade_ids: &'a [u64]) -> LogFuture<'a, Vec<Submission>>;
+}
@@ -1,26 +1,33 @@
 use serde::{Deserialize, Serialize};
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum SettlementError {
-    #[error("ENGINE_SIGNING_KEY not found in environment")]
-    MissingKeypair,
+    #[error("No {0} key configured: set {0}_KEYSTORE_PATH, {0}_KEYPAIR_PATH or the hex key variable")]
+    MissingKeypair(String),
     #[error("Invalid keypair format")]
     InvalidKeypair,
+    #[error("Failed to read key file {0}")]
+    KeyFile(String),
+    #[error("Keystore error: {0}")]
+    Keystore(String),
+    #[error("Remote signer error: {0}")]
+    RemoteSigner(String),
+    #[error("None of the configured engine keys ({0}) is accepted by the program")]
+    NoAcceptedSigner(String),
     #[error("Failed to sign trade: {0}")]
     SigningError(String),
+    #[error("Simulation failed: {err}")]
+    SimulationFailed { err: TransactionError, logs: Vec<String> },
+    #[error("Transaction {signature} failed: {err}")]
+    TransactionFailed { signature: Signature, err: TransactionError },
+    #[error("Blockhash expired {0} times without the transaction landing")]
+    BlockhashExpired(u32),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TradeSettlementMessage {
-    pub trade_id: u64,
-    pub buyer: [u8; 32],
-    pub seller: [u8; 32],
-    pub market: [u8; 16],
-    pub price: u64,
-    pub quantity: u64,
-    pub timestamp: i64,
-}
+pub use settlement_message::TradeSettlementMessage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedTradeSettlement {
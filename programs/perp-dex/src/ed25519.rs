@@ -0,0 +1,52 @@
+use anchor_lang::prelude::*;
+use crate::error::PerpError;
+
+// Layout of the native Ed25519 program's instruction data:
+// [num_signatures: u8, padding: u8, offsets: [SignatureOffsets; num_signatures], ...payload]
+const OFFSETS_START: usize = 2;
+const OFFSETS_SIZE: usize = 14;
+
+/// One (pubkey, message) pair verified by an Ed25519 instruction.
+pub struct VerifiedMessage<'a> {
+    pub pubkey: Pubkey,
+    pub message: &'a [u8],
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    let bytes = data.get(at..at + 2).ok_or(PerpError::MalformedSignatureInstruction)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn slice(data: &[u8], offset: u16, len: usize) -> Result<&[u8]> {
+    let start = offset as usize;
+    data.get(start..start + len).ok_or(PerpError::MalformedSignatureInstruction.into())
+}
+
+/// Extracts every pubkey/message the Ed25519 instruction verified. Only self-contained
+/// instructions are accepted: all offsets must point into the instruction's own data.
+pub fn verified_messages(data: &[u8]) -> Result<Vec<VerifiedMessage<'_>>> {
+    let count = *data.first().ok_or(PerpError::MalformedSignatureInstruction)? as usize;
+    let mut out = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let base = OFFSETS_START + i * OFFSETS_SIZE;
+        let signature_ix = read_u16(data, base + 2)?;
+        let pubkey_offset = read_u16(data, base + 4)?;
+        let pubkey_ix = read_u16(data, base + 6)?;
+        let message_offset = read_u16(data, base + 8)?;
+        let message_size = read_u16(data, base + 10)?;
+        let message_ix = read_u16(data, base + 12)?;
+
+        require!(
+            signature_ix == u16::MAX && pubkey_ix == u16::MAX && message_ix == u16::MAX,
+            PerpError::MalformedSignatureInstruction
+        );
+
+        let pubkey_bytes: [u8; 32] = slice(data, pubkey_offset, 32)?.try_into().unwrap();
+        out.push(VerifiedMessage {
+            pubkey: Pubkey::new_from_array(pubkey_bytes),
+            message: slice(data, message_offset, message_size as usize)?,
+        });
+    }
+    Ok(out)
+}
@@ -20,4 +20,46 @@ pub enum PerpError {
     MaxPositionsReached,
     #[msg("Trade message mismatch")]
     TradeMessageMismatch,
-}
\ No newline at end of file
+    #[msg("Malformed Ed25519 instruction data")]
+    MalformedSignatureInstruction,
+    #[msg("Trade was not signed by the engine signer")]
+    InvalidEngineSigner,
+    #[msg("Batch must contain at least one trade")]
+    EmptyBatch,
+    #[msg("Remaining accounts do not match the batch")]
+    BatchAccountsMismatch,
+    #[msg("Buyer and seller must be different accounts")]
+    SelfTrade,
+    #[msg("Signed trade message is malformed or has an unknown version")]
+    MalformedTradeMessage,
+    #[msg("New engine signer must differ from the current one")]
+    InvalidSignerRotation,
+    #[msg("Signer rotation must activate at least MIN_ROTATION_DELAY_SLOTS from now")]
+    RotationActivatesTooSoon,
+}
+impl PerpError {
+    /// Every variant in declaration order, so off-chain code can map a custom error code back.
+    pub const ALL: [PerpError; 17] = [
+        PerpError::MissingSignature,
+        PerpError::InvalidSignatureProgram,
+        PerpError::StaleNonce,
+        PerpError::PriceOutOfBounds,
+        PerpError::ZeroPositionSize,
+        PerpError::InsufficientCollateral,
+        PerpError::MathOverflow,
+        PerpError::MaxPositionsReached,
+        PerpError::TradeMessageMismatch,
+        PerpError::MalformedSignatureInstruction,
+        PerpError::InvalidEngineSigner,
+        PerpError::EmptyBatch,
+        PerpError::BatchAccountsMismatch,
+        PerpError::SelfTrade,
+        PerpError::MalformedTradeMessage,
+        PerpError::InvalidSignerRotation,
+        PerpError::RotationActivatesTooSoon,
+    ];
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| u32::from(*e) == code)
+    }
+}
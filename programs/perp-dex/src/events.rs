@@ -0,0 +1,36 @@
+use anchor_lang::prelude::*;
+use crate::state::MARKET_NAME_LEN;
+
+// Anchor events, logged as `Program data: <base64>` and decoded off-chain by the indexer.
+// Add fields at the end only: the indexer decodes older transactions with the same layout.
+
+/// A trade applied to both margin accounts by `settle_trade` or `settle_trades_batch`.
+#[event]
+pub struct TradeSettled {
+    pub trade_id: u64,
+    pub market: [u8; MARKET_NAME_LEN],
+    /// Owners of the buyer's and seller's margin accounts
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+    pub quantity: u64,
+    /// Nonces the trade consumed
+    pub buyer_nonce: u64,
+    pub seller_nonce: u64,
+}
+
+#[event]
+pub struct CollateralDeposited {
+    pub owner: Pubkey,
+    pub amount: u64,
+    /// Account collateral after the deposit
+    pub collateral: u64,
+}
+
+/// A rotation staged, or cancelled when `pending_signer` is the default pubkey.
+#[event]
+pub struct EngineSignerRotated {
+    pub current_signer: Pubkey,
+    pub pending_signer: Pubkey,
+    pub activation_slot: u64,
+}
@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 use crate::state::*;
+use crate::events::CollateralDeposited;
 
 #[derive(Accounts)]
 pub struct Deposit<'info> {
@@ -10,6 +11,8 @@ pub struct Deposit<'info> {
 }
 
 pub fn deposit_handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-    ctx.accounts.margin_account.collateral += amount;
+    let account = &mut ctx.accounts.margin_account;
+    account.collateral += amount;
+    emit!(CollateralDeposited { owner: account.owner, amount, collateral: account.collateral });
     Ok(())
 }
\ No newline at end of file
@@ -3,7 +3,7 @@ use crate::state::*;
 
 #[derive(Accounts)]
 pub struct Initialize<'info> {
-    #[account(init, payer = authority, space = 8 + 128, seeds = [b"engine_config"], bump)]
+    #[account(init, payer = authority, space = EngineConfig::SPACE, seeds = [b"engine_config"], bump)]
     pub config: Account<'info, EngineConfig>,
     pub usdc_mint: AccountInfo<'info>, // Simplified for prototype
     #[account(mut)]
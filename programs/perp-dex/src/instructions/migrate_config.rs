@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_lang::Discriminator;
+use crate::state::*;
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    /// CHECK: may still be in the layout from before signer rotation, which
+    /// `Account<EngineConfig>` can't deserialize; checked in the handler
+    #[account(mut, seeds = [b"engine_config"], bump, owner = crate::ID)]
+    pub config: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Grows a config created before signer rotation to the current layout, with the
+/// authority paying the extra rent. The new fields start zeroed, i.e. no rotation
+/// staged. Does nothing on a config that is already current.
+pub fn migrate_config_handler(ctx: Context<MigrateConfig>) -> Result<()> {
+    let config = &ctx.accounts.config;
+    let authority = &ctx.accounts.authority;
+    if config.data_len() >= EngineConfig::SPACE {
+        return Ok(());
+    }
+
+    {
+        let data = config.try_borrow_data()?;
+        require!(
+            data.len() >= EngineConfig::LEGACY_SPACE && data[..8] == EngineConfig::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+        // authority is the first field in both layouts
+        let stored_authority = Pubkey::try_from(&data[8..40]).map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+        require_keys_eq!(stored_authority, authority.key(), ErrorCode::ConstraintHasOne);
+    }
+
+    let shortfall = Rent::get()?.minimum_balance(EngineConfig::SPACE).saturating_sub(config.lamports());
+    if shortfall > 0 {
+        let accounts = Transfer { from: authority.to_account_info(), to: config.to_account_info() };
+        system_program::transfer(CpiContext::new(ctx.accounts.system_program.to_account_info(), accounts), shortfall)?;
+    }
+    config.realloc(EngineConfig::SPACE, true)?;
+    Ok(())
+}
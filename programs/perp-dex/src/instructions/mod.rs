@@ -1,7 +1,13 @@
 pub mod initialize;
 pub mod deposit;
 pub mod settle_trade;
+pub mod settle_trades_batch;
+pub mod rotate_engine_signer;
+pub mod migrate_config;
 
 pub use initialize::*;
 pub use deposit::*;
-pub use settle_trade::*;
\ No newline at end of file
+pub use settle_trade::*;
+pub use settle_trades_batch::*;
+pub use rotate_engine_signer::*;
+pub use migrate_config::*;
\ No newline at end of file
@@ -0,0 +1,50 @@
+use anchor_lang::prelude::*;
+use crate::state::*;
+use crate::error::PerpError;
+use crate::events::EngineSignerRotated;
+
+/// Shortest overlap between staging a rotation and its activation (~1 minute),
+/// so relayers get to see the new key before the old one stops being accepted
+pub const MIN_ROTATION_DELAY_SLOTS: u64 = 150;
+
+#[derive(Accounts)]
+pub struct RotateEngineSigner<'info> {
+    #[account(mut, seeds = [b"engine_config"], bump = config.bump, has_one = authority)]
+    pub config: Account<'info, EngineConfig>,
+    pub authority: Signer<'info>,
+}
+
+/// Stages `new_signer` to take over at `activation_slot`. Until then settlements
+/// signed by either key are accepted, so relayers can switch over at their own
+/// pace; from that slot on only the new key is, so it must be at least
+/// `MIN_ROTATION_DELAY_SLOTS` away. Passing the default pubkey cancels a staged
+/// rotation that hasn't activated yet.
+pub fn rotate_engine_signer_handler(
+    ctx: Context<RotateEngineSigner>,
+    new_signer: Pubkey,
+    activation_slot: u64,
+) -> Result<()> {
+    let config = &mut ctx.accounts.config;
+    let slot = Clock::get()?.slot;
+
+    // A rotation whose activation has passed is already in force; make it the current signer
+    if config.has_pending_signer() && slot >= config.pending_activation_slot {
+        config.engine_signer = config.pending_engine_signer;
+        config.pending_engine_signer = Pubkey::default();
+        config.pending_activation_slot = 0;
+    }
+
+    require_keys_neq!(new_signer, config.engine_signer, PerpError::InvalidSignerRotation);
+    if new_signer != Pubkey::default() {
+        require!(activation_slot >= slot.saturating_add(MIN_ROTATION_DELAY_SLOTS), PerpError::RotationActivatesTooSoon);
+    }
+    config.pending_engine_signer = new_signer;
+    config.pending_activation_slot = if new_signer == Pubkey::default() { 0 } else { activation_slot };
+
+    emit!(EngineSignerRotated {
+        current_signer: config.engine_signer,
+        pending_signer: new_signer,
+        activation_slot: config.pending_activation_slot,
+    });
+    Ok(())
+}
@@ -1,7 +1,12 @@
 use anchor_lang::prelude::*;
+use solana_program::instruction::Instruction;
 use solana_program::sysvar::instructions::{load_instruction_at_checked, ID as IX_SYSVAR_ID};
 use crate::state::*;
 use crate::error::PerpError;
+use crate::ed25519::verified_messages;
+use crate::events::TradeSettled;
+use super::settle_trades_batch::BatchTrade;
+use settlement_message::TradeSettlementMessage;
 
 #[derive(Accounts)]
 #[instruction(trade_id: u64)]
@@ -22,19 +27,25 @@ pub struct SettleTrade<'info> {
 
 pub fn settle_trade_handler(
     ctx: Context<SettleTrade>, 
-    _trade_id: u64, 
+    trade_id: u64, 
     price: u64, 
     qty: u64, 
     b_nonce: u64, 
     s_nonce: u64
 ) -> Result<()> {
     // 1. Signature Verification (Introspection)
-    let ix_sysvar = &ctx.accounts.ix_sysvar;
-    let current_ix = solana_program::sysvar::instructions::load_current_index_checked(ix_sysvar)?;
-    require!(current_ix > 0, PerpError::MissingSignature);
-
-    let signature_ix = load_instruction_at_checked((current_ix - 1) as usize, ix_sysvar)?;
-    require!(signature_ix.program_id == solana_program::ed25519_program::ID, PerpError::InvalidSignatureProgram);
+    let signature_ix = load_signature_ix(&ctx.accounts.ix_sysvar)?;
+    let verified = verified_messages(&signature_ix.data)?;
+    require!(verified.len() == 1, PerpError::MissingSignature);
+    let slot = Clock::get()?.slot;
+    require!(ctx.accounts.config.accepts_engine_signer(&verified[0].pubkey, slot), PerpError::InvalidEngineSigner);
+    let trade = BatchTrade { trade_id, price, quantity: qty, buyer_nonce: b_nonce, seller_nonce: s_nonce };
+    let signed = check_trade_message(
+        verified[0].message,
+        &trade,
+        &ctx.accounts.buyer_margin.owner,
+        &ctx.accounts.seller_margin.owner,
+    )?;
     
     // 2. Replay Protection (Nonces)
     let b_account = &mut ctx.accounts.buyer_margin;
@@ -45,20 +56,62 @@ pub fn settle_trade_handler(
 
     // 3. Execution: Apply fills to both accounts
     // For Perp DEX: Buyer gets +qty (Long), Seller gets -qty (Short)
-    let market = [0u8; 16]; // In production, pass market name as bytes
-    
-    apply_fill_to_account(b_account, market, qty as i64, price)?;
-    apply_fill_to_account(s_account, market, -(qty as i64), price)?;
+    apply_fill_to_account(b_account, signed.market, qty as i64, price)?;
+    apply_fill_to_account(s_account, signed.market, -(qty as i64), price)?;
 
     // 4. Advance Nonces
     b_account.nonce += 1;
     s_account.nonce += 1;
 
-    msg!("Settled Trade {}: Price {} | Qty {}", _trade_id, price, qty);
+    emit!(TradeSettled {
+        trade_id,
+        market: signed.market,
+        buyer: b_account.owner,
+        seller: s_account.owner,
+        price,
+        quantity: qty,
+        buyer_nonce: b_nonce,
+        seller_nonce: s_nonce,
+    });
     Ok(())
 }
 
-fn apply_fill_to_account(
+/// Decodes an engine-signed message and checks it describes exactly the trade being
+/// settled: this program, the same owners, amounts and nonces.
+pub fn check_trade_message(
+    message: &[u8],
+    trade: &BatchTrade,
+    buyer: &Pubkey,
+    seller: &Pubkey,
+) -> Result<TradeSettlementMessage> {
+    let signed = TradeSettlementMessage::decode(message).map_err(|_| error!(PerpError::MalformedTradeMessage))?;
+    require!(
+        signed.program_id == crate::ID.to_bytes()
+            && signed.trade_id == trade.trade_id
+            && signed.buyer == buyer.to_bytes()
+            && signed.seller == seller.to_bytes()
+            && signed.price == trade.price
+            && signed.quantity == trade.quantity
+            && signed.buyer_nonce == trade.buyer_nonce
+            && signed.seller_nonce == trade.seller_nonce,
+        PerpError::TradeMessageMismatch
+    );
+    Ok(signed)
+}
+
+/// Loads the Ed25519 instruction that must immediately precede the current one.
+pub(crate) fn load_signature_ix(ix_sysvar: &AccountInfo) -> Result<Instruction> {
+    let current_ix = solana_program::sysvar::instructions::load_current_index_checked(ix_sysvar)?;
+    require!(current_ix > 0, PerpError::MissingSignature);
+
+    let signature_ix = load_instruction_at_checked((current_ix - 1) as usize, ix_sysvar)?;
+    require!(signature_ix.program_id == solana_program::ed25519_program::ID, PerpError::InvalidSignatureProgram);
+    Ok(signature_ix)
+}
+
+/// Mirrored off-chain by db-processor's position ledger, whose golden test
+/// replays fills through both; change them together.
+pub fn apply_fill_to_account(
     account: &mut MarginAccount,
     market: [u8; 16],
     qty_delta: i64, 
@@ -94,7 +147,7 @@ fn apply_fill_to_account(
             if new_size != 0 && (new_size > 0) == (pos.size > 0) {
                  // Weighted average for increasing position size
                  pos.avg_entry_price = (pos.avg_entry_price * pos.size.unsigned_abs() + price * qty_delta.unsigned_abs()) 
-                                        / new_size.unsigned_abs() as u64;
+                                        / new_size.unsigned_abs();
             } else if new_size != 0 {
                 // If flipping position (Short to Long or vice versa), new entry is the fill price
                 pos.avg_entry_price = price;
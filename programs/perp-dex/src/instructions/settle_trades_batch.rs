@@ -0,0 +1,95 @@
+use anchor_lang::prelude::*;
+use solana_program::sysvar::instructions::ID as IX_SYSVAR_ID;
+use crate::state::*;
+use crate::error::PerpError;
+use crate::ed25519::verified_messages;
+use crate::events::TradeSettled;
+use super::settle_trade::{apply_fill_to_account, check_trade_message, load_signature_ix};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchTrade {
+    pub trade_id: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub buyer_nonce: u64,
+    pub seller_nonce: u64,
+}
+
+// remaining_accounts: [buyer_margin, seller_margin] for each trade, in batch order
+#[derive(Accounts)]
+pub struct SettleTradesBatch<'info> {
+    #[account(seeds = [b"engine_config"], bump = config.bump)]
+    pub config: Account<'info, EngineConfig>,
+
+    /// CHECK: Instructions Sysvar for Ed25519 introspection
+    #[account(address = IX_SYSVAR_ID)]
+    pub ix_sysvar: AccountInfo<'info>,
+}
+
+pub fn settle_trades_batch_handler<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SettleTradesBatch<'info>>,
+    trades: Vec<BatchTrade>,
+) -> Result<()> {
+    require!(!trades.is_empty(), PerpError::EmptyBatch);
+    require!(ctx.remaining_accounts.len() == trades.len() * 2, PerpError::BatchAccountsMismatch);
+
+    // 1. Signature Verification: one engine signature per trade in a single Ed25519 ix,
+    // in batch order; each message is checked against its trade below
+    let signature_ix = load_signature_ix(&ctx.accounts.ix_sysvar)?;
+    let verified = verified_messages(&signature_ix.data)?;
+    require!(verified.len() == trades.len(), PerpError::MissingSignature);
+    let slot = Clock::get()?.slot;
+    for v in &verified {
+        require!(ctx.accounts.config.accepts_engine_signer(&v.pubkey, slot), PerpError::InvalidEngineSigner);
+    }
+
+    for ((trade, accounts), v) in trades.iter().zip(ctx.remaining_accounts.chunks(2)).zip(&verified) {
+        let (buyer_info, seller_info) = (&accounts[0], &accounts[1]);
+        require_keys_neq!(buyer_info.key(), seller_info.key(), PerpError::SelfTrade);
+
+        // Accounts are reloaded per trade so an account appearing in several
+        // trades sees the nonce and position written by the previous one
+        let mut b_account = load_margin_account(buyer_info)?;
+        let mut s_account = load_margin_account(seller_info)?;
+        let signed = check_trade_message(v.message, trade, &b_account.owner, &s_account.owner)?;
+
+        // 2. Replay Protection (Nonces)
+        require!(trade.buyer_nonce == b_account.nonce, PerpError::StaleNonce);
+        require!(trade.seller_nonce == s_account.nonce, PerpError::StaleNonce);
+
+        // 3. Execution
+        apply_fill_to_account(&mut b_account, signed.market, trade.quantity as i64, trade.price)?;
+        apply_fill_to_account(&mut s_account, signed.market, -(trade.quantity as i64), trade.price)?;
+
+        // 4. Advance Nonces and persist before the next trade loads them again
+        b_account.nonce += 1;
+        s_account.nonce += 1;
+        b_account.exit(&crate::ID)?;
+        s_account.exit(&crate::ID)?;
+
+        emit!(TradeSettled {
+            trade_id: trade.trade_id,
+            market: signed.market,
+            buyer: b_account.owner,
+            seller: s_account.owner,
+            price: trade.price,
+            quantity: trade.quantity,
+            buyer_nonce: trade.buyer_nonce,
+            seller_nonce: trade.seller_nonce,
+        });
+    }
+    Ok(())
+}
+
+fn load_margin_account<'info>(info: &'info AccountInfo<'info>) -> Result<Account<'info, MarginAccount>> {
+    require!(info.is_writable, PerpError::BatchAccountsMismatch);
+    // Checks program ownership and the account discriminator
+    let account = Account::<MarginAccount>::try_from(info)?;
+    let expected = Pubkey::create_program_address(
+        &[b"margin_account", account.owner.as_ref(), &[account.bump]],
+        &crate::ID,
+    )
+    .map_err(|_| error!(PerpError::BatchAccountsMismatch))?;
+    require_keys_eq!(expected, info.key(), PerpError::BatchAccountsMismatch);
+    Ok(account)
+}
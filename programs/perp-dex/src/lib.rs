@@ -2,6 +2,8 @@ use anchor_lang::prelude::*;
 pub mod instructions;
 pub mod state;
 pub mod error;
+pub mod ed25519;
+pub mod events;
 
 use instructions::*;
 
@@ -37,4 +39,23 @@ pub mod perp_dex {
     ) -> Result<()> {
         instructions::settle_trade::settle_trade_handler(ctx, trade_id, price, quantity, buyer_nonce, seller_nonce)
     }
-}
\ No newline at end of file
+
+    pub fn settle_trades_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleTradesBatch<'info>>,
+        trades: Vec<BatchTrade>,
+    ) -> Result<()> {
+        instructions::settle_trades_batch::settle_trades_batch_handler(ctx, trades)
+    }
+
+    pub fn rotate_engine_signer(
+        ctx: Context<RotateEngineSigner>,
+        new_signer: Pubkey,
+        activation_slot: u64,
+    ) -> Result<()> {
+        instructions::rotate_engine_signer::rotate_engine_signer_handler(ctx, new_signer, activation_slot)
+    }
+
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        instructions::migrate_config::migrate_config_handler(ctx)
+    }
+}
@@ -10,6 +10,33 @@ pub struct EngineConfig {
     pub usdc_mint: Pubkey,
     pub maintenance_margin_bps: u16,
     pub bump: u8,
+    /// Key taking over from `engine_signer`, or the default pubkey when no rotation is staged
+    pub pending_engine_signer: Pubkey,
+    /// Slot from which only `pending_engine_signer` is accepted
+    pub pending_activation_slot: u64,
+}
+
+impl EngineConfig {
+    pub const SPACE: usize = 8 + 32 * 3 + 2 + 1 + 32 + 8;
+    /// Serialized length of configs created before signer rotation. Their accounts were
+    /// allocated `8 + 128` bytes, zero past this; `migrate_config` grows them to `SPACE`
+    pub const LEGACY_SPACE: usize = 8 + 32 * 3 + 2 + 1;
+
+    pub fn has_pending_signer(&self) -> bool {
+        self.pending_engine_signer != Pubkey::default()
+    }
+
+    /// Whether `key` may sign settlements at `slot`. While a rotation is staged both
+    /// keys are accepted; once the activation slot is reached only the new one is.
+    pub fn accepts_engine_signer(&self, key: &Pubkey, slot: u64) -> bool {
+        if !self.has_pending_signer() {
+            return *key == self.engine_signer;
+        }
+        if slot >= self.pending_activation_slot {
+            return *key == self.pending_engine_signer;
+        }
+        *key == self.engine_signer || *key == self.pending_engine_signer
+    }
 }
 
 #[account]
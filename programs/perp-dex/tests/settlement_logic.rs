@@ -8,6 +8,7 @@ use solana_sdk::{
 };
 use anchor_lang::{InstructionData, AccountDeserialize};
 use hybrid_perp_dex::instruction as perp_ix;
+use settlement_message::TradeSettlementMessage;
 
 #[test]
 fn test_signature_and_margin_health() {
@@ -78,33 +79,38 @@ fn test_signature_and_margin_health() {
     let qty = 1_000_000u64;      
     let timestamp = 1620000000i64;
     
-    // RECONSTRUCT PAYLOAD (Must match program logic exactly)
-    let mut msg = Vec::new();
-    msg.extend_from_slice(&trade_id.to_le_bytes());
-    msg.extend_from_slice(buyer.pubkey().as_ref());
-    msg.extend_from_slice(seller.pubkey().as_ref());
-    msg.extend_from_slice(&[0u8; 16]); // market placeholder
-    msg.extend_from_slice(&price.to_le_bytes());
-    msg.extend_from_slice(&qty.to_le_bytes());
-    msg.extend_from_slice(&timestamp.to_le_bytes());
+    // Canonical payload, shared with the settlement client and the program
+    let msg = TradeSettlementMessage {
+        program_id: program_id.to_bytes(),
+        trade_id,
+        buyer: buyer.pubkey().to_bytes(),
+        seller: seller.pubkey().to_bytes(),
+        market: [0u8; 16],
+        price,
+        quantity: qty,
+        timestamp,
+        buyer_nonce: 0,
+        seller_nonce: 0,
+    }
+    .encode();
     
-    // let signature = engine_identity.sign(&msg).to_bytes();
+    let signature = engine_identity.sign_message(&msg);
     let pubkey_bytes = engine_identity.pubkey().to_bytes();
 
     // 5. Construct Ed25519 Instruction Data
-    let mut instruction_data = Vec::with_capacity(176 + msg.len());
+    let mut instruction_data = Vec::with_capacity(112 + msg.len());
     instruction_data.extend_from_slice(&[1, 0]); // num_signatures, padding
-    instruction_data.extend_from_slice(&[112u8, 0]); // signature_offset
-    instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
-    instruction_data.extend_from_slice(&[48u8, 0]);  // pubkey_offset
-    instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
-    instruction_data.extend_from_slice(&[176u8, 0]); // message_offset
+    instruction_data.extend_from_slice(&[48u8, 0]); // signature_offset
+    instruction_data.extend_from_slice(&u16::MAX.to_le_bytes());
+    instruction_data.extend_from_slice(&[16u8, 0]);  // pubkey_offset
+    instruction_data.extend_from_slice(&u16::MAX.to_le_bytes());
+    instruction_data.extend_from_slice(&[112u8, 0]); // message_offset
     instruction_data.extend_from_slice(&(msg.len() as u16).to_le_bytes());
-    instruction_data.extend_from_slice(&[u16::MAX, u16::MAX].map(|x| x.to_le_bytes()).concat());
+    instruction_data.extend_from_slice(&u16::MAX.to_le_bytes());
     
-    instruction_data.extend_from_slice(&pubkey_bytes); // offset 48
-    // instruction_data.extend_from_slice(&signature);    // offset 112
-    instruction_data.extend_from_slice(&msg);          // offset 176
+    instruction_data.extend_from_slice(&pubkey_bytes); // offset 16
+    instruction_data.extend_from_slice(signature.as_ref()); // offset 48
+    instruction_data.extend_from_slice(&msg);          // offset 112
 
     let verify_ix = Instruction {
         program_id: solana_sdk::ed25519_program::ID,
@@ -142,4 +148,75 @@ fn test_signature_and_margin_health() {
     let buyer_acc_raw = svm.get_account(&buyer_margin_pda).unwrap();
     let buyer_acc = hybrid_perp_dex::state::MarginAccount::try_deserialize(&mut &buyer_acc_raw.data[..]).unwrap();
     assert_eq!(buyer_acc.nonce, 1);
-}
\ No newline at end of file
+}
+#[test]
+fn test_config_migration_and_rotation_window() {
+    use anchor_lang::Discriminator;
+    use hybrid_perp_dex::state::EngineConfig;
+
+    let mut svm = LiteSVM::new();
+    let authority = Keypair::new();
+    let engine_identity = Keypair::new();
+    svm.airdrop(&authority.pubkey(), 10_000_000_000).unwrap();
+    let program_id = hybrid_perp_dex::id();
+    let program_bytes = std::fs::read("../../target/deploy/hybrid_perp_dex.so")
+        .expect("Compiled .so not found. Run 'anchor build' first.");
+    svm.add_program(program_id, &program_bytes);
+    let (config_pda, config_bump) = Pubkey::find_program_address(&[b"engine_config"], &program_id);
+
+    // A config as created before signer rotation existed: `initialize` allocated
+    // 8 + 128 bytes and serialized the old layout into the start of them
+    let mut legacy = EngineConfig::DISCRIMINATOR.to_vec();
+    legacy.extend_from_slice(authority.pubkey().as_ref());
+    legacy.extend_from_slice(engine_identity.pubkey().as_ref());
+    legacy.extend_from_slice(Pubkey::new_unique().as_ref());
+    legacy.extend_from_slice(&500u16.to_le_bytes());
+    legacy.push(config_bump);
+    assert_eq!(legacy.len(), EngineConfig::LEGACY_SPACE);
+    legacy.resize(8 + 128, 0);
+    svm.set_account(config_pda, solana_sdk::account::Account {
+        lamports: svm.minimum_balance_for_rent_exemption(legacy.len()),
+        data: legacy,
+        owner: program_id,
+        executable: false,
+        rent_epoch: 0,
+    }).unwrap();
+
+    let send = |svm: &mut LiteSVM, ix: Instruction| {
+        let tx = Transaction::new_signed_with_payer(&[ix], Some(&authority.pubkey()), &[&authority], svm.latest_blockhash());
+        let result = svm.send_transaction(tx);
+        svm.expire_blockhash();
+        result
+    };
+    let migrate_ix = Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(config_pda, false),
+            AccountMeta::new(authority.pubkey(), true),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+        data: perp_ix::MigrateConfig {}.data(),
+    };
+    send(&mut svm, migrate_ix.clone()).expect("Failed to migrate the config");
+    // Running it again is harmless
+    send(&mut svm, migrate_ix).expect("Failed to re-run the migration");
+
+    let raw = svm.get_account(&config_pda).unwrap();
+    assert_eq!(raw.data.len(), EngineConfig::SPACE);
+    let config = EngineConfig::try_deserialize(&mut &raw.data[..]).unwrap();
+    assert_eq!(config.engine_signer, engine_identity.pubkey());
+    assert!(!config.has_pending_signer());
+    assert_eq!(config.pending_activation_slot, 0);
+
+    // A rotation needs an overlap window before it activates
+    let rotate_ix = |activation_slot: u64| Instruction {
+        program_id,
+        accounts: vec![AccountMeta::new(config_pda, false), AccountMeta::new_readonly(authority.pubkey(), true)],
+        data: perp_ix::RotateEngineSigner { new_signer: Pubkey::new_unique(), activation_slot }.data(),
+    };
+    svm.warp_to_slot(1_000);
+    let min_delay = hybrid_perp_dex::instructions::MIN_ROTATION_DELAY_SLOTS;
+    assert!(send(&mut svm, rotate_ix(1_000)).is_err(), "Immediate rotation was accepted");
+    assert!(send(&mut svm, rotate_ix(1_000 + min_delay - 1)).is_err(), "Rotation inside the window was accepted");
+    send(&mut svm, rotate_ix(1_000 + min_delay)).expect("Rotation after the window was rejected");
+}
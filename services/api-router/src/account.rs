@@ -0,0 +1,67 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use deadpool_postgres::Pool;
+use utoipa::ToSchema;
+
+use crate::db;
+use crate::units::fixed;
+
+/// A user's positions and realized PnL from the off-chain ledger. They reflect
+/// every persisted trade, including ones still waiting to settle on chain.
+#[derive(Serialize, ToSchema)]
+struct AccountResponse {
+    user_id: String,
+    /// Sum over all markets, in the collateral token
+    realized_pnl: Decimal,
+    positions: Vec<PositionRecord>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PositionRecord {
+    market: String,
+    /// Signed: positive is long, negative is short, zero is flat
+    size: Decimal,
+    avg_entry_price: Decimal,
+    realized_pnl: Decimal,
+    /// Unix milliseconds of the last trade applied
+    updated_at: i64,
+}
+
+/// Positions and realized PnL for a user. Markets the user has traded but is flat in are included.
+#[utoipa::path(
+    params(("user_id" = String, Path, description = "The user's wallet address")),
+    responses(
+        (status = 200, description = "The user's ledger", body = AccountResponse),
+        (status = 429, description = "Rate limit exceeded", body = crate::rate_limit::RateLimitedResponse),
+        (status = 503, description = "Database unavailable", body = String),
+    )
+)]
+#[get("/account/{user_id}")]
+async fn get_account(pool: web::Data<Pool>, user_id: web::Path<String>) -> impl Responder {
+    let db = match db::client(&pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+    let rows = match db.query(
+        "SELECT market, size, avg_entry_price, realized_pnl, updated_at FROM positions WHERE user_id = $1 ORDER BY market",
+        &[&user_id.as_str()],
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => return crate::internal_error("Reading positions", e),
+    };
+
+    let positions: Vec<PositionRecord> = rows
+        .iter()
+        .map(|row| PositionRecord {
+            market: row.get(0),
+            size: fixed(row.get(1)),
+            avg_entry_price: fixed(row.get(2)),
+            realized_pnl: fixed(row.get(3)),
+            updated_at: row.get(4),
+        })
+        .collect();
+    let realized_pnl = positions.iter().map(|p| p.realized_pnl).sum();
+    HttpResponse::Ok().json(AccountResponse { user_id: user_id.into_inner(), realized_pnl, positions })
+}
+
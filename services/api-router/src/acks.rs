@@ -0,0 +1,113 @@
+use common_utils::EngineAck;
+use fred::clients::SubscriberClient;
+use fred::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Requests waiting for the engines' `EngineAck`s, keyed by request ID.
+#[derive(Default)]
+pub struct AckRegistry {
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+struct Pending {
+    tx: oneshot::Sender<EngineAck>,
+    // One ack per engine the command was sent to
+    expected: usize,
+    received: Vec<EngineAck>,
+}
+
+impl AckRegistry {
+    /// Must be called before the command is queued so an early ack can't be missed.
+    /// The receiver gets the `engines` acks merged into one once they've all arrived.
+    pub fn register(&self, request_id: &str, engines: usize) -> oneshot::Receiver<EngineAck> {
+        let (tx, rx) = oneshot::channel();
+        let pending = Pending { tx, expected: engines.max(1), received: Vec::with_capacity(engines) };
+        self.pending.lock().unwrap().insert(request_id.to_string(), pending);
+        rx
+    }
+
+    pub fn cancel(&self, request_id: &str) {
+        self.pending.lock().unwrap().remove(request_id);
+    }
+
+    fn resolve(&self, ack: EngineAck) {
+        let request_id = ack.request_id().to_string();
+        let mut pending = self.pending.lock().unwrap();
+        let Some(entry) = pending.get_mut(&request_id) else { return };
+        entry.received.push(ack);
+        if entry.received.len() < entry.expected {
+            return;
+        }
+        let entry = pending.remove(&request_id).unwrap();
+        if let Some(ack) = merge(entry.received) {
+            let _ = entry.tx.send(ack);
+        }
+    }
+}
+
+/// Combines the acks of a command every engine applied: the orders each cancelled,
+/// stamped like the last engine to finish. Commands for a single engine have one ack.
+fn merge(acks: Vec<EngineAck>) -> Option<EngineAck> {
+    let mut acks = acks.into_iter();
+    let mut merged = acks.next()?;
+    for ack in acks {
+        match (&mut merged, ack) {
+            (EngineAck::Cancel(merged), EngineAck::Cancel(ack)) => {
+                merged.cancelled_order_ids.extend(ack.cancelled_order_ids);
+                if ack.sequence > merged.sequence {
+                    (merged.sequence, merged.timestamp_ns) = (ack.sequence, ack.timestamp_ns);
+                }
+            }
+            (EngineAck::DeadManSwitch(merged), EngineAck::DeadManSwitch(ack)) if ack.sequence > merged.sequence => {
+                *merged = ack;
+            }
+            _ => {}
+        }
+    }
+    Some(merged)
+}
+
+/// Forwards every ack published on `ORDER_ACKS` to the request waiting for it.
+pub async fn listen(subscriber: SubscriberClient, registry: std::sync::Arc<AckRegistry>) {
+    let mut rx = subscriber.message_rx();
+    while let Ok(message) = rx.recv().await {
+        let raw = match message.value.convert::<String>() {
+            Ok(raw) => raw,
+            Err(e) => {
+                eprintln!("❌ Invalid ack payload: {}", e);
+                continue;
+            }
+        };
+        match serde_json::from_str::<EngineAck>(&raw) {
+            Ok(ack) => registry.resolve(ack),
+            Err(e) => eprintln!("❌ Failed to parse ack: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_utils::CancelAck;
+
+    #[test]
+    fn a_command_sent_to_every_engine_resolves_once_all_have_acked() {
+        let registry = AckRegistry::default();
+        let mut rx = registry.register("cancel", 2);
+        let ack = |ids: Vec<u64>, sequence| EngineAck::Cancel(CancelAck {
+            request_id: "cancel".into(),
+            cancelled_order_ids: ids,
+            sequence,
+            timestamp_ns: sequence * 10,
+        });
+
+        registry.resolve(ack(vec![], 7));
+        assert!(rx.try_recv().is_err());
+        registry.resolve(ack(vec![3, 4], 5));
+        let Ok(EngineAck::Cancel(merged)) = rx.try_recv() else { panic!("expected a merged cancel ack") };
+        assert_eq!((merged.cancelled_order_ids, merged.sequence, merged.timestamp_ns), (vec![3, 4], 7, 70));
+        assert!(registry.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use common_utils::CANDLE_INTERVALS;
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db;
+use crate::units::fixed;
+
+const DEFAULT_CANDLES: i64 = 500;
+const MAX_CANDLES: i64 = 1_000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CandleQuery {
+    /// One of 1m, 5m, 1h, 1d (default 1m)
+    interval: Option<String>,
+    /// Earliest open time to include, Unix milliseconds
+    from: Option<i64>,
+    /// Open times before this are included, Unix milliseconds
+    to: Option<i64>,
+    /// At most 1000 (default 500); the most recent candles in the range are returned
+    limit: Option<i64>,
+}
+
+/// One OHLCV bar. Intervals without trades have no candle.
+#[derive(Serialize, ToSchema)]
+struct Candle {
+    /// Unix milliseconds
+    open_time: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: Decimal,
+    trade_count: i32,
+}
+
+/// OHLCV candles for a market, oldest first.
+#[utoipa::path(
+    params(("market" = String, Path, description = "Market symbol, e.g. SOL_USDC"), CandleQuery),
+    responses(
+        (status = 200, description = "Candles in the requested range", body = Vec<Candle>),
+        (status = 400, description = "Unknown interval or invalid range", body = String),
+        (status = 429, description = "Rate limit exceeded", body = crate::rate_limit::RateLimitedResponse),
+        (status = 503, description = "Database unavailable", body = String),
+    )
+)]
+#[get("/candles/{market}")]
+async fn get_candles(
+    pool: web::Data<Pool>,
+    market: web::Path<String>,
+    query: web::Query<CandleQuery>,
+) -> impl Responder {
+    let interval = query.interval.as_deref().unwrap_or("1m");
+    if !CANDLE_INTERVALS.iter().any(|(name, _)| *name == interval) {
+        let names: Vec<&str> = CANDLE_INTERVALS.iter().map(|(name, _)| *name).collect();
+        return HttpResponse::BadRequest().body(format!("interval must be one of {}", names.join(", ")));
+    }
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(i64::MAX);
+    if from >= to {
+        return HttpResponse::BadRequest().body("from must be before to");
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_CANDLES).clamp(1, MAX_CANDLES);
+    let db = match db::client(&pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+
+    let rows = match db.query(
+        "SELECT open_time, open, high, low, close, volume, trade_count FROM candles
+         WHERE market = $1 AND interval = $2 AND open_time >= $3 AND open_time < $4
+         ORDER BY open_time DESC LIMIT $5",
+        &[&market.as_str(), &interval, &from, &to, &limit],
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => return crate::internal_error("Reading candles", e),
+    };
+
+    let candles: Vec<Candle> = rows
+        .iter()
+        .rev()
+        .map(|row| Candle {
+            open_time: row.get(0),
+            open: fixed(row.get(1)),
+            high: fixed(row.get(2)),
+            low: fixed(row.get(3)),
+            close: fixed(row.get(4)),
+            volume: fixed(row.get(5)),
+            trade_count: row.get(6),
+        })
+        .collect();
+    HttpResponse::Ok().json(candles)
+}
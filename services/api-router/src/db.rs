@@ -0,0 +1,22 @@
+use actix_web::HttpResponse;
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use std::str::FromStr;
+
+/// Pool for the read endpoints, sized by API_DB_POOL_SIZE (16). Connections are
+/// opened on demand and replaced once broken, so the router starts while Postgres
+/// is down and recovers when it's back.
+pub fn pool_from_env() -> anyhow::Result<Pool> {
+    let url = std::env::var("DATABASE_URL").map_err(|_| anyhow::anyhow!("DATABASE_URL must be set in .env"))?;
+    let config = tokio_postgres::Config::from_str(&url)?;
+    let size = std::env::var("API_DB_POOL_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(16);
+    let manager = Manager::from_config(config, tokio_postgres::NoTls, ManagerConfig { recycling_method: RecyclingMethod::Fast });
+    Ok(Pool::builder(manager).max_size(size).build()?)
+}
+
+/// A pooled connection, or a 503 for the caller to return if Postgres can't be reached.
+pub async fn client(pool: &Pool) -> Result<Object, HttpResponse> {
+    pool.get().await.map_err(|e| {
+        eprintln!("❌ Postgres unavailable: {}", e);
+        HttpResponse::ServiceUnavailable().body("Database unavailable")
+    })
+}
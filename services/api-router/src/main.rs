@@ -1,51 +1,419 @@
-use actix_web::{web, App, HttpResponse, HttpServer, post, Responder};
-use common_utils::{Order, OrderRequest};
+use actix_web::{delete, middleware, web, App, HttpRequest, HttpResponse, HttpServer, post, Responder};
+use common_utils::{BatchOrderRequest, EngineAck, EngineCommand, Order, OrderRequest, OrderStatus};
 use fred::prelude::*;
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use std::env;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_actix_web::AppExt;
+use utoipa_actix_web::service_config::ServiceConfig;
 
-#[post("/order")]
-async fn create_order(redis: web::Data<RedisClient>, req: web::Json<OrderRequest>) -> impl Responder {
-    // 1. Transform OrderRequest (Strings) -> Order (Decimals)
-    let order = match (Decimal::from_str(&req.price), Decimal::from_str(&req.quantity)) {
-        (Ok(p), Ok(q)) => Order {
+mod account;
+mod acks;
+mod candles;
+mod db;
+mod openapi;
+mod orders;
+mod rate_limit;
+mod routing;
+mod statement;
+mod units;
+mod ws;
+
+use acks::AckRegistry;
+use rate_limit::{Action, RateLimitedResponse, RateLimiter};
+use routing::Markets;
+
+const DEFAULT_ACK_TIMEOUT_MS: u64 = 2_000;
+const MAX_ACK_TIMEOUT_MS: u64 = 10_000;
+const MAX_BATCH_ORDERS: usize = 50;
+const MAX_DEAD_MAN_TIMEOUT_MS: u64 = 3_600_000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AckQuery {
+    // Wait for the engine's ack instead of returning 202 immediately
+    #[serde(default)]
+    wait: bool,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DeadManSwitchRequest {
+    user_id: String,
+    // Countdown after which the engine cancels all of the user's orders; 0 disarms
+    timeout_ms: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct MassCancelQuery {
+    user_id: String,
+    market: Option<String>,
+    side: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct QueuedResponse {
+    status: String,
+    request_id: String,
+}
+
+/// Transform OrderRequest (Strings) -> Order (Decimals)
+fn parse_order(req: &OrderRequest, request_id: &str) -> Option<Order> {
+    match (Decimal::from_str(&req.price), Decimal::from_str(&req.quantity)) {
+        (Ok(p), Ok(q)) => Some(Order {
+            order_id: 0,
+            request_id: Some(request_id.to_string()),
             user_id: req.user_id.clone(),
+            market: req.market.clone(),
             price: p,
             quantity: q,
             side: req.side.clone(),
-        },
-        _ => return HttpResponse::BadRequest().body("Invalid price or quantity format"),
+        }),
+        _ => None,
+    }
+}
+
+/// Why a command wasn't queued.
+enum SubmitError {
+    // No engine takes it; the reason is meant for the client
+    Rejected(String),
+    Redis(RedisError),
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Rejected(reason) => f.write_str(reason),
+            SubmitError::Redis(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Queues `cmd` and, if the caller asked to wait, returns the engines' ack.
+/// `Ok(None)` means the command is queued but no ack arrived (or none was requested).
+async fn submit(
+    redis: &RedisClient,
+    acks: &AckRegistry,
+    markets: &Markets,
+    query: &AckQuery,
+    request_id: &str,
+    cmd: &EngineCommand,
+) -> Result<Option<EngineAck>, SubmitError> {
+    let ack_rx = enqueue(redis, acks, markets, query.wait, request_id, cmd).await?;
+    Ok(await_ack(acks, request_id, ack_rx, query.timeout_ms).await)
+}
+
+/// Pushes `cmd` to the queue of every engine that must apply it. With `wait`, the
+/// returned receiver gets their ack.
+async fn enqueue(
+    redis: &RedisClient,
+    acks: &AckRegistry,
+    markets: &Markets,
+    wait: bool,
+    request_id: &str,
+    cmd: &EngineCommand,
+) -> Result<Option<oneshot::Receiver<EngineAck>>, SubmitError> {
+    let queues = markets.queues_for(cmd).map_err(SubmitError::Rejected)?;
+
+    // Registered before queueing so a fast engine can't beat us to the ack
+    let ack_rx = wait.then(|| acks.register(request_id, queues.len()));
+    let payload = serde_json::to_string(cmd).unwrap();
+    for queue in &queues {
+        if let Err(e) = redis.lpush::<i64, _, _>(queue, payload.clone()).await {
+            acks.cancel(request_id);
+            return Err(SubmitError::Redis(e));
+        }
+    }
+    Ok(ack_rx)
+}
+
+/// Waits up to `timeout_ms` (capped) for the ack of a command `enqueue`d with `wait`.
+async fn await_ack(
+    acks: &AckRegistry,
+    request_id: &str,
+    ack_rx: Option<oneshot::Receiver<EngineAck>>,
+    timeout_ms: Option<u64>,
+) -> Option<EngineAck> {
+    let rx = ack_rx?;
+    let timeout_ms = timeout_ms.unwrap_or(DEFAULT_ACK_TIMEOUT_MS).min(MAX_ACK_TIMEOUT_MS);
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), rx).await {
+        Ok(Ok(ack)) => Some(ack),
+        // Timed out: the command is still queued, fall back to the async response
+        _ => {
+            acks.cancel(request_id);
+            None
+        }
+    }
+}
+
+fn ack_response(ack: EngineAck) -> HttpResponse {
+    let rejected = match &ack {
+        EngineAck::Order(order) => order.status == OrderStatus::Rejected,
+        EngineAck::Batch { orders, .. } => orders.iter().any(|o| o.status == OrderStatus::Rejected),
+        EngineAck::Cancel(_) | EngineAck::DeadManSwitch(_) => false,
     };
+    if rejected {
+        HttpResponse::UnprocessableEntity().json(ack)
+    } else {
+        HttpResponse::Ok().json(ack)
+    }
+}
 
-    // 2. Serialize and push to the queue the Engine is actually watching
-    let payload = serde_json::to_string(&order).unwrap();
-    
-    if let Err(e) = redis.lpush::<i64, _, _>("ORDER_QUEUE", payload).await {
-        return HttpResponse::InternalServerError().body(e.to_string());
+/// Logs `e` and returns a 500 that doesn't expose it: database and Redis errors can
+/// name tables, queries or hosts.
+fn internal_error(context: &str, e: impl std::fmt::Display) -> HttpResponse {
+    eprintln!("❌ {}: {}", context, e);
+    HttpResponse::InternalServerError().body("Internal server error")
+}
+
+fn submit_error(e: SubmitError) -> HttpResponse {
+    match e {
+        SubmitError::Rejected(reason) => HttpResponse::BadRequest().body(reason),
+        SubmitError::Redis(e) => internal_error("Queueing command", e),
     }
+}
 
-    println!("✅ Order Queued: {} {} @ {}", order.side, order.quantity, order.price);
-    HttpResponse::Accepted().json(serde_json::json!({"status": "queued"}))
+fn queued_response(request_id: &str) -> HttpResponse {
+    HttpResponse::Accepted().json(QueuedResponse { status: "queued".into(), request_id: request_id.to_string() })
+}
+
+/// Place a single limit order.
+#[utoipa::path(
+    params(AckQuery),
+    request_body = OrderRequest,
+    responses(
+        (status = 200, description = "Order processed by the engine", body = EngineAck),
+        (status = 202, description = "Order queued; no ack was requested or none arrived in time", body = QueuedResponse),
+        (status = 400, description = "Invalid price or quantity format, or unknown market", body = String),
+        (status = 422, description = "Order rejected by the engine", body = EngineAck),
+        (status = 429, description = "Rate limit exceeded", body = RateLimitedResponse),
+    )
+)]
+#[post("/order")]
+async fn create_order(
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    query: web::Query<AckQuery>,
+    req: web::Json<OrderRequest>,
+) -> impl Responder {
+    let request_id = uuid::Uuid::new_v4().to_string();
+
+    let Some(order) = parse_order(&req, &request_id) else {
+        return HttpResponse::BadRequest().body("Invalid price or quantity format");
+    };
+    let summary = format!("{} {} @ {}", order.side, order.quantity, order.price);
+
+    match submit(&redis, &acks, &markets, &query, &request_id, &EngineCommand::PlaceOrder(order)).await {
+        Ok(ack) => {
+            println!("✅ Order Queued: {}", summary);
+            ack.map_or_else(|| queued_response(&request_id), ack_response)
+        }
+        Err(e) => submit_error(e),
+    }
+}
+
+/// Place up to 50 orders that the engine processes in sequence as one command.
+#[utoipa::path(
+    params(AckQuery),
+    request_body = BatchOrderRequest,
+    responses(
+        (status = 200, description = "Batch processed by the engine", body = EngineAck),
+        (status = 202, description = "Batch queued; no ack was requested or none arrived in time", body = QueuedResponse),
+        (status = 400, description = "Empty, oversized or malformed batch, or orders for an unknown or more than one market", body = String),
+        (status = 413, description = "The batch costs more than the caller's rate limit allows at once", body = String),
+        (status = 422, description = "Batch rejected by the engine", body = EngineAck),
+        (status = 429, description = "Rate limit exceeded", body = RateLimitedResponse),
+    )
+)]
+#[post("/orders/batch")]
+async fn create_order_batch(
+    http_req: HttpRequest,
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    limiter: web::Data<RateLimiter>,
+    query: web::Query<AckQuery>,
+    req: web::Json<BatchOrderRequest>,
+) -> impl Responder {
+    if req.orders.is_empty() || req.orders.len() > MAX_BATCH_ORDERS {
+        return HttpResponse::BadRequest().body(format!("A batch must contain 1 to {} orders", MAX_BATCH_ORDERS));
+    }
+    if let Err(response) = limiter.check_request(&http_req, Action::PlaceOrder, req.orders.len() as u32) {
+        return response;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut orders = Vec::with_capacity(req.orders.len());
+    for (i, order_req) in req.orders.iter().enumerate() {
+        match parse_order(order_req, &request_id) {
+            Some(order) => orders.push(order),
+            None => return HttpResponse::BadRequest().body(format!("Order {}: Invalid price or quantity format", i)),
+        }
+    }
+    let count = orders.len();
+
+    let cmd = EngineCommand::PlaceBatch { request_id: request_id.clone(), orders };
+    match submit(&redis, &acks, &markets, &query, &request_id, &cmd).await {
+        Ok(ack) => {
+            println!("✅ Batch Queued: {} orders", count);
+            ack.map_or_else(|| queued_response(&request_id), ack_response)
+        }
+        Err(e) => submit_error(e),
+    }
+}
+
+/// Cancel a user's resting orders, optionally narrowed to a market and side.
+/// Without a market, every market's engine cancels and the acks are combined.
+#[utoipa::path(
+    params(AckQuery, MassCancelQuery),
+    responses(
+        (status = 200, description = "Orders cancelled by the engine", body = EngineAck),
+        (status = 202, description = "Cancel queued; no ack was requested or none arrived in time", body = QueuedResponse),
+        (status = 400, description = "Unknown market", body = String),
+        (status = 429, description = "Rate limit exceeded", body = RateLimitedResponse),
+    )
+)]
+#[delete("/orders")]
+async fn mass_cancel(
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    query: web::Query<AckQuery>,
+    filter: web::Query<MassCancelQuery>,
+) -> impl Responder {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let filter = filter.into_inner();
+
+    let cmd = EngineCommand::MassCancel {
+        request_id: request_id.clone(),
+        user_id: filter.user_id,
+        market: filter.market,
+        side: filter.side,
+    };
+    match submit(&redis, &acks, &markets, &query, &request_id, &cmd).await {
+        Ok(ack) => ack.map_or_else(|| queued_response(&request_id), ack_response),
+        Err(e) => submit_error(e),
+    }
+}
+
+/// Arm, refresh or disarm (timeout_ms = 0) the user's dead man's switch.
+#[utoipa::path(
+    params(AckQuery),
+    request_body = DeadManSwitchRequest,
+    responses(
+        (status = 200, description = "Switch updated by the engine", body = EngineAck),
+        (status = 202, description = "Update queued; no ack was requested or none arrived in time", body = QueuedResponse),
+        (status = 400, description = "timeout_ms out of range", body = String),
+        (status = 429, description = "Rate limit exceeded", body = RateLimitedResponse),
+    )
+)]
+#[post("/dead-man-switch")]
+async fn dead_man_switch(
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    query: web::Query<AckQuery>,
+    req: web::Json<DeadManSwitchRequest>,
+) -> impl Responder {
+    if req.timeout_ms > MAX_DEAD_MAN_TIMEOUT_MS {
+        return HttpResponse::BadRequest().body(format!("timeout_ms must be at most {}", MAX_DEAD_MAN_TIMEOUT_MS));
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let cmd = EngineCommand::ArmDeadManSwitch {
+        request_id: request_id.clone(),
+        user_id: req.user_id.clone(),
+        timeout_ms: req.timeout_ms,
+    };
+    match submit(&redis, &acks, &markets, &query, &request_id, &cmd).await {
+        Ok(ack) => ack.map_or_else(|| queued_response(&request_id), ack_response),
+        Err(e) => submit_error(e),
+    }
+}
+
+/// Registers every route. Only `#[utoipa::path]` handlers can be registered here,
+/// which keeps `/openapi.json` in step with the router.
+fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(create_order)
+        .service(create_order_batch)
+        .service(mass_cancel)
+        .service(dead_man_switch)
+        .service(candles::get_candles)
+        .service(orders::list_orders)
+        .service(orders::order_events)
+        .service(account::get_account)
+        .service(statement::export_statement)
+        .service(ws::connect);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenvy::dotenv().ok();
+
+    // Statements for accounting: `api-router export <wallet> [from_ms] [to_ms] [csv|json]`
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export") {
+        let result = match db::pool_from_env() {
+            Ok(pool) => statement::run_export_cli(&pool, &args[1..]).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("❌ {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let redis_url = env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
 
     let config = RedisConfig::from_url(&redis_url).unwrap();
-    let redis = Builder::from_config(config).build().unwrap();
+    let redis = Builder::from_config(config.clone()).build().unwrap();
     redis.init().await.unwrap();
 
+    // Dedicated connection for engine acks (a subscribed connection can't issue other commands)
+    let subscriber = Builder::from_config(config).build_subscriber_client().unwrap();
+    subscriber.init().await.unwrap();
+    subscriber.subscribe("ORDER_ACKS").await.unwrap();
+    subscriber.manage_subscriptions();
+
+    let acks = web::Data::new(AckRegistry::default());
+    tokio::spawn(acks::listen(subscriber, acks.clone().into_inner()));
+
+    let limiter = web::Data::new(RateLimiter::from_env());
+    let markets = web::Data::new(Markets::from_env());
+
+    // Read-only market data, order history and positions written by db-processor
+    let db = match db::pool_from_env() {
+        Ok(pool) => web::Data::new(pool),
+        Err(e) => {
+            eprintln!("❌ Postgres: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     println!("🚀 API Router running on 127.0.0.1:7000");
 
     HttpServer::new(move || {
         App::new()
+            .into_utoipa_app()
+            .openapi(openapi::base())
             .app_data(web::Data::new(redis.clone()))
-            .service(create_order)
+            .app_data(acks.clone())
+            .app_data(limiter.clone())
+            .app_data(markets.clone())
+            .app_data(db.clone())
+            .configure(routes)
+            .openapi_service(openapi::service)
+            .map(|app| app.wrap(middleware::from_fn(rate_limit::enforce)))
+            .into_app()
     })
     .bind("127.0.0.1:7000")?
     .run()
     .await
-}
\ No newline at end of file
+}
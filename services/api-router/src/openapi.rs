@@ -0,0 +1,98 @@
+use actix_web::{web, HttpResponse, Resource};
+use utoipa::openapi::OpenApi as OpenApiDoc;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(info(
+    title = "Hybrid PERP DEX API Router",
+    description = "Order entry and market data for the off-chain matching engine. Prices and quantities are decimal strings."
+))]
+struct ApiDoc;
+
+/// Top-level document the handler paths and schemas are collected into.
+pub fn base() -> OpenApiDoc {
+    ApiDoc::openapi()
+}
+
+/// Serves the collected spec at `/openapi.json`.
+pub fn service(spec: OpenApiDoc) -> Resource {
+    web::resource("/openapi.json")
+        .app_data(web::Data::new(spec))
+        .route(web::get().to(|spec: web::Data<OpenApiDoc>| async move { HttpResponse::Ok().json(spec.get_ref()) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, App};
+    use utoipa::openapi::path::Operation;
+    use utoipa::openapi::PathItem;
+    use utoipa_actix_web::AppExt;
+
+    fn spec() -> OpenApiDoc {
+        App::new().into_utoipa_app().openapi(base()).configure(crate::routes).split_for_parts().1
+    }
+
+    fn operations(item: &PathItem) -> [(Method, Option<&Operation>); 5] {
+        [
+            (Method::GET, item.get.as_ref()),
+            (Method::POST, item.post.as_ref()),
+            (Method::PUT, item.put.as_ref()),
+            (Method::PATCH, item.patch.as_ref()),
+            (Method::DELETE, item.delete.as_ref()),
+        ]
+    }
+
+    fn is_routed(status: StatusCode) -> bool {
+        status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED
+    }
+
+    // Handlers run without app data here, so a routed request fails extraction (4xx/5xx)
+    // rather than touching Redis; only 404/405 mean the router doesn't know the operation.
+    #[actix_web::test]
+    async fn spec_operations_match_routed_handlers() {
+        let spec = spec();
+        let app = test::init_service(
+            App::new().into_utoipa_app().openapi(base()).configure(crate::routes).openapi_service(service).into_app(),
+        )
+        .await;
+
+        let mut documented = 0;
+        for (path, item) in &spec.paths.paths {
+            for (method, op) in operations(item) {
+                let req = test::TestRequest::default().method(method.clone()).uri(path).to_request();
+                let status = test::call_service(&app, req).await.status();
+                if op.is_some() {
+                    documented += 1;
+                    assert!(is_routed(status), "{} {} is documented but not routed ({})", method, path, status);
+                } else {
+                    assert!(!is_routed(status), "{} {} is routed but not documented ({})", method, path, status);
+                }
+            }
+        }
+        assert_eq!(documented, 10);
+
+        let served: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/openapi.json").to_request()).await;
+        assert_eq!(served, serde_json::to_value(&spec).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn request_bodies_reference_shared_types() {
+        let spec = serde_json::to_value(spec()).unwrap();
+        let body_ref = |path: &str| spec["paths"][path]["post"]["requestBody"]["content"]["application/json"]["schema"]["$ref"].clone();
+
+        assert_eq!(body_ref("/order"), "#/components/schemas/OrderRequest");
+        assert_eq!(body_ref("/orders/batch"), "#/components/schemas/BatchOrderRequest");
+        assert_eq!(body_ref("/dead-man-switch"), "#/components/schemas/DeadManSwitchRequest");
+
+        let schemas = &spec["components"]["schemas"];
+        for name in ["OrderRequest", "BatchOrderRequest", "EngineAck", "OrderAck", "MatchResult", "QueuedResponse", "Candle", "OrderRecord", "OrderEventRecord", "AccountResponse", "StatementEntry"] {
+            assert!(schemas.get(name).is_some(), "schema {} missing from spec", name);
+        }
+        // Decimals travel as strings so clients never round through floats
+        assert_eq!(schemas["MatchResult"]["properties"]["price"]["type"], "string");
+        assert_eq!(schemas["Candle"]["properties"]["close"]["type"], "string");
+    }
+}
@@ -0,0 +1,157 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db;
+use crate::units::fixed;
+
+const DEFAULT_ORDERS: i64 = 100;
+const MAX_ORDERS: i64 = 500;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrderHistoryQuery {
+    user_id: String,
+    /// resting, partially_filled, filled, cancelled or rejected
+    status: Option<String>,
+    market: Option<String>,
+    /// Only orders created before this, Unix milliseconds; pass the last `created_at` to page back
+    before: Option<i64>,
+    /// At most 500 (default 100)
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrderEventsQuery {
+    /// Owner of the order
+    user_id: String,
+}
+
+/// An order's latest state as recorded by db-processor.
+#[derive(Serialize, ToSchema)]
+struct OrderRecord {
+    order_id: i64,
+    market: String,
+    user_id: String,
+    side: String,
+    price: Decimal,
+    quantity: Decimal,
+    filled_quantity: Decimal,
+    status: String,
+    /// Unix milliseconds
+    created_at: i64,
+    /// Unix milliseconds
+    updated_at: i64,
+}
+
+/// One step in an order's life: placed, filled, cancelled or rejected.
+#[derive(Serialize, ToSchema)]
+struct OrderEventRecord {
+    event_type: String,
+    /// Filled quantity for fills, the cancelled remainder for cancels, otherwise the order quantity
+    quantity: Option<Decimal>,
+    /// Fill price for fills, otherwise the order's limit price
+    price: Option<Decimal>,
+    trade_id: Option<i64>,
+    reason: Option<String>,
+    /// Engine sequence number; events of one order are in this order
+    sequence: i64,
+    /// Unix milliseconds
+    timestamp: i64,
+}
+
+/// A user's orders, newest first.
+#[utoipa::path(
+    params(OrderHistoryQuery),
+    responses(
+        (status = 200, description = "Orders matching the filters", body = Vec<OrderRecord>),
+        (status = 429, description = "Rate limit exceeded", body = crate::rate_limit::RateLimitedResponse),
+        (status = 503, description = "Database unavailable", body = String),
+    )
+)]
+#[get("/orders")]
+async fn list_orders(pool: web::Data<Pool>, query: web::Query<OrderHistoryQuery>) -> impl Responder {
+    let db = match db::client(&pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_ORDERS).clamp(1, MAX_ORDERS);
+    let before = query.before.unwrap_or(i64::MAX);
+
+    let rows = match db.query(
+        "SELECT order_id, market, user_id, side, price, quantity, filled_quantity, status, created_at, updated_at FROM orders
+         WHERE user_id = $1 AND created_at < $2 AND ($3::VARCHAR IS NULL OR status = $3) AND ($4::VARCHAR IS NULL OR market = $4)
+         ORDER BY created_at DESC, order_id DESC LIMIT $5",
+        &[&query.user_id, &before, &query.status, &query.market, &limit],
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => return crate::internal_error("Reading orders", e),
+    };
+
+    let orders: Vec<OrderRecord> = rows
+        .iter()
+        .map(|row| OrderRecord {
+            order_id: row.get(0),
+            market: row.get(1),
+            user_id: row.get(2),
+            side: row.get(3),
+            price: fixed(row.get(4)),
+            quantity: fixed(row.get(5)),
+            filled_quantity: fixed(row.get(6)),
+            status: row.get(7),
+            created_at: row.get(8),
+            updated_at: row.get(9),
+        })
+        .collect();
+    HttpResponse::Ok().json(orders)
+}
+
+/// Everything that happened to one order, oldest first.
+#[utoipa::path(
+    params(("order_id" = i64, Path, description = "Order ID from the engine's ack"), OrderEventsQuery),
+    responses(
+        (status = 200, description = "The order's lifecycle events", body = Vec<OrderEventRecord>),
+        (status = 404, description = "No such order for this user", body = String),
+        (status = 429, description = "Rate limit exceeded", body = crate::rate_limit::RateLimitedResponse),
+        (status = 503, description = "Database unavailable", body = String),
+    )
+)]
+#[get("/orders/{order_id}/events")]
+async fn order_events(pool: web::Data<Pool>, order_id: web::Path<i64>, query: web::Query<OrderEventsQuery>) -> impl Responder {
+    let db = match db::client(&pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+    let order_id = order_id.into_inner();
+    match db.query_opt("SELECT 1 FROM orders WHERE order_id = $1 AND user_id = $2", &[&order_id, &query.user_id]).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body(format!("Order {} not found", order_id)),
+        Err(e) => return crate::internal_error("Reading orders", e),
+    }
+
+    let rows = match db.query(
+        "SELECT event_type, quantity, price, trade_id, reason, sequence, timestamp FROM order_events
+         WHERE order_id = $1 ORDER BY sequence, event_id",
+        &[&order_id],
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => return crate::internal_error("Reading orders", e),
+    };
+
+    let events: Vec<OrderEventRecord> = rows
+        .iter()
+        .map(|row| OrderEventRecord {
+            event_type: row.get(0),
+            quantity: row.get::<_, Option<i64>>(1).map(fixed),
+            price: row.get::<_, Option<i64>>(2).map(fixed),
+            trade_id: row.get(3),
+            reason: row.get(4),
+            sequence: row.get(5),
+            timestamp: row.get(6),
+        })
+        .collect();
+    HttpResponse::Ok().json(events)
+}
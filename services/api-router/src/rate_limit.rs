@@ -0,0 +1,389 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+// Buckets that have refilled completely carry no state and are dropped past this size
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+// Longest Retry-After sent, e.g. for a tier that never refills
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+// Routes whose cost depends on the body; their handlers charge the whole request
+// through `check_request`, so the middleware leaves them alone
+const CHARGED_BY_HANDLER: &[&str] = &["/orders/batch"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    PlaceOrder,
+    CancelOrder,
+    Read,
+}
+
+impl Action {
+    fn for_method(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD => Action::Read,
+            Method::DELETE => Action::CancelOrder,
+            _ => Action::PlaceOrder,
+        }
+    }
+
+    fn weight(self) -> f64 {
+        match self {
+            Action::PlaceOrder => 2.0,
+            Action::CancelOrder => 1.0,
+            Action::Read => 1.0,
+        }
+    }
+}
+
+/// Burst capacity and sustained refill rate, both in weight units.
+#[derive(Debug, Clone, Copy)]
+pub struct Tier {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl Tier {
+    /// Parses `capacity:refill_per_sec`, e.g. `20:10`.
+    fn parse(raw: &str) -> Option<Self> {
+        let (capacity, refill) = raw.trim().split_once(':')?;
+        Some(Tier { capacity: capacity.parse().ok()?, refill_per_sec: refill.parse().ok()? })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, tier: Tier, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * tier.refill_per_sec).min(tier.capacity);
+        self.last = now;
+    }
+
+    /// Time until `weight` tokens are available, zero if they already are. Capped at
+    /// MAX_RETRY_AFTER, including for weights the bucket can never hold.
+    fn wait_for(&self, tier: Tier, weight: f64) -> Duration {
+        if self.tokens >= weight {
+            return Duration::ZERO;
+        }
+        if weight > tier.capacity || tier.refill_per_sec <= 0.0 {
+            return MAX_RETRY_AFTER;
+        }
+        Duration::from_secs_f64((weight - self.tokens) / tier.refill_per_sec).min(MAX_RETRY_AFTER)
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    users: HashMap<String, Bucket>,
+    ips: HashMap<IpAddr, Bucket>,
+}
+
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+    tiers: HashMap<String, Tier>,
+    user_tiers: HashMap<String, String>,
+    ip_tier: Tier,
+    // Peers whose X-Forwarded-For / Forwarded headers name the client
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RateLimiter {
+    /// Reads limits from the environment:
+    /// - `RATE_LIMIT_TIERS`: `name=capacity:refill,...`, must include `default`
+    /// - `RATE_LIMIT_USER_TIERS`: `user_id=tier,...`
+    /// - `RATE_LIMIT_IP`: `capacity:refill` applied per client IP
+    /// - `TRUSTED_PROXIES`: `ip,...` of proxies whose forwarding headers are believed
+    pub fn from_env() -> Self {
+        let mut tiers: HashMap<String, Tier> = parse_pairs(&std::env::var("RATE_LIMIT_TIERS").unwrap_or_default())
+            .filter_map(|(name, raw)| Some((name, Tier::parse(&raw)?)))
+            .collect();
+        tiers.entry("default".into()).or_insert(Tier { capacity: 20.0, refill_per_sec: 10.0 });
+
+        let user_tiers = parse_pairs(&std::env::var("RATE_LIMIT_USER_TIERS").unwrap_or_default()).collect();
+
+        let ip_tier = std::env::var("RATE_LIMIT_IP")
+            .ok()
+            .and_then(|raw| Tier::parse(&raw))
+            .unwrap_or(Tier { capacity: 50.0, refill_per_sec: 25.0 });
+
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+
+        Self::new(tiers, user_tiers, ip_tier).with_trusted_proxies(trusted_proxies)
+    }
+
+    pub fn new(tiers: HashMap<String, Tier>, user_tiers: HashMap<String, String>, ip_tier: Tier) -> Self {
+        Self { buckets: Mutex::new(Buckets::default()), tiers, user_tiers, ip_tier, trusted_proxies: Vec::new() }
+    }
+
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// The IP `req` came from. Behind a trusted proxy that's the last address in its
+    /// forwarding chain that isn't one of our proxies; everything before it could have
+    /// been sent by the client. Anyone else is charged by their own address.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let chain = forwarded_for(req);
+        let client = chain.iter().rev().find(|ip| !self.trusted_proxies.contains(ip)).or(chain.first());
+        Some(client.copied().unwrap_or(peer))
+    }
+
+    fn user_tier(&self, user_id: &str) -> Tier {
+        self.user_tiers
+            .get(user_id)
+            .and_then(|name| self.tiers.get(name))
+            .or_else(|| self.tiers.get("default"))
+            .copied()
+            .unwrap_or(Tier { capacity: 0.0, refill_per_sec: 0.0 })
+    }
+
+    /// Most units of `action` a single request can ever be charged for: beyond this
+    /// the user's or the IP's bucket couldn't hold the cost even when full.
+    pub fn max_count(&self, user_id: Option<&str>, ip: Option<IpAddr>, action: Action) -> u32 {
+        let capacity = user_id
+            .map(|id| self.user_tier(id).capacity)
+            .into_iter()
+            .chain(ip.map(|_| self.ip_tier.capacity))
+            .fold(f64::INFINITY, f64::min);
+        (capacity / action.weight()).floor().min(f64::from(u32::MAX)) as u32
+    }
+
+    /// Charges `count` units of `action` against both the user's and the IP's bucket.
+    /// Nothing is charged unless both have room; otherwise returns how long to wait.
+    pub fn check(&self, user_id: Option<&str>, ip: Option<IpAddr>, action: Action, count: u32) -> Result<(), Duration> {
+        let weight = action.weight() * f64::from(count);
+        let now = Instant::now();
+        let mut guard = self.buckets.lock().unwrap();
+        let buckets = &mut *guard;
+
+        let user = user_id.map(|id| {
+            let tier = self.user_tier(id);
+            let bucket = buckets
+                .users
+                .entry(id.to_string())
+                .or_insert(Bucket { tokens: tier.capacity, last: now });
+            bucket.refill(tier, now);
+            (bucket, tier)
+        });
+        let ip_tier = self.ip_tier;
+        let ip = ip.map(|addr| {
+            let bucket = buckets.ips.entry(addr).or_insert(Bucket { tokens: ip_tier.capacity, last: now });
+            bucket.refill(ip_tier, now);
+            (bucket, ip_tier)
+        });
+
+        let wait = user
+            .iter()
+            .chain(ip.iter())
+            .map(|(bucket, tier)| bucket.wait_for(*tier, weight))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (bucket, _) in user.into_iter().chain(ip) {
+            bucket.tokens -= weight;
+        }
+
+        if buckets.users.len() > MAX_TRACKED_BUCKETS {
+            buckets.users.retain(|id, b| {
+                let tier = self.user_tier(id);
+                b.tokens + now.saturating_duration_since(b.last).as_secs_f64() * tier.refill_per_sec < tier.capacity
+            });
+        }
+        if buckets.ips.len() > MAX_TRACKED_BUCKETS {
+            buckets.ips.retain(|_, b| {
+                b.tokens + now.saturating_duration_since(b.last).as_secs_f64() * ip_tier.refill_per_sec < ip_tier.capacity
+            });
+        }
+        Ok(())
+    }
+
+    /// Charges the caller of `req`; used by handlers whose cost depends on the body
+    /// (see CHARGED_BY_HANDLER). A request that costs more than the caller's buckets
+    /// can hold gets a 413 instead of a 429 it could never get past.
+    pub fn check_request(&self, req: &HttpRequest, action: Action, count: u32) -> Result<(), HttpResponse> {
+        let user_id = req.headers().get("X-User-Id").and_then(|v| v.to_str().ok());
+        let ip = self.client_ip(req);
+        let max = self.max_count(user_id, ip, action);
+        if count > max {
+            return Err(HttpResponse::PayloadTooLarge().body(format!("At most {} per request at this rate limit", max)));
+        }
+        self.check(user_id, ip, action, count).map_err(too_many_requests)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RateLimitedResponse {
+    error: String,
+    retry_after_secs: u64,
+}
+
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    // Round up so clients never retry before the bucket has refilled
+    let secs = retry_after.as_secs().saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", secs.to_string()))
+        .json(RateLimitedResponse { error: "rate limit exceeded".into(), retry_after_secs: secs })
+}
+
+/// Middleware charging every request, except CHARGED_BY_HANDLER routes, against the caller's buckets.
+/// The user is identified by the `X-User-Id` header set by the auth proxy.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if let Some(limiter) = req.app_data::<web::Data<RateLimiter>>()
+        && !CHARGED_BY_HANDLER.contains(&req.path())
+        && let Err(response) = limiter.check_request(req.request(), Action::for_method(req.method()), 1)
+    {
+        return Ok(req.into_response(response));
+    }
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+/// Addresses in `Forwarded` (`for=` parameters), or else in `X-Forwarded-For`, in
+/// the order the proxies appended them. Obfuscated or unknown entries are skipped.
+fn forwarded_for(req: &HttpRequest) -> Vec<IpAddr> {
+    let values = |name| req.headers().get_all(name).filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(','));
+    let forwarded: Vec<IpAddr> = values("Forwarded")
+        .flat_map(|element| element.split(';'))
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .filter_map(|(_, node)| parse_node(node))
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    values("X-Forwarded-For").filter_map(parse_node).collect()
+}
+
+/// `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:4711"` or `2001:db8::1`.
+fn parse_node(raw: &str) -> Option<IpAddr> {
+    let node = raw.trim().trim_matches('"');
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+fn parse_pairs(raw: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    raw.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        let tiers = HashMap::from([
+            ("default".to_string(), Tier { capacity: 4.0, refill_per_sec: 1.0 }),
+            ("mm".to_string(), Tier { capacity: 100.0, refill_per_sec: 50.0 }),
+        ]);
+        let user_tiers = HashMap::from([("maker".to_string(), "mm".to_string())]);
+        RateLimiter::new(tiers, user_tiers, Tier { capacity: 10.0, refill_per_sec: 5.0 })
+    }
+
+    #[test]
+    fn order_placement_costs_more_than_cancels() {
+        let rl = limiter();
+        assert!(rl.check(Some("alice"), None, Action::PlaceOrder, 1).is_ok());
+        assert!(rl.check(Some("alice"), None, Action::PlaceOrder, 1).is_ok());
+        let wait = rl.check(Some("alice"), None, Action::PlaceOrder, 1).unwrap_err();
+        assert!(wait > Duration::from_millis(1500) && wait <= Duration::from_secs(2));
+
+        let rl = limiter();
+        for _ in 0..4 {
+            assert!(rl.check(Some("alice"), None, Action::CancelOrder, 1).is_ok());
+        }
+        assert!(rl.check(Some("alice"), None, Action::Read, 1).is_err());
+    }
+
+    #[test]
+    fn tiers_and_ip_are_limited_independently() {
+        let rl = limiter();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..5 {
+            assert!(rl.check(Some("maker"), Some(ip), Action::PlaceOrder, 1).is_ok());
+        }
+        // The market maker still has budget, but the shared IP is exhausted
+        assert!(rl.check(Some("maker"), Some(ip), Action::PlaceOrder, 1).is_err());
+        assert!(rl.check(Some("maker"), None, Action::PlaceOrder, 1).is_ok());
+    }
+
+    #[test]
+    fn rejected_requests_are_not_charged() {
+        let rl = limiter();
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        for _ in 0..4 {
+            assert!(rl.check(Some("alice"), Some(ip), Action::Read, 1).is_ok());
+        }
+        assert!(rl.check(Some("alice"), Some(ip), Action::Read, 1).is_err());
+        // The IP bucket was only charged for the four accepted reads
+        for _ in 0..6 {
+            assert!(rl.check(None, Some(ip), Action::Read, 1).is_ok());
+        }
+        assert!(rl.check(None, Some(ip), Action::Read, 1).is_err());
+    }
+
+    #[test]
+    fn client_ip_is_only_taken_from_trusted_proxies_headers() {
+        let proxy: IpAddr = "10.0.0.9".parse().unwrap();
+        let rl = limiter().with_trusted_proxies(vec![proxy]);
+        let request = |peer: &str, header: (&str, &str)| {
+            actix_web::test::TestRequest::default().peer_addr(peer.parse().unwrap()).insert_header(header).to_http_request()
+        };
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        // The client may send its own X-Forwarded-For; only what the proxy appended counts
+        let proxied = request("10.0.0.9:4000", ("X-Forwarded-For", "6.6.6.6, 1.2.3.4"));
+        assert_eq!(rl.client_ip(&proxied), ip("1.2.3.4"));
+        let forwarded = request("10.0.0.9:4000", ("Forwarded", "for=6.6.6.6, for=\"[2001:db8::1]:4711\";proto=https"));
+        assert_eq!(rl.client_ip(&forwarded), ip("2001:db8::1"));
+        let through_both = request("10.0.0.9:4000", ("X-Forwarded-For", "1.2.3.4, 10.0.0.9"));
+        assert_eq!(rl.client_ip(&through_both), ip("1.2.3.4"));
+
+        // Anyone else can't pick the bucket they're charged to
+        let direct = request("5.5.5.5:4000", ("X-Forwarded-For", "1.2.3.4"));
+        assert_eq!(rl.client_ip(&direct), ip("5.5.5.5"));
+        let headerless = request("10.0.0.9:4000", ("X-Other", "1.2.3.4"));
+        assert_eq!(rl.client_ip(&headerless), ip("10.0.0.9"));
+    }
+
+    #[test]
+    fn requests_the_buckets_cannot_hold_are_capped() {
+        let rl = limiter();
+        let ip: IpAddr = "10.0.0.3".parse().unwrap();
+        // Two orders fill the default tier, five the IP bucket
+        assert_eq!(rl.max_count(Some("alice"), Some(ip), Action::PlaceOrder), 2);
+        assert_eq!(rl.max_count(Some("maker"), Some(ip), Action::PlaceOrder), 5);
+        assert_eq!(rl.max_count(Some("maker"), None, Action::PlaceOrder), 50);
+
+        // Oversized charges take nothing and ask for a bounded wait
+        assert_eq!(rl.check(Some("alice"), Some(ip), Action::PlaceOrder, 3), Err(MAX_RETRY_AFTER));
+        assert!(rl.check(Some("alice"), Some(ip), Action::PlaceOrder, 2).is_ok());
+    }
+}
@@ -0,0 +1,77 @@
+use common_utils::{order_queue, EngineCommand};
+
+/// Markets with a running matching engine, from MARKETS (comma-separated, SOL_USDC
+/// by default). Each engine consumes only its own market's queue.
+pub struct Markets(Vec<String>);
+
+impl Markets {
+    pub fn from_env() -> Self {
+        Self::new(common_utils::markets_from_env())
+    }
+
+    pub fn new(mut markets: Vec<String>) -> Self {
+        if markets.is_empty() {
+            markets.push(common_utils::default_market());
+        }
+        markets.sort();
+        markets.dedup();
+        Self(markets)
+    }
+
+    /// Queues `cmd` goes to: its market's, or every market's for commands that
+    /// apply to all of a user's orders. A batch is applied as a unit by one engine,
+    /// so it must stay within one market.
+    pub fn queues_for(&self, cmd: &EngineCommand) -> Result<Vec<String>, String> {
+        let markets = cmd.markets();
+        if let Some(unknown) = markets.iter().find(|m| !self.0.iter().any(|known| known == *m)) {
+            return Err(format!("Unknown market '{}'", unknown));
+        }
+        match markets[..] {
+            [] => Ok(self.0.iter().map(|m| order_queue(m)).collect()),
+            [market] => Ok(vec![order_queue(market)]),
+            _ => Err("A batch must only contain orders for one market".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_utils::Order;
+    use rust_decimal::Decimal;
+
+    fn order(market: &str) -> Order {
+        Order {
+            order_id: 0,
+            request_id: None,
+            user_id: "alice".into(),
+            market: market.into(),
+            price: Decimal::ONE,
+            quantity: Decimal::ONE,
+            side: "BUY".into(),
+        }
+    }
+
+    #[test]
+    fn commands_go_to_their_markets_engine_and_user_wide_ones_to_every_engine() {
+        let markets = Markets::new(vec!["SOL_USDC".into(), "BTC_USDC".into()]);
+        let cancel = |market: Option<&str>| EngineCommand::MassCancel {
+            request_id: "r".into(),
+            user_id: "alice".into(),
+            market: market.map(Into::into),
+            side: None,
+        };
+
+        assert_eq!(markets.queues_for(&EngineCommand::PlaceOrder(order("BTC_USDC"))).unwrap(), ["ORDER_QUEUE:BTC_USDC"]);
+        assert_eq!(markets.queues_for(&cancel(Some("SOL_USDC"))).unwrap(), ["ORDER_QUEUE:SOL_USDC"]);
+        assert_eq!(markets.queues_for(&cancel(None)).unwrap(), ["ORDER_QUEUE:BTC_USDC", "ORDER_QUEUE:SOL_USDC"]);
+        let dms = EngineCommand::ArmDeadManSwitch { request_id: "r".into(), user_id: "alice".into(), timeout_ms: 0 };
+        assert_eq!(markets.queues_for(&dms).unwrap().len(), 2);
+
+        assert_eq!(markets.queues_for(&EngineCommand::PlaceOrder(order("ETH_USDC"))).unwrap_err(), "Unknown market 'ETH_USDC'");
+        let mixed = EngineCommand::PlaceBatch { request_id: "r".into(), orders: vec![order("SOL_USDC"), order("BTC_USDC")] };
+        assert!(markets.queues_for(&mixed).is_err());
+        let same = EngineCommand::PlaceBatch { request_id: "r".into(), orders: vec![order("SOL_USDC"), order("SOL_USDC")] };
+        assert_eq!(markets.queues_for(&same).unwrap(), ["ORDER_QUEUE:SOL_USDC"]);
+    }
+}
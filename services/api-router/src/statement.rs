@@ -0,0 +1,246 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpResponse, Responder};
+use deadpool_postgres::{Object, Pool};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db;
+use crate::units::fixed;
+
+// Both sides of a self-trade are listed, each side through its own index
+const STATEMENT_SQL: &str = "
+    SELECT 'fill' AS kind, timestamp, market, 'buy' AS side, price, quantity, NULL::BIGINT AS amount, trade_id, NULL::TEXT AS signature
+        FROM trades WHERE buyer_id = $1 AND timestamp >= $2 AND timestamp < $3
+    UNION ALL
+    SELECT 'fill', timestamp, market, 'sell', price, quantity, NULL, trade_id, NULL
+        FROM trades WHERE seller_id = $1 AND timestamp >= $2 AND timestamp < $3
+    UNION ALL
+    SELECT 'deposit', block_time * 1000, NULL, NULL, NULL, NULL, (data->>'amount')::BIGINT, NULL, signature
+        FROM program_events WHERE event_type = 'collateral_deposited' AND data->>'owner' = $1
+            AND block_time * 1000 >= $2 AND block_time * 1000 < $3
+    ORDER BY timestamp, trade_id";
+
+const CSV_HEADER: &str = "kind,timestamp,market,side,price,quantity,amount,trade_id,signature\n";
+
+/// One line of a wallet's statement: a fill from `trades` or a deposit indexed
+/// from the program's events. Fees, funding payments and withdrawals aren't
+/// recorded anywhere yet, so they don't appear.
+#[derive(Serialize, ToSchema)]
+struct StatementEntry {
+    /// fill or deposit
+    kind: String,
+    /// Unix milliseconds: match time for fills, block time for deposits
+    timestamp: i64,
+    market: Option<String>,
+    /// buy or sell, for fills
+    side: Option<String>,
+    price: Option<Decimal>,
+    quantity: Option<Decimal>,
+    /// Collateral deposited, for deposits
+    amount: Option<Decimal>,
+    trade_id: Option<i64>,
+    /// Deposit transaction signature
+    signature: Option<String>,
+}
+
+impl StatementEntry {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            kind: row.get(0),
+            timestamp: row.get(1),
+            market: row.get(2),
+            side: row.get(3),
+            price: row.get::<_, Option<i64>>(4).map(fixed),
+            quantity: row.get::<_, Option<i64>>(5).map(fixed),
+            amount: row.get::<_, Option<i64>>(6).map(fixed),
+            trade_id: row.get(7),
+            signature: row.get(8),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        fn field(value: Option<impl ToString>) -> String {
+            let value = value.map(|v| v.to_string()).unwrap_or_default();
+            if value.contains([',', '"', '\n']) { format!("\"{}\"", value.replace('"', "\"\"")) } else { value }
+        }
+        format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            self.kind, self.timestamp, field(self.market.as_ref()), field(self.side.as_ref()), field(self.price),
+            field(self.quantity), field(self.amount), field(self.trade_id), field(self.signature.as_ref()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// `wallet`'s fills and deposits in `[from_ms, to_ms)`, oldest first, rendered as
+/// they're read from Postgres: CSV with a header line, or one JSON array. The stream
+/// holds `db` until its last chunk, so the connection goes back to the pool only
+/// once the rows have been read off it.
+async fn stream(
+    db: Object,
+    wallet: &str,
+    from_ms: i64,
+    to_ms: i64,
+    format: Format,
+) -> Result<impl Stream<Item = Result<Bytes, tokio_postgres::Error>> + use<>, tokio_postgres::Error> {
+    let params: [&(dyn ToSql + Sync); 3] = [&wallet, &from_ms, &to_ms];
+    let rows = db.query_raw(STATEMENT_SQL, params).await?;
+    let (open, close) = match format {
+        Format::Csv => (CSV_HEADER, ""),
+        Format::Json => ("[", "]"),
+    };
+    let entries = rows.enumerate().map(move |(i, row)| {
+        row.map(|row| {
+            let entry = StatementEntry::from_row(&row);
+            let text = match format {
+                Format::Csv => entry.to_csv(),
+                Format::Json if i == 0 => serde_json::to_string(&entry).unwrap(),
+                Format::Json => format!(",{}", serde_json::to_string(&entry).unwrap()),
+            };
+            Bytes::from(text)
+        })
+    });
+    Ok(stream::once(async move { Ok(Bytes::from_static(open.as_bytes())) })
+        .chain(entries)
+        .chain(stream::once(async move {
+            drop(db);
+            Ok(Bytes::from_static(close.as_bytes()))
+        })))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportQuery {
+    /// Unix milliseconds, inclusive (default: the beginning)
+    from: Option<i64>,
+    /// Unix milliseconds, exclusive (default: now)
+    to: Option<i64>,
+    /// csv (default) or json
+    format: Option<String>,
+}
+
+/// A wallet's statement for accounting: every fill and collateral deposit in the
+/// range, with prices, quantities and amounts as exact decimals. Trades archived
+/// out of the database by retention aren't included.
+#[utoipa::path(
+    params(("pubkey" = String, Path, description = "The wallet's public key, base58"), ExportQuery),
+    responses(
+        (status = 200, description = "The statement, oldest entry first", content(
+            (String = "text/csv"),
+            (Vec<StatementEntry> = "application/json"),
+        )),
+        (status = 400, description = "Unknown format", body = String),
+        (status = 429, description = "Rate limit exceeded", body = crate::rate_limit::RateLimitedResponse),
+        (status = 503, description = "Database unavailable", body = String),
+    )
+)]
+#[get("/account/{pubkey}/export")]
+async fn export_statement(pool: web::Data<Pool>, pubkey: web::Path<String>, query: web::Query<ExportQuery>) -> impl Responder {
+    let Some(format) = Format::parse(query.format.as_deref().unwrap_or("csv")) else {
+        return HttpResponse::BadRequest().body("format must be csv or json");
+    };
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().timestamp_millis() + 1);
+
+    let db = match db::client(&pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+    let body = match stream(db, &pubkey, from, to, format).await {
+        Ok(body) => body,
+        Err(e) => return crate::internal_error("Reading statement", e),
+    };
+    let content_type = match format {
+        Format::Csv => "text/csv; charset=utf-8",
+        Format::Json => "application/json",
+    };
+    let filename = format!("statement-{}-{}-{}.{}", pubkey, from, to, format.extension());
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition { disposition: DispositionType::Attachment, parameters: vec![DispositionParam::Filename(filename)] })
+        .streaming(body)
+}
+
+const EXPORT_USAGE: &str = "Usage: api-router export <wallet> [from_ms] [to_ms] [csv|json]";
+
+/// `api-router export <wallet> [from_ms] [to_ms] [csv|json]` writes a statement to
+/// stdout. Bad arguments are an error, so the process exits non-zero.
+pub async fn run_export_cli(pool: &Pool, args: &[String]) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::Write;
+
+    let (Some(wallet), Some(format)) = (args.first(), Format::parse(args.get(3).map_or("csv", String::as_str))) else {
+        anyhow::bail!(EXPORT_USAGE);
+    };
+    if args.len() > 4 {
+        anyhow::bail!(EXPORT_USAGE);
+    }
+    let from: i64 = args.get(1).map(|v| v.parse()).transpose().context("from_ms must be Unix milliseconds")?.unwrap_or(0);
+    let to: i64 = args
+        .get(2)
+        .map(|v| v.parse())
+        .transpose()
+        .context("to_ms must be Unix milliseconds")?
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_millis() + 1);
+
+    let db = pool.get().await?;
+    let mut out = std::io::stdout().lock();
+    let mut body = std::pin::pin!(stream(db, wallet, from, to, format).await?);
+    while let Some(chunk) = body.try_next().await? {
+        out.write_all(&chunk)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_lines_keep_the_fixed_point_scale_and_leave_missing_values_empty() {
+        let fill = StatementEntry {
+            kind: "fill".into(),
+            timestamp: 1_700_000_000_000,
+            market: Some("SOL_USDC".into()),
+            side: Some("sell".into()),
+            price: Some(fixed(150_250_000)),
+            quantity: Some(fixed(1)),
+            amount: None,
+            trade_id: Some(42),
+            signature: None,
+        };
+        assert_eq!(fill.to_csv(), "fill,1700000000000,SOL_USDC,sell,150.250000,0.000001,,42,\n");
+        assert_eq!(serde_json::to_value(&fill).unwrap()["price"], "150.250000");
+
+        let odd_market = StatementEntry { market: Some("A,\"B\"".into()), ..fill };
+        assert!(odd_market.to_csv().starts_with("fill,1700000000000,\"A,\"\"B\"\"\",sell,"));
+    }
+}
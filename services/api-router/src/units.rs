@@ -0,0 +1,7 @@
+use rust_decimal::Decimal;
+
+/// Prices, quantities and amounts are stored as 6-decimal fixed point. The scale
+/// is kept, so values serialize exactly as stored.
+pub fn fixed(value: i64) -> Decimal {
+    Decimal::new(value, 6)
+}
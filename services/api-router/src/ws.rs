@@ -0,0 +1,190 @@
+use actix_web::{get, rt, web, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use common_utils::{EngineCommand, OrderRequest};
+use fred::prelude::*;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use utoipa::IntoParams;
+
+use crate::acks::AckRegistry;
+use crate::rate_limit::{Action, RateLimiter};
+use crate::routing::Markets;
+use crate::{await_ack, enqueue, parse_order, submit, AckQuery, SubmitError};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SessionQuery {
+    // Cancel all of the user's orders when this session ends, however it ends
+    #[serde(default)]
+    cancel_on_disconnect: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    Place(OrderRequest),
+    CancelAll { market: Option<String>, side: Option<String> },
+}
+
+struct SessionState {
+    user_id: String,
+    ip: Option<IpAddr>,
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    limiter: web::Data<RateLimiter>,
+}
+
+/// Open a WebSocket trading session. Clients send `{"op": "place", ...OrderRequest}`
+/// or `{"op": "cancel_all", "market": .., "side": ..}` and receive the engine's ack.
+/// The session trades as the user the auth proxy put in `X-User-Id`.
+#[utoipa::path(
+    params(("X-User-Id" = String, Header, description = "User authenticated by the auth proxy"), SessionQuery),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 401, description = "No X-User-Id header")
+    )
+)]
+#[get("/ws")]
+pub async fn connect(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<SessionQuery>,
+    redis: web::Data<RedisClient>,
+    acks: web::Data<AckRegistry>,
+    markets: web::Data<Markets>,
+    limiter: web::Data<RateLimiter>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(user_id) = req.headers().get("X-User-Id").and_then(|v| v.to_str().ok()).map(String::from) else {
+        return Ok(HttpResponse::Unauthorized().body("Missing X-User-Id"));
+    };
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let query = query.into_inner();
+    let state = SessionState {
+        user_id,
+        ip: limiter.client_ip(&req),
+        redis,
+        acks,
+        markets,
+        limiter,
+    };
+    rt::spawn(run(session, stream, state, query.cancel_on_disconnect));
+    Ok(response)
+}
+
+async fn run(mut session: Session, mut stream: MessageStream, state: SessionState, cancel_on_disconnect: bool) {
+    println!("🔌 WebSocket session opened for {}", state.user_id);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            msg = stream.recv() => {
+                // Stream ended or errored: the client is gone
+                let Some(Ok(msg)) = msg else { break };
+                last_seen = Instant::now();
+                match msg {
+                    Message::Text(text) => handle_text(&session, &state, &text).await,
+                    Message::Ping(bytes) => {
+                        if session.pong(&bytes).await.is_err() { break; }
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT || session.ping(b"").await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    println!("🔌 WebSocket session closed for {}", state.user_id);
+    // Commands are queued on this loop, so everything the session sent is queued by
+    // now and the cancel reaches each engine after the session's last order
+    if cancel_on_disconnect {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let cmd = EngineCommand::MassCancel {
+            request_id: request_id.clone(),
+            user_id: state.user_id.clone(),
+            market: None,
+            side: None,
+        };
+        let query = AckQuery { wait: false, timeout_ms: None };
+        if let Err(e) = submit(&state.redis, &state.acks, &state.markets, &query, &request_id, &cmd).await {
+            eprintln!("❌ Cancel-on-disconnect failed for {}: {}", state.user_id, e);
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+async fn handle_text(session: &Session, state: &SessionState, text: &str) {
+    let mut session = session.clone();
+    let msg = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(msg) => msg,
+        Err(e) => {
+            let _ = session.text(serde_json::json!({"error": e.to_string()}).to_string()).await;
+            return;
+        }
+    };
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let (action, cmd) = match msg {
+        ClientMessage::Place(mut req) => {
+            // A session may only trade for the user it was opened for
+            req.user_id = state.user_id.clone();
+            match parse_order(&req, &request_id) {
+                Some(order) => (Action::PlaceOrder, EngineCommand::PlaceOrder(order)),
+                None => {
+                    let _ = session.text(serde_json::json!({"error": "Invalid price or quantity format"}).to_string()).await;
+                    return;
+                }
+            }
+        }
+        ClientMessage::CancelAll { market, side } => (
+            Action::CancelOrder,
+            EngineCommand::MassCancel { request_id: request_id.clone(), user_id: state.user_id.clone(), market, side },
+        ),
+    };
+
+    if let Err(retry_after) = state.limiter.check(Some(&state.user_id), state.ip, action, 1) {
+        let reply = serde_json::json!({"error": "rate limit exceeded", "retry_after_ms": retry_after.as_millis() as u64});
+        let _ = session.text(reply.to_string()).await;
+        return;
+    }
+
+    // Queued on the read loop so the engines see the session's commands in the order
+    // it sent them; only the wait for the ack runs off it, to keep heartbeats going
+    let ack_rx = match enqueue(&state.redis, &state.acks, &state.markets, true, &request_id, &cmd).await {
+        Ok(ack_rx) => ack_rx,
+        Err(e) => {
+            let _ = session.text(error_reply(e)).await;
+            return;
+        }
+    };
+    let acks = state.acks.clone();
+    rt::spawn(async move {
+        let reply = match await_ack(&acks, &request_id, ack_rx, None).await {
+            Some(ack) => serde_json::to_string(&ack).unwrap(),
+            None => serde_json::json!({"status": "queued", "request_id": request_id}).to_string(),
+        };
+        let _ = session.text(reply).await;
+    });
+}
+
+/// The client sees why a command was rejected, but not Redis errors, which can name hosts.
+fn error_reply(e: SubmitError) -> String {
+    let message = match e {
+        SubmitError::Rejected(reason) => reason,
+        SubmitError::Redis(e) => {
+            eprintln!("❌ Queueing command: {}", e);
+            "Internal server error".into()
+        }
+    };
+    serde_json::json!({"error": message}).to_string()
+}
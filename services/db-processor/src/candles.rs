@@ -0,0 +1,76 @@
+use common_utils::CANDLE_INTERVALS;
+use tokio_postgres::Client;
+
+use crate::partitions;
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Upserts the candles of every interval from `source`, a relation with
+/// `market, price, quantity, timestamp, seq` columns. Rows merge into existing
+/// candles by sequence number: a trade only replaces the open if it came earlier
+/// than the current open, and the close if it came later, so late or out-of-order
+/// batches end up with the same candles as an in-order replay.
+pub fn upsert_sql(source: &str) -> String {
+    let intervals: Vec<String> = CANDLE_INTERVALS.iter().map(|(name, ms)| format!("('{}', {}::BIGINT)", name, ms)).collect();
+    format!(
+        "INSERT INTO candles AS c (market, interval, open_time, open, high, low, close, volume, trade_count, open_sequence, close_sequence)
+         SELECT t.market, i.name, t.timestamp - t.timestamp % i.ms AS bucket,
+                (array_agg(t.price ORDER BY t.seq))[1], max(t.price), min(t.price), (array_agg(t.price ORDER BY t.seq DESC))[1],
+                sum(t.quantity)::BIGINT, count(*)::INT, min(t.seq), max(t.seq)
+         FROM {source} t CROSS JOIN (VALUES {intervals}) AS i(name, ms)
+         GROUP BY t.market, i.name, bucket
+         ON CONFLICT (market, interval, open_time) DO UPDATE SET
+             open = CASE WHEN EXCLUDED.open_sequence < c.open_sequence THEN EXCLUDED.open ELSE c.open END,
+             open_sequence = LEAST(c.open_sequence, EXCLUDED.open_sequence),
+             close = CASE WHEN EXCLUDED.close_sequence > c.close_sequence THEN EXCLUDED.close ELSE c.close END,
+             close_sequence = GREATEST(c.close_sequence, EXCLUDED.close_sequence),
+             high = GREATEST(c.high, EXCLUDED.high),
+             low = LEAST(c.low, EXCLUDED.low),
+             volume = c.volume + EXCLUDED.volume,
+             trade_count = c.trade_count + EXCLUDED.trade_count",
+        source = source,
+        intervals = intervals.join(", "),
+    )
+}
+
+/// Sequence used to order trades within a candle. Trades from before the engine
+/// stamped events fall back to their trade ID, which was assigned in match order.
+pub const TRADE_SEQUENCE: &str = "COALESCE(sequence, trade_id)";
+
+/// Rebuilds `market`'s candles from `trades` for whole UTC days covering
+/// `[from_ms, to_ms)`, replacing whatever is stored there. Returns the number of
+/// candle rows written.
+pub async fn backfill(client: &mut Client, market: &str, from_ms: i64, to_ms: i64) -> Result<u64, tokio_postgres::Error> {
+    // Every interval divides a day, so day-aligned bounds never split a candle
+    let from = from_ms - from_ms.rem_euclid(DAY_MS);
+    let to = to_ms + (DAY_MS - to_ms.rem_euclid(DAY_MS)) % DAY_MS;
+
+    let tx = client.transaction().await?;
+    // Archived trades are gone, so candles before the retention horizon are kept as they are
+    let from = from.max(partitions::retention_horizon(&tx).await?);
+    // Holds off the live writer so none of its trades are counted twice or lost
+    // between the delete and the rebuild
+    tx.batch_execute("LOCK TABLE candles IN SHARE ROW EXCLUSIVE MODE").await?;
+    tx.execute("DELETE FROM candles WHERE market = $1 AND open_time >= $2 AND open_time < $3", &[&market, &from, &to]).await?;
+    let source = format!(
+        "(SELECT market, price, quantity, timestamp, {} AS seq FROM trades WHERE market = $1 AND timestamp >= $2 AND timestamp < $3)",
+        TRADE_SEQUENCE
+    );
+    let written = tx.execute(&upsert_sql(&source), &[&market, &from, &to]).await?;
+    tx.commit().await?;
+    Ok(written)
+}
+
+/// `db-processor candles backfill <market> [from_ms] [to_ms]` rebuilds a market's
+/// candles from stored trades (default: all of them).
+pub async fn run_candles_cli(client: &mut Client, args: &[String]) -> anyhow::Result<()> {
+    let (Some("backfill"), Some(market)) = (args.first().map(String::as_str), args.get(1)) else {
+        eprintln!("Usage: db-processor candles backfill <market> [from_ms] [to_ms]");
+        return Ok(());
+    };
+    let from_ms: i64 = args.get(2).map(|v| v.parse()).transpose()?.unwrap_or(0);
+    let to_ms: i64 = args.get(3).map(|v| v.parse()).transpose()?.unwrap_or_else(|| chrono::Utc::now().timestamp_millis() + 1);
+    let written = backfill(client, market, from_ms, to_ms).await?;
+    println!("🕯️ Rebuilt {} candle(s) for {}", written, market);
+    Ok(())
+}
@@ -0,0 +1,746 @@
+use common_utils::MatchResult;
+use fred::prelude::*;
+use fred::types::Expiration;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::pin::pin;
+use std::time::{Duration, Instant};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::Client;
+
+use crate::candles;
+use crate::ledger::{self, Fill};
+
+/// A Redis list the engine pushes to, drained in batches.
+///
+/// Each db-processor instance claims messages into its own processing list (see
+/// `Consumer`) and leaves them there until their batch has committed to Postgres,
+/// so a crash or failed write leaves the batch to be written again; writers skip
+/// rows that already exist.
+#[derive(Debug, Clone, Copy)]
+pub struct Queue {
+    pub name: &'static str,
+}
+
+/// Matched trades
+pub const DB_QUEUE: Queue = Queue { name: "DB_QUEUE" };
+/// Order lifecycle events
+pub const ORDER_EVENT_QUEUE: Queue = Queue { name: "ORDER_EVENT_QUEUE" };
+
+impl Queue {
+    /// Messages Postgres kept rejecting, as `DeadLetter`s, newest first
+    pub fn dead_letters(self) -> String {
+        format!("{}_DLQ", self.name)
+    }
+
+    // The processing list every instance shared before each had its own
+    fn legacy_processing(self) -> String {
+        format!("{}_PROCESSING", self.name)
+    }
+}
+
+/// One instance's claim on a queue: its processing list, and how many times
+/// Postgres rejected the batch in it.
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    pub queue: Queue,
+    processing: String,
+    attempts: String,
+}
+
+impl Consumer {
+    pub fn new(queue: Queue, instance: &Instance) -> Self {
+        Self {
+            queue,
+            processing: format!("{}_PROCESSING:{}", queue.name, instance.id),
+            attempts: format!("{}_ATTEMPTS:{}", queue.name, instance.id),
+        }
+    }
+}
+
+/// A running db-processor. Its lease in Redis tells other instances that its
+/// processing lists are in use; once the lease runs out they are put back on
+/// their queues by the next instance to start.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub id: String,
+    pub lease: Duration,
+}
+
+// Every instance that has claimed messages, live or not
+const INSTANCES_KEY: &str = "DB_PROCESSORS";
+
+impl Instance {
+    /// Reads DB_PROCESSOR_ID (a random ID) and DB_PROCESSOR_LEASE_SECS (30). A fixed
+    /// ID lets a restarted instance pick up its own batch without waiting for the lease.
+    pub fn from_env() -> Self {
+        let id = std::env::var("DB_PROCESSOR_ID").unwrap_or_else(|_| {
+            let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+            format!("{}-{:x}", std::process::id(), nanos)
+        });
+        let lease_secs = std::env::var("DB_PROCESSOR_LEASE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30).max(3);
+        Self { id, lease: Duration::from_secs(lease_secs) }
+    }
+
+    fn lease_key(&self) -> String {
+        lease_key(&self.id)
+    }
+}
+
+fn lease_key(id: &str) -> String {
+    format!("DB_PROCESSOR_LEASE:{}", id)
+}
+
+/// Takes out the instance's lease and records it among the instances.
+pub async fn register(redis: &RedisClient, instance: &Instance) -> RedisResult<()> {
+    redis.sadd::<(), _, _>(INSTANCES_KEY, instance.id.as_str()).await?;
+    renew(redis, instance).await
+}
+
+async fn renew(redis: &RedisClient, instance: &Instance) -> RedisResult<()> {
+    let expiry = Expiration::EX(instance.lease.as_secs() as i64);
+    redis.set::<(), _, _>(instance.lease_key(), "1", Some(expiry), None, false).await
+}
+
+/// Renews the lease three times per lease period until the process exits, and
+/// requeues the batches of instances that stopped meanwhile.
+pub async fn keep_alive(redis: RedisClient, instance: Instance, queues: &'static [Queue]) {
+    loop {
+        tokio::time::sleep(instance.lease / 3).await;
+        if let Err(e) = renew(&redis, &instance).await {
+            eprintln!("❌ Failed to renew the db-processor lease: {}", e);
+        }
+        match requeue_expired(&redis, &instance, queues).await {
+            Ok(0) => {}
+            Ok(requeued) => println!("♻️ Requeued {} message(s) claimed by a stopped db-processor", requeued),
+            Err(e) => eprintln!("❌ Failed to requeue messages of stopped db-processors: {}", e),
+        }
+    }
+}
+
+// Moves every message of the processing list KEYS[1] back to the consuming end of
+// KEYS[2], the oldest claim first in line, and drops its attempt count KEYS[3]
+const REQUEUE_SCRIPT: &str = r#"
+local moved = 0
+while redis.call('LMOVE', KEYS[1], KEYS[2], 'LEFT', 'RIGHT') do
+    moved = moved + 1
+end
+redis.call('DEL', KEYS[3])
+return moved
+"#;
+
+/// Puts the batches of instances whose lease ran out back on their queues, as
+/// well as any left in the processing list shared before lists were per instance.
+/// Run on startup, after `register`; `keep_alive` keeps doing the former. Returns
+/// how many messages were requeued.
+pub async fn recover_stale(redis: &RedisClient, instance: &Instance, queues: &[Queue]) -> RedisResult<u64> {
+    let mut requeued = requeue_expired(redis, instance, queues).await?;
+    for queue in queues {
+        let legacy = queue.legacy_processing();
+        requeued += requeue(redis, queue, &legacy, &format!("{}_ATTEMPTS", queue.name)).await?;
+    }
+    Ok(requeued)
+}
+
+async fn requeue_expired(redis: &RedisClient, instance: &Instance, queues: &[Queue]) -> RedisResult<u64> {
+    let ids: Vec<String> = redis.smembers(INSTANCES_KEY).await?;
+    let mut requeued = 0;
+    for id in ids.iter().filter(|id| **id != instance.id) {
+        if redis.exists::<u64, _>(lease_key(id)).await? > 0 {
+            continue;
+        }
+        let stale = Instance { id: id.clone(), lease: instance.lease };
+        for queue in queues {
+            let consumer = Consumer::new(*queue, &stale);
+            requeued += requeue(redis, queue, &consumer.processing, &consumer.attempts).await?;
+        }
+        redis.srem::<(), _, _>(INSTANCES_KEY, id.as_str()).await?;
+    }
+    Ok(requeued)
+}
+
+async fn requeue(redis: &RedisClient, queue: &Queue, processing: &str, attempts: &str) -> RedisResult<u64> {
+    redis.eval(REQUEUE_SCRIPT, vec![processing, queue.name, attempts], Vec::<String>::new()).await
+}
+
+// Moves up to ARGV[1] messages from the tail of the queue to the processing list atomically
+const CLAIM_SCRIPT: &str = r#"
+local claimed = {}
+for i = 1, tonumber(ARGV[1]) do
+    local msg = redis.call('LMOVE', KEYS[1], KEYS[2], 'RIGHT', 'LEFT')
+    if not msg then break end
+    claimed[#claimed + 1] = msg
+end
+return claimed
+"#;
+
+/// Columns of `trades`, in table order
+pub const COLUMNS: &str = "trade_id, market, buyer_id, seller_id, price, quantity, timestamp, sequence, timestamp_ns";
+const COLUMN_TYPES: [Type; 9] = [
+    Type::INT8, Type::VARCHAR, Type::VARCHAR, Type::VARCHAR, Type::INT8, Type::INT8, Type::INT8, Type::INT8, Type::INT8,
+];
+// Idle poll interval while the queue is empty
+const IDLE_POLL: Duration = Duration::from_millis(100);
+// While filling a batch, how long to wait before checking the queue again
+const FILL_POLL: Duration = Duration::from_millis(5);
+/// Pause after a Redis or Postgres error before trying again
+pub const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How much to buffer before writing: whichever of `max_rows` or `max_wait`
+/// (measured from the first message of the batch) comes first. A batch Postgres
+/// rejects `max_attempts` times is written message by message, and the messages it
+/// still rejects are dead-lettered.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    pub max_rows: usize,
+    pub max_wait: Duration,
+    pub max_attempts: u64,
+}
+
+impl BatchConfig {
+    /// Reads DB_BATCH_SIZE (1000), DB_BATCH_MAX_WAIT_MS (50) and DB_MAX_ATTEMPTS (5).
+    pub fn from_env() -> Self {
+        let max_rows = std::env::var("DB_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(1000).max(1);
+        let max_wait_ms = std::env::var("DB_BATCH_MAX_WAIT_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(50);
+        let max_attempts = std::env::var("DB_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()).unwrap_or(5).max(1);
+        Self { max_rows, max_wait: Duration::from_millis(max_wait_ms), max_attempts }
+    }
+}
+
+/// One `trades` row. Price and quantity are 6-decimal fixed point; `timestamp`
+/// is the engine's match time in milliseconds.
+#[derive(Debug, Clone)]
+pub struct TradeRow {
+    pub trade_id: i64,
+    pub market: String,
+    pub buyer_id: String,
+    pub seller_id: String,
+    pub price: i64,
+    pub quantity: i64,
+    pub timestamp: i64,
+    pub sequence: Option<i64>,
+    pub timestamp_ns: Option<i64>,
+}
+
+impl TradeRow {
+    /// `received_ms` is only used for trades matched before the engine stamped events.
+    pub fn from_match(m: &MatchResult, received_ms: i64) -> Self {
+        // Convert Decimal to i64 (multiply by 1,000,000 for 6 decimal places)
+        let scale = rust_decimal::Decimal::from(1_000_000);
+        let stamped = m.sequence > 0;
+        Self {
+            trade_id: m.trade_id as i64,
+            market: m.market.clone(),
+            buyer_id: m.buyer_id.clone(),
+            seller_id: m.seller_id.clone(),
+            price: (m.price * scale).to_i64().unwrap_or(0),
+            quantity: (m.quantity * scale).to_i64().unwrap_or(0),
+            timestamp: if stamped { (m.timestamp_ns / 1_000_000) as i64 } else { received_ms },
+            sequence: stamped.then_some(m.sequence as i64),
+            timestamp_ns: stamped.then_some(m.timestamp_ns as i64),
+        }
+    }
+}
+
+/// The next batch to write: a batch that didn't commit (crash, failed write) is
+/// retried before new messages are claimed. Returns `None` after a Redis error.
+pub async fn claim(redis: &RedisClient, consumer: &Consumer, config: BatchConfig) -> Option<Vec<String>> {
+    let claimed = match unacknowledged(redis, consumer).await {
+        Ok(pending) if !pending.is_empty() => Ok(pending),
+        Ok(_) => next_batch(redis, consumer, config).await,
+        Err(e) => Err(e),
+    };
+    match claimed {
+        Ok(batch) => Some(batch),
+        Err(e) => {
+            eprintln!("❌ Redis error on {}: {}", consumer.queue.name, e);
+            tokio::time::sleep(RETRY_DELAY).await;
+            None
+        }
+    }
+}
+
+/// Messages left in the processing list by a batch that never committed, in the
+/// order they were claimed.
+pub async fn unacknowledged(redis: &RedisClient, consumer: &Consumer) -> RedisResult<Vec<String>> {
+    // Claims are pushed to the head, so the list holds the batch newest first
+    let mut pending: Vec<String> = redis.lrange(consumer.processing.as_str(), 0, -1).await?;
+    pending.reverse();
+    Ok(pending)
+}
+
+/// Waits for the next message, then keeps claiming until the batch is full or
+/// `max_wait` has passed since the first one arrived.
+pub async fn next_batch(redis: &RedisClient, consumer: &Consumer, config: BatchConfig) -> RedisResult<Vec<String>> {
+    let mut batch = Vec::new();
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let want = config.max_rows - batch.len();
+        let keys = vec![consumer.queue.name, consumer.processing.as_str()];
+        let claimed: Vec<String> = redis.eval(CLAIM_SCRIPT, keys, vec![want as i64]).await?;
+        if !claimed.is_empty() && deadline.is_none() {
+            deadline = Some(Instant::now() + config.max_wait);
+        }
+        batch.extend(claimed);
+
+        let Some(deadline) = deadline else {
+            tokio::time::sleep(IDLE_POLL).await;
+            continue;
+        };
+        let now = Instant::now();
+        if batch.len() >= config.max_rows || now >= deadline {
+            return Ok(batch);
+        }
+        tokio::time::sleep(FILL_POLL.min(deadline - now)).await;
+    }
+}
+
+/// Drops the processing list and its attempt count once its batch is committed.
+pub async fn acknowledge(redis: &RedisClient, consumer: &Consumer) -> RedisResult<()> {
+    redis.del::<(), _>(vec![consumer.processing.as_str(), consumer.attempts.as_str()]).await
+}
+
+/// A message taken off a queue without being written, with why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub payload: String,
+    pub reason: String,
+    pub attempts: u64,
+    pub failed_at_ms: i64,
+}
+
+/// Records `payload` in the queue's dead-letter list. The caller still has to
+/// acknowledge the batch it came from.
+pub async fn dead_letter(redis: &RedisClient, queue: Queue, payload: &str, reason: String, attempts: u64) -> RedisResult<()> {
+    eprintln!("☠️ Dead-lettered a message from {}: {}", queue.name, reason);
+    let entry = DeadLetter { payload: payload.to_string(), reason, attempts, failed_at_ms: chrono::Utc::now().timestamp_millis() };
+    redis.lpush::<(), _, _>(queue.dead_letters(), serde_json::to_string(&entry).expect("DeadLetter serializes")).await
+}
+
+/// Handles a failed write of the consumer's claimed batch, which stays claimed to be
+/// retried. Errors that aren't Postgres rejecting the data (connection trouble) are
+/// retried indefinitely. Once Postgres has rejected the batch `max_attempts` times
+/// this returns the attempt count instead: the caller then writes each message on
+/// its own and passes the result to `settle_alone`, so a single bad message can't
+/// hold up the queue forever.
+pub async fn write_failed(redis: &RedisClient, consumer: &Consumer, config: BatchConfig, error: &tokio_postgres::Error, count: usize) -> Option<u64> {
+    let name = consumer.queue.name;
+    if error.as_db_error().is_none() {
+        eprintln!("❌ Database error, retrying {} message(s) from {}: {}", count, name, error);
+        tokio::time::sleep(RETRY_DELAY).await;
+        return None;
+    }
+    let attempts = match redis.incr::<u64, _>(consumer.attempts.as_str()).await {
+        Ok(attempts) => attempts,
+        Err(e) => {
+            eprintln!("❌ Redis error on {}: {}", name, e);
+            tokio::time::sleep(RETRY_DELAY).await;
+            return None;
+        }
+    };
+    if attempts < config.max_attempts {
+        eprintln!("❌ Postgres rejected {} message(s) from {} (attempt {}/{}): {}", count, name, attempts, config.max_attempts, error);
+        tokio::time::sleep(RETRY_DELAY).await;
+        return None;
+    }
+    eprintln!("⚠️ Postgres rejected a batch from {} {} times; writing it one message at a time", name, attempts);
+    Some(attempts)
+}
+
+/// Settles the write of one message from a batch `write_failed` split up,
+/// dead-lettering it if Postgres rejected it. Returns false if the rest of the
+/// batch must wait for a retry; messages already written are skipped as
+/// duplicates then. Acknowledge the batch once every message is settled.
+pub async fn settle_alone(redis: &RedisClient, consumer: &Consumer, payload: &str, result: Result<(), tokio_postgres::Error>, attempts: u64) -> bool {
+    let name = consumer.queue.name;
+    match result {
+        Ok(()) => true,
+        Err(e) if e.as_db_error().is_some() => match dead_letter(redis, consumer.queue, payload, e.as_db_error().unwrap().to_string(), attempts).await {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("❌ Redis error on {}: {}", name, e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                false
+            }
+        },
+        Err(e) => {
+            eprintln!("❌ Database error, retrying the rest of a batch from {}: {}", name, e);
+            tokio::time::sleep(RETRY_DELAY).await;
+            false
+        }
+    }
+}
+
+/// Redis channel that gets `{"trade_id", "detected_at"}` for every quarantined trade
+pub const TRADE_CONFLICTS_CHANNEL: &str = "TRADE_CONFLICTS";
+/// Redis counter of trades quarantined since the exchange started, for dashboards
+pub const TRADE_CONFLICTS_TOTAL: &str = "TRADE_CONFLICTS_TOTAL";
+
+/// What one `TradeWriter::write` did.
+#[derive(Debug)]
+pub struct Written {
+    /// Rows that were new
+    pub inserted: u64,
+    /// IDs of rows newly quarantined in `trade_conflicts`
+    pub conflicts: Vec<i64>,
+    /// When the conflicts were detected, Unix milliseconds
+    pub detected_at: i64,
+}
+
+/// Raises an alert for each quarantined trade: a message on TRADE_CONFLICTS_CHANNEL
+/// and a bump of TRADE_CONFLICTS_TOTAL. Alerts are best effort; the rows are
+/// already in `trade_conflicts`.
+pub async fn alert_conflicts(redis: &RedisClient, written: &Written) {
+    if written.conflicts.is_empty() {
+        return;
+    }
+    for trade_id in &written.conflicts {
+        eprintln!("🚨 Trade #{} conflicts with another trade with the same ID; quarantined in trade_conflicts", trade_id);
+        let alert = serde_json::json!({"trade_id": trade_id, "detected_at": written.detected_at});
+        if let Err(e) = redis.publish::<(), _, _>(TRADE_CONFLICTS_CHANNEL, alert.to_string()).await {
+            eprintln!("❌ Failed to publish trade conflict alert: {}", e);
+        }
+    }
+    if let Err(e) = redis.incr_by::<(), _>(TRADE_CONFLICTS_TOTAL, written.conflicts.len() as i64).await {
+        eprintln!("❌ Failed to count trade conflicts: {}", e);
+    }
+}
+
+/// Writes rows to a table shaped like `trades` with binary COPY. COPY can't skip
+/// conflicts, so rows land in a session-local staging table first and are moved
+/// over with `ON CONFLICT DO NOTHING`, all in one transaction. Rows that reuse a
+/// stored trade's ID with different content go to `trade_conflicts` instead.
+pub struct TradeWriter {
+    table: String,
+    staging_ready: bool,
+    update_candles: bool,
+    update_positions: bool,
+}
+
+impl TradeWriter {
+    pub fn new(table: &str) -> Self {
+        Self { table: table.to_string(), staging_ready: false, update_candles: false, update_positions: false }
+    }
+
+    /// Also folds each batch's new trades into `candles` in the same transaction,
+    /// so a redelivered batch can't count a trade twice.
+    pub fn with_candles(mut self) -> Self {
+        self.update_candles = true;
+        self
+    }
+
+    /// Also folds each batch's new trades into the position ledger, in the same transaction.
+    pub fn with_positions(mut self) -> Self {
+        self.update_positions = true;
+        self
+    }
+
+    pub async fn write(&mut self, client: &mut Client, rows: &[TradeRow]) -> Result<Written, tokio_postgres::Error> {
+        let staging = format!("{}_staging", self.table);
+        if !self.staging_ready {
+            client.batch_execute(&format!(
+                "CREATE TEMP TABLE IF NOT EXISTS {} (LIKE {} INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+                staging, self.table
+            )).await?;
+            self.staging_ready = true;
+        }
+
+        let tx = client.transaction().await?;
+        let sink = tx.copy_in(&format!("COPY {} ({}) FROM STDIN BINARY", staging, COLUMNS)).await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, &COLUMN_TYPES));
+        for row in rows {
+            let values: [&(dyn ToSql + Sync); 9] = [
+                &row.trade_id, &row.market, &row.buyer_id, &row.seller_id, &row.price, &row.quantity, &row.timestamp,
+                &row.sequence, &row.timestamp_ns,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await?;
+
+        // Trades from before the engine stamped events are timed when they're received,
+        // which differs on every delivery, so a redelivery takes the stored trade's time
+        tx.execute(&format!(
+            "UPDATE {staging} s SET timestamp = t.timestamp FROM {table} t
+             WHERE s.trade_id = t.trade_id AND s.sequence IS NULL AND t.sequence IS NULL",
+            staging = staging, table = self.table,
+        ), &[]).await?;
+
+        // The same ID with different content is another trade, not a redelivery. It's
+        // quarantined (and alerted on by the caller) rather than skipped by ON CONFLICT like a duplicate
+        let detected_at = chrono::Utc::now().timestamp_millis();
+        let conflicts = tx.query(&format!(
+            "WITH conflicting AS (
+                DELETE FROM {staging} s
+                WHERE EXISTS (SELECT 1 FROM {table} t WHERE t.trade_id = s.trade_id AND ROW(t.*) IS DISTINCT FROM ROW(s.*))
+                   OR EXISTS (SELECT 1 FROM {staging} o WHERE o.trade_id = s.trade_id AND o.ctid < s.ctid AND ROW(o.*) IS DISTINCT FROM ROW(s.*))
+                RETURNING *
+             )
+             INSERT INTO trade_conflicts (trade_id, incoming, existing, detected_at)
+             SELECT c.trade_id, to_jsonb(c), (SELECT to_jsonb(t) FROM {table} t WHERE t.trade_id = c.trade_id LIMIT 1), $1
+             FROM conflicting c
+             ON CONFLICT DO NOTHING
+             RETURNING trade_id",
+            staging = staging, table = self.table,
+        ), &[&detected_at]).await?;
+        let conflicts = conflicts.iter().map(|row| row.get(0)).collect();
+
+        let insert = format!(
+            "INSERT INTO {table} ({cols}) SELECT {cols} FROM {staging} ON CONFLICT (trade_id, timestamp) DO NOTHING",
+            table = self.table, cols = COLUMNS, staging = staging,
+        );
+        let inserted = if self.update_candles || self.update_positions {
+            let candles = if self.update_candles {
+                format!(", candles_updated AS ({})", candles::upsert_sql("inserted"))
+            } else {
+                String::new()
+            };
+            let new_trades = tx.query(&format!(
+                "WITH inserted AS ({insert} RETURNING trade_id, market, buyer_id, seller_id, price, quantity, timestamp, sequence, {seq} AS seq){candles}
+                 SELECT market, buyer_id, seller_id, price, quantity, COALESCE(sequence, 0), timestamp FROM inserted ORDER BY {order}",
+                insert = insert, seq = candles::TRADE_SEQUENCE, candles = candles, order = ledger::TRADE_ORDER,
+            ), &[]).await?;
+            if self.update_positions {
+                let fills: Vec<Fill> = new_trades
+                    .iter()
+                    .flat_map(|r| Fill::for_trade(r.get(0), r.get(1), r.get(2), r.get(3), r.get(4), r.get(5), r.get(6)))
+                    .collect();
+                ledger::apply(&tx, &fills).await?;
+            }
+            new_trades.len() as u64
+        } else {
+            tx.execute(&insert, &[]).await?
+        };
+        tx.commit().await?;
+        Ok(Written { inserted, conflicts, detected_at })
+    }
+}
+
+/// `db-processor bench [rows] [batch_size]`: compares one INSERT per trade with
+/// batched COPY on a temporary copy of `trades` and prints rows/sec.
+pub async fn run_bench(client: &mut Client, args: &[String]) -> anyhow::Result<()> {
+    let rows: usize = args.first().and_then(|v| v.parse().ok()).unwrap_or(100_000);
+    let batch_size: usize = args.get(1).and_then(|v| v.parse().ok()).unwrap_or(1000).max(1);
+    client.batch_execute("CREATE TEMP TABLE bench_trades (LIKE trades INCLUDING ALL)").await?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let trades: Vec<TradeRow> = (0..rows as i64)
+        .map(|i| TradeRow {
+            trade_id: i,
+            market: "SOL_USDC".into(),
+            buyer_id: format!("buyer-{}", i % 1000),
+            seller_id: format!("seller-{}", i % 997),
+            price: 150_000_000 + i % 1000,
+            quantity: 1_000_000,
+            timestamp: now + i,
+            sequence: Some(i + 1),
+            timestamp_ns: Some((now + i) * 1_000_000),
+        })
+        .collect();
+
+    // Row-at-a-time is slow, so it gets a smaller sample
+    let sample = &trades[..rows.min(5_000)];
+    let started = Instant::now();
+    for row in sample {
+        client.execute(
+            &format!("INSERT INTO bench_trades ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT (trade_id, timestamp) DO NOTHING", COLUMNS),
+            &[&row.trade_id, &row.market, &row.buyer_id, &row.seller_id, &row.price, &row.quantity, &row.timestamp, &row.sequence, &row.timestamp_ns],
+        ).await?;
+    }
+    report("single-row INSERT", sample.len(), started.elapsed());
+
+    client.batch_execute("TRUNCATE bench_trades").await?;
+    let mut writer = TradeWriter::new("bench_trades");
+    let started = Instant::now();
+    for chunk in trades.chunks(batch_size) {
+        writer.write(client, chunk).await?;
+    }
+    report(&format!("COPY, {} rows per batch", batch_size), trades.len(), started.elapsed());
+    Ok(())
+}
+
+fn report(label: &str, rows: usize, elapsed: Duration) {
+    println!("⏱️ {:<28} {:>8} rows in {:>8.1?} = {:>10.0} rows/sec", label, rows, elapsed, rows as f64 / elapsed.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn trade(trade_id: i64, price: i64) -> TradeRow {
+        TradeRow {
+            trade_id,
+            market: "SOL_USDC".into(),
+            buyer_id: "buyer".into(),
+            seller_id: "seller".into(),
+            price,
+            quantity: 1_000_000,
+            timestamp: 1_700_000_000_000,
+            sequence: Some(trade_id),
+            timestamp_ns: Some(1_700_000_000_000_000_000),
+        }
+    }
+
+    async fn quarantined(client: &Client, trade_id: i64) -> Vec<(i64, Option<i64>)> {
+        let rows = client.query(
+            "SELECT (incoming->>'price')::BIGINT, (existing->>'price')::BIGINT FROM trade_conflicts WHERE trade_id = $1 ORDER BY detected_at",
+            &[&trade_id],
+        ).await.unwrap();
+        rows.iter().map(|r| (r.get(0), r.get(1))).collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn a_reused_trade_id_is_quarantined_and_a_redelivery_is_skipped() {
+        let mut client = testing::database().await;
+        client.batch_execute("CREATE TEMP TABLE conflict_trades (LIKE trades INCLUDING ALL)").await.unwrap();
+        let mut writer = TradeWriter::new("conflict_trades");
+        let id = testing::unique_trade_id();
+
+        assert_eq!(writer.write(&mut client, &[trade(id, 100)]).await.unwrap().inserted, 1);
+        // The same trade again is a redelivery: skipped, not quarantined
+        let redelivered = writer.write(&mut client, &[trade(id, 100)]).await.unwrap();
+        assert_eq!((redelivered.inserted, redelivered.conflicts.len()), (0, 0));
+        // The same ID with a different price is another trade
+        let reused = writer.write(&mut client, &[trade(id, 200)]).await.unwrap();
+        assert_eq!((reused.inserted, reused.conflicts), (0, vec![id]));
+        // Quarantined once, with the stored trade for comparison
+        assert!(writer.write(&mut client, &[trade(id, 200)]).await.unwrap().conflicts.is_empty());
+        assert_eq!(quarantined(&client, id).await, [(200, Some(100))]);
+
+        let stored: i64 = client.query_one("SELECT price FROM conflict_trades WHERE trade_id = $1", &[&id]).await.unwrap().get(0);
+        assert_eq!(stored, 100);
+        client.execute("DELETE FROM trade_conflicts WHERE trade_id = $1", &[&id]).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn a_trade_is_stored_and_candled_under_the_market_that_matched_it() {
+        let mut client = testing::database().await;
+        client.batch_execute("CREATE TEMP TABLE market_trades (LIKE trades INCLUDING ALL)").await.unwrap();
+        let id = testing::unique_trade_id();
+        // Unique so the candles can't merge with another run's
+        let market = format!("T{}", -id % 1_000_000_000);
+        let raw = serde_json::json!({
+            "trade_id": 1, "market": market, "price": "150", "quantity": "1",
+            "buyer_id": "buyer", "seller_id": "seller", "sequence": 1, "timestamp_ns": 1_700_000_000_000_000_000u64,
+        });
+        let row = TradeRow::from_match(&serde_json::from_value(raw).unwrap(), 0);
+        assert_eq!(row.market, market);
+
+        TradeWriter::new("market_trades").with_candles().write(&mut client, &[row]).await.unwrap();
+        let candles: i64 = client.query_one("SELECT COUNT(*) FROM candles WHERE market = $1", &[&market]).await.unwrap().get(0);
+        assert!(candles > 0);
+        client.execute("DELETE FROM candles WHERE market = $1", &[&market]).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn duplicates_within_a_batch_keep_the_first_trade() {
+        let mut client = testing::database().await;
+        client.batch_execute("CREATE TEMP TABLE batch_trades (LIKE trades INCLUDING ALL)").await.unwrap();
+        let mut writer = TradeWriter::new("batch_trades");
+        let (same, reused) = (testing::unique_trade_id(), testing::unique_trade_id());
+
+        let written = writer.write(&mut client, &[trade(same, 100), trade(same, 100), trade(reused, 100), trade(reused, 300)]).await.unwrap();
+        assert_eq!((written.inserted, written.conflicts), (2, vec![reused]));
+        // Nothing was stored yet when the batch's second copy arrived
+        assert_eq!(quarantined(&client, reused).await, [(300, None)]);
+        assert!(quarantined(&client, same).await.is_empty());
+        client.execute("DELETE FROM trade_conflicts WHERE trade_id = $1", &[&reused]).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn an_unstamped_trade_redelivered_later_is_skipped() {
+        let mut client = testing::database().await;
+        client.batch_execute("CREATE TEMP TABLE legacy_trades (LIKE trades INCLUDING ALL)").await.unwrap();
+        let mut writer = TradeWriter::new("legacy_trades");
+        let id = testing::unique_trade_id();
+        let unstamped = |received_ms| TradeRow { timestamp: received_ms, sequence: None, timestamp_ns: None, ..trade(id, 100) };
+
+        assert_eq!(writer.write(&mut client, &[unstamped(1_700_000_000_000)]).await.unwrap().inserted, 1);
+        // Received again a second later, so timed differently
+        let redelivered = writer.write(&mut client, &[unstamped(1_700_000_001_000)]).await.unwrap();
+        assert_eq!((redelivered.inserted, redelivered.conflicts.len()), (0, 0));
+        let stored: Vec<i64> = client.query("SELECT timestamp FROM legacy_trades", &[]).await.unwrap().iter().map(|r| r.get(0)).collect();
+        assert_eq!(stored, [1_700_000_000_000]);
+    }
+
+    fn instance(name: &str) -> Instance {
+        Instance { id: testing::unique_name(name).to_string(), lease: Duration::from_secs(30) }
+    }
+
+    async fn push(redis: &RedisClient, queue: Queue, messages: &[&str]) {
+        for message in messages {
+            redis.lpush::<(), _, _>(queue.name, *message).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis (TEST_REDIS_URL)"]
+    async fn a_stopped_instances_batch_is_requeued_once_its_lease_runs_out() {
+        let redis = testing::redis().await;
+        let queue = Queue { name: testing::unique_name("TEST_QUEUE") };
+        let config = BatchConfig { max_rows: 2, max_wait: Duration::ZERO, max_attempts: 5 };
+        let (crashed, survivor) = (instance("crashed"), instance("survivor"));
+        register(&redis, &crashed).await.unwrap();
+        register(&redis, &survivor).await.unwrap();
+        push(&redis, queue, &["1", "2", "3"]).await;
+
+        let crashed_consumer = Consumer::new(queue, &crashed);
+        assert_eq!(next_batch(&redis, &crashed_consumer, config).await.unwrap(), ["1", "2"]);
+        // Nothing is taken from an instance that still holds its lease
+        assert_eq!(recover_stale(&redis, &survivor, &[queue]).await.unwrap(), 0);
+
+        redis.del::<(), _>(crashed.lease_key()).await.unwrap();
+        assert_eq!(recover_stale(&redis, &survivor, &[queue]).await.unwrap(), 2);
+        assert!(unacknowledged(&redis, &crashed_consumer).await.unwrap().is_empty());
+        // The requeued batch is next in line, in its original order
+        let survivor_consumer = Consumer::new(queue, &survivor);
+        assert_eq!(claim(&redis, &survivor_consumer, config).await.unwrap(), ["1", "2"]);
+        acknowledge(&redis, &survivor_consumer).await.unwrap();
+        assert_eq!(claim(&redis, &survivor_consumer, config).await.unwrap(), ["3"]);
+        // A crash before acknowledging leaves the batch to the same instance on restart
+        assert_eq!(claim(&redis, &survivor_consumer, config).await.unwrap(), ["3"]);
+        acknowledge(&redis, &survivor_consumer).await.unwrap();
+
+        let live: Vec<String> = redis.smembers(INSTANCES_KEY).await.unwrap();
+        assert!(!live.contains(&crashed.id));
+        redis.srem::<(), _, _>(INSTANCES_KEY, survivor.id.as_str()).await.unwrap();
+        redis.del::<(), _>(vec![survivor.lease_key(), queue.name.to_string()]).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres and Redis (TEST_DATABASE_URL, TEST_REDIS_URL)"]
+    async fn a_batch_postgres_keeps_rejecting_is_dead_lettered_message_by_message() {
+        let redis = testing::redis().await;
+        let client = testing::database().await;
+        let queue = Queue { name: testing::unique_name("TEST_QUEUE") };
+        let config = BatchConfig { max_rows: 10, max_wait: Duration::ZERO, max_attempts: 2 };
+        let consumer = Consumer::new(queue, &instance("rejecting"));
+        push(&redis, queue, &["1", "oops", "3"]).await;
+        let batch = next_batch(&redis, &consumer, config).await.unwrap();
+        let write = async |values: &[String]| client.execute("SELECT $1::TEXT[]::BIGINT[]", &[&values]).await.map(drop);
+
+        let rejected = write(&batch).await.unwrap_err();
+        assert_eq!(write_failed(&redis, &consumer, config, &rejected, batch.len()).await, None);
+        assert_eq!(unacknowledged(&redis, &consumer).await.unwrap(), batch);
+        let attempts = write_failed(&redis, &consumer, config, &rejected, batch.len()).await.unwrap();
+        assert_eq!(attempts, 2);
+        for message in &batch {
+            let result = write(std::slice::from_ref(message)).await;
+            assert!(settle_alone(&redis, &consumer, message, result, attempts).await);
+        }
+        acknowledge(&redis, &consumer).await.unwrap();
+
+        let dead: Vec<String> = redis.lrange(queue.dead_letters(), 0, -1).await.unwrap();
+        let dead: Vec<DeadLetter> = dead.iter().map(|d| serde_json::from_str(d).unwrap()).collect();
+        assert_eq!(dead.iter().map(|d| (d.payload.as_str(), d.attempts)).collect::<Vec<_>>(), [("oops", 2)]);
+        assert!(unacknowledged(&redis, &consumer).await.unwrap().is_empty());
+        // The attempt count went with the batch
+        assert!(!redis.exists::<bool, _>(consumer.attempts.as_str()).await.unwrap());
+        redis.del::<(), _>(queue.dead_letters()).await.unwrap();
+    }
+}
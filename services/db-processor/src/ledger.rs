@@ -0,0 +1,421 @@
+//! Off-chain position and realized PnL per user and market, folded from trades
+//! with the same arithmetic the program applies in `apply_fill_to_account`, so
+//! `positions` shows what the user's `MarginAccount` will hold once the trades settle.
+
+use futures::TryStreamExt;
+use std::collections::{HashMap, HashSet};
+use std::pin::pin;
+use tokio_postgres::{Client, Transaction};
+
+/// One user's position in one market. Size (signed, + long) and prices are
+/// 6-decimal fixed point like on chain; `realized_pnl` is in collateral units,
+/// i.e. what the program has added to or taken from the account's collateral.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PositionState {
+    pub size: i64,
+    pub avg_entry_price: i64,
+    pub realized_pnl: i64,
+}
+
+impl PositionState {
+    /// Applies a fill of `qty_delta` (+ bought, - sold) at `price`, rounding exactly
+    /// like the program: PnL is truncated per fill and the average entry rounds down.
+    pub fn apply_fill(&mut self, qty_delta: i64, price: i64) {
+        // Reducing or flipping realizes PnL on the closed part
+        if self.size != 0 && (self.size > 0) != (qty_delta > 0) {
+            let closed = self.size.abs().min(qty_delta.abs()) as i128;
+            let per_unit = if self.size > 0 { price - self.avg_entry_price } else { self.avg_entry_price - price } as i128;
+            self.realized_pnl += (per_unit * closed / 1_000_000) as i64;
+        }
+
+        let new_size = self.size + qty_delta;
+        self.avg_entry_price = if new_size == 0 {
+            0
+        } else if (new_size > 0) == (self.size > 0) {
+            // Still on the same side. Like the program, this averages the fill in even
+            // when it reduced the position, which moves the entry of what's left
+            let weighted = self.avg_entry_price as u128 * self.size.unsigned_abs() as u128
+                + price as u128 * qty_delta.unsigned_abs() as u128;
+            (weighted / new_size.unsigned_abs() as u128) as i64
+        } else {
+            // Flipped (or opened): the remainder was entered at the fill price
+            price
+        };
+        self.size = new_size;
+    }
+}
+
+/// One side of a trade: the buyer gets `+quantity`, the seller `-quantity`.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub user_id: String,
+    pub market: String,
+    pub qty_delta: i64,
+    pub price: i64,
+    pub sequence: i64,
+    pub timestamp: i64,
+}
+
+impl Fill {
+    pub fn for_trade(market: &str, buyer_id: &str, seller_id: &str, price: i64, quantity: i64, sequence: i64, timestamp: i64) -> [Fill; 2] {
+        let fill = |user_id: &str, qty_delta| Fill {
+            user_id: user_id.to_string(),
+            market: market.to_string(),
+            qty_delta,
+            price,
+            sequence,
+            timestamp,
+        };
+        [fill(buyer_id, quantity), fill(seller_id, -quantity)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    state: PositionState,
+    last_sequence: i64,
+    updated_at: i64,
+}
+
+type Positions = HashMap<(String, String), Entry>;
+
+// Positions folded from archived trades only (see `snapshot_partition`)
+const SNAPSHOTS: &str = "position_snapshots";
+
+/// Folds `fills`, in the order given, into the stored positions of the users
+/// involved. Runs inside the transaction that inserted their trades. A position
+/// that gets a fill older than one it already holds is recomputed from its
+/// snapshot and `trades`, which by now include the late fill.
+pub async fn apply(tx: &Transaction<'_>, fills: &[Fill]) -> Result<(), tokio_postgres::Error> {
+    if fills.is_empty() {
+        return Ok(());
+    }
+    let keys: Vec<(&str, &str)> = fills.iter().map(|f| (f.user_id.as_str(), f.market.as_str())).collect();
+    let mut positions = load(tx, "positions", &keys, true).await?;
+
+    let late: HashSet<(String, String)> = fills
+        .iter()
+        .filter(|f| f.sequence != 0 && positions.get(&(f.user_id.clone(), f.market.clone())).is_some_and(|e| f.sequence < e.last_sequence))
+        .map(|f| (f.user_id.clone(), f.market.clone()))
+        .collect();
+    let recompute = !late.is_empty() && snapshots_complete(tx).await?;
+    for fill in fills {
+        let key = (fill.user_id.clone(), fill.market.clone());
+        if recompute && late.contains(&key) {
+            continue;
+        }
+        fold(&mut positions, fill);
+    }
+    for (user_id, market) in &late {
+        if recompute {
+            eprintln!("⚠️ Late trade for {} on {}; recomputing the position", user_id, market);
+            let entry = recompute_position(tx, user_id, market).await?;
+            positions.insert((user_id.clone(), market.clone()), entry);
+        } else {
+            eprintln!("⚠️ Late trade for {} on {}, but partitions archived before position snapshots keep it from being recomputed", user_id, market);
+        }
+    }
+    upsert(tx, "positions", &positions).await
+}
+
+fn fold(positions: &mut Positions, fill: &Fill) {
+    let entry = positions
+        .entry((fill.user_id.clone(), fill.market.clone()))
+        .or_insert(Entry { updated_at: fill.timestamp, ..Entry::default() });
+    entry.state.apply_fill(fill.qty_delta, fill.price);
+    entry.last_sequence = entry.last_sequence.max(fill.sequence);
+    entry.updated_at = fill.timestamp;
+}
+
+/// The stored rows of `table` (`positions` or `position_snapshots`) for `keys`.
+async fn load(tx: &Transaction<'_>, table: &str, keys: &[(&str, &str)], for_update: bool) -> Result<Positions, tokio_postgres::Error> {
+    let users: Vec<&str> = keys.iter().map(|(user, _)| *user).collect();
+    let markets: Vec<&str> = keys.iter().map(|(_, market)| *market).collect();
+    let rows = tx.query(
+        &format!(
+            "SELECT p.user_id, p.market, p.size, p.avg_entry_price, p.realized_pnl, p.last_sequence, p.updated_at
+             FROM {} p JOIN (SELECT DISTINCT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[])) AS k(user_id, market)
+                 USING (user_id, market){}",
+            table,
+            if for_update { " FOR UPDATE OF p" } else { "" },
+        ),
+        &[&users, &markets],
+    ).await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let state = PositionState { size: row.get(2), avg_entry_price: row.get(3), realized_pnl: row.get(4) };
+            ((row.get(0), row.get(1)), Entry { state, last_sequence: row.get(5), updated_at: row.get(6) })
+        })
+        .collect())
+}
+
+async fn upsert(tx: &Transaction<'_>, table: &str, positions: &Positions) -> Result<(), tokio_postgres::Error> {
+    let users: Vec<&str> = positions.keys().map(|(user, _)| user.as_str()).collect();
+    let markets: Vec<&str> = positions.keys().map(|(_, market)| market.as_str()).collect();
+    let column = |f: fn(&Entry) -> i64| positions.values().map(f).collect::<Vec<i64>>();
+    let sizes = column(|e| e.state.size);
+    let entries = column(|e| e.state.avg_entry_price);
+    let pnls = column(|e| e.state.realized_pnl);
+    let updated = column(|e| e.updated_at);
+    let sequences = column(|e| e.last_sequence);
+
+    tx.execute(
+        &format!(
+            "INSERT INTO {} (user_id, market, size, avg_entry_price, realized_pnl, updated_at, last_sequence)
+             SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::BIGINT[], $4::BIGINT[], $5::BIGINT[], $6::BIGINT[], $7::BIGINT[])
+             ON CONFLICT (user_id, market) DO UPDATE SET
+                 size = EXCLUDED.size,
+                 avg_entry_price = EXCLUDED.avg_entry_price,
+                 realized_pnl = EXCLUDED.realized_pnl,
+                 updated_at = EXCLUDED.updated_at,
+                 last_sequence = EXCLUDED.last_sequence",
+            table,
+        ),
+        &[&users, &markets, &sizes, &entries, &pnls, &updated, &sequences],
+    ).await?;
+    Ok(())
+}
+
+/// Trades in the order the ledger folds them: those from before the engine
+/// stamped events by trade ID, then everything else by sequence number.
+pub const TRADE_ORDER: &str = "sequence NULLS FIRST, trade_id";
+
+// Trade columns `fold_trades` expects, in order
+const FILL_COLUMNS: &str = "market, buyer_id, seller_id, price, quantity, COALESCE(sequence, 0), timestamp";
+
+/// Folds the trades `query` returns (`FILL_COLUMNS`, in `TRADE_ORDER`) into
+/// `positions`, keeping only the fills `keep` accepts.
+async fn fold_trades(
+    tx: &Transaction<'_>,
+    query: &str,
+    params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+    positions: &mut Positions,
+    keep: impl Fn(&Fill) -> bool,
+) -> Result<(), tokio_postgres::Error> {
+    let stream = tx.query_raw(query, params.iter().copied()).await?;
+    let mut stream = pin!(stream);
+    while let Some(row) = stream.try_next().await? {
+        let market: String = row.get(0);
+        for fill in Fill::for_trade(&market, row.get(1), row.get(2), row.get(3), row.get(4), row.get(5), row.get(6)) {
+            if keep(&fill) {
+                fold(positions, &fill);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `position_snapshots` covers every archived partition.
+async fn snapshots_complete(tx: &Transaction<'_>) -> Result<bool, tokio_postgres::Error> {
+    tx.query_one("SELECT COALESCE(BOOL_AND(snapshotted), TRUE) FROM trade_archives", &[]).await.map(|r| r.get(0))
+}
+
+/// One position folded from its snapshot and its trades still in `trades`.
+async fn recompute_position(tx: &Transaction<'_>, user_id: &str, market: &str) -> Result<Entry, tokio_postgres::Error> {
+    let key = (user_id.to_string(), market.to_string());
+    let mut positions = load(tx, SNAPSHOTS, &[(user_id, market)], false).await?;
+    let query = format!(
+        "SELECT {} FROM trades WHERE market = $1 AND (buyer_id = $2 OR seller_id = $2) ORDER BY {}",
+        FILL_COLUMNS, TRADE_ORDER
+    );
+    fold_trades(tx, &query, &[&market, &user_id], &mut positions, |f| f.user_id == user_id).await?;
+    Ok(positions.remove(&key).unwrap_or_default())
+}
+
+/// Folds the trades of a partition about to be archived into `position_snapshots`
+/// and marks its archive as snapshotted. Runs in the archiving transaction, after
+/// the partition's `trade_archives` row is written.
+pub async fn snapshot_partition(tx: &Transaction<'_>, partition: &str) -> Result<(), tokio_postgres::Error> {
+    let rows = tx.query(&format!("SELECT DISTINCT user_id, market FROM (
+            SELECT buyer_id AS user_id, market FROM {p} UNION SELECT seller_id, market FROM {p}
+        ) users", p = partition), &[]).await?;
+    let keys: Vec<(String, String)> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+    let keys: Vec<(&str, &str)> = keys.iter().map(|(u, m)| (u.as_str(), m.as_str())).collect();
+    let mut snapshots = load(tx, SNAPSHOTS, &keys, true).await?;
+    let query = format!("SELECT {} FROM {} ORDER BY {}", FILL_COLUMNS, partition, TRADE_ORDER);
+    fold_trades(tx, &query, &[], &mut snapshots, |_| true).await?;
+    upsert(tx, SNAPSHOTS, &snapshots).await?;
+    tx.execute("UPDATE trade_archives SET snapshotted = TRUE WHERE partition_name = $1", &[&partition]).await?;
+    Ok(())
+}
+
+/// Recomputes every position from `position_snapshots` and `trades`. Returns how
+/// many positions were written. Fails if partitions were archived before position
+/// snapshots existed, since their trades are gone.
+pub async fn rebuild(client: &mut Client) -> anyhow::Result<usize> {
+    let tx = client.transaction().await?;
+    // Blocks the live writer until the rebuild commits; trades it inserted meanwhile
+    // aren't visible here and get applied on top afterwards
+    tx.batch_execute("LOCK TABLE positions IN EXCLUSIVE MODE").await?;
+    if !snapshots_complete(&tx).await? {
+        anyhow::bail!("partitions were archived before position snapshots existed; positions can't be rebuilt");
+    }
+    tx.execute("DELETE FROM positions", &[]).await?;
+
+    let rows = tx.query(&format!("SELECT user_id, market FROM {}", SNAPSHOTS), &[]).await?;
+    let keys: Vec<(String, String)> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+    let keys: Vec<(&str, &str)> = keys.iter().map(|(u, m)| (u.as_str(), m.as_str())).collect();
+    let mut positions = load(&tx, SNAPSHOTS, &keys, false).await?;
+    let query = format!("SELECT {} FROM trades ORDER BY {}", FILL_COLUMNS, TRADE_ORDER);
+    fold_trades(&tx, &query, &[], &mut positions, |_| true).await?;
+
+    upsert(&tx, "positions", &positions).await?;
+    tx.commit().await?;
+    Ok(positions.len())
+}
+
+/// `db-processor positions rebuild` recomputes the ledger from stored trades.
+pub async fn run_positions_cli(client: &mut Client, args: &[String]) -> anyhow::Result<()> {
+    if args.first().map(String::as_str) != Some("rebuild") {
+        eprintln!("Usage: db-processor positions rebuild");
+        return Ok(());
+    }
+    let written = rebuild(client).await?;
+    println!("📒 Rebuilt {} position(s) from trades", written);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hybrid_perp_dex::instructions::apply_fill_to_account;
+    use hybrid_perp_dex::state::{MarginAccount, Position, MAX_POSITIONS};
+
+    const MARKET: [u8; 16] = *b"SOL_USDC\0\0\0\0\0\0\0\0";
+    const COLLATERAL: u64 = 1_000_000_000_000_000;
+
+    fn margin_account() -> MarginAccount {
+        MarginAccount {
+            owner: Default::default(),
+            collateral: COLLATERAL,
+            positions: [Position::default(); MAX_POSITIONS],
+            position_count: 0,
+            nonce: 0,
+            bump: 0,
+        }
+    }
+
+    /// Replays `fills` through the program and the ledger, comparing after each one.
+    fn assert_matches_program(fills: &[(i64, u64)]) {
+        let mut account = margin_account();
+        let mut ledger = PositionState::default();
+        for (i, &(qty_delta, price)) in fills.iter().enumerate() {
+            apply_fill_to_account(&mut account, MARKET, qty_delta, price).unwrap();
+            ledger.apply_fill(qty_delta, price as i64);
+
+            let on_chain = account.positions[..account.position_count as usize].iter().find(|p| p.market == MARKET);
+            let expected = PositionState {
+                size: on_chain.map_or(0, |p| p.size),
+                avg_entry_price: on_chain.map_or(0, |p| p.avg_entry_price as i64),
+                realized_pnl: account.collateral as i64 - COLLATERAL as i64,
+            };
+            assert_eq!(ledger, expected, "diverged after fill {} ({} @ {})", i, qty_delta, price);
+        }
+    }
+
+    #[test]
+    fn ledger_matches_apply_fill_to_account() {
+        // Open, add, reduce at a profit, flip short, cover at a loss, reopen
+        assert_matches_program(&[
+            (2_000_000, 150_000_000),
+            (1_000_000, 153_000_000),
+            (-1_500_000, 160_000_000),
+            (-3_000_000, 158_500_000),
+            (500_000, 161_000_000),
+            (1_000_000, 149_999_999),
+            (333_333, 150_123_457),
+        ]);
+
+        // Pseudo-random walk, including odd sizes that exercise truncation
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let fills: Vec<(i64, u64)> = (0..500)
+            .map(|_| {
+                let qty = (next() % 5_000_000 + 1) as i64;
+                let price = 100_000_000 + next() % 100_000_000;
+                (if next() % 2 == 0 { qty } else { -qty }, price)
+            })
+            .collect();
+        assert_matches_program(&fills);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn a_late_fill_recomputes_the_position_from_its_snapshot() {
+        let mut client = crate::testing::database().await;
+        let base = -crate::testing::unique_trade_id();
+        let (user, other) = (format!("late-{}", base), format!("other-{}", base));
+        let trade = |n: i64, buy: bool, price: i64| crate::ingest::TradeRow {
+            trade_id: -(base + n),
+            market: "SOL_USDC".into(),
+            buyer_id: if buy { user.clone() } else { other.clone() },
+            seller_id: if buy { other.clone() } else { user.clone() },
+            price,
+            quantity: 1_000_000,
+            timestamp: 1_700_000_000_000 + n,
+            sequence: Some(base + n),
+            timestamp_ns: Some((1_700_000_000_000 + n) * 1_000_000),
+        };
+        // An archived buy at 50, then trades 1 and 3 arrive before 2
+        let mut expected = PositionState::default();
+        expected.apply_fill(1_000_000, 50_000_000);
+        client.execute(
+            "INSERT INTO position_snapshots (user_id, market, size, avg_entry_price, realized_pnl, updated_at, last_sequence)
+             VALUES ($1, 'SOL_USDC', $2, $3, 0, 0, 0)",
+            &[&user, &expected.size, &expected.avg_entry_price],
+        ).await.unwrap();
+        let trades = [trade(1, true, 100_000_000), trade(2, true, 400_000_000), trade(3, false, 200_000_000)];
+        for t in &trades {
+            expected.apply_fill(if t.buyer_id == user { t.quantity } else { -t.quantity }, t.price);
+        }
+
+        let mut writer = crate::ingest::TradeWriter::new("trades").with_positions();
+        writer.write(&mut client, &[trades[0].clone(), trades[2].clone()]).await.unwrap();
+        writer.write(&mut client, &[trades[1].clone()]).await.unwrap();
+
+        let row = client.query_one(
+            "SELECT size, avg_entry_price, realized_pnl, last_sequence FROM positions WHERE user_id = $1 AND market = 'SOL_USDC'",
+            &[&user],
+        ).await.unwrap();
+        let stored = PositionState { size: row.get(0), avg_entry_price: row.get(1), realized_pnl: row.get(2) };
+        assert_eq!(stored, expected);
+        assert_eq!(row.get::<_, i64>(3), base + 3);
+
+        let users = vec![user, other];
+        client.execute("DELETE FROM trades WHERE buyer_id = ANY($1)", &[&users]).await.unwrap();
+        client.execute("DELETE FROM positions WHERE user_id = ANY($1)", &[&users]).await.unwrap();
+        client.execute("DELETE FROM position_snapshots WHERE user_id = ANY($1)", &[&users]).await.unwrap();
+    }
+    #[tokio::test]
+    #[ignore = "needs Postgres (TEST_DATABASE_URL)"]
+    async fn each_market_matched_in_gets_its_own_position() {
+        let mut client = crate::testing::database().await;
+        let id = crate::testing::unique_trade_id();
+        let (user, other) = (format!("markets-{}", id), format!("other-{}", id));
+        let matched = |n: i64, market: &str, user_buys: bool| {
+            let (buyer_id, seller_id) = if user_buys { (&user, &other) } else { (&other, &user) };
+            let raw = serde_json::json!({
+                "trade_id": -(id - n), "market": market, "price": "100", "quantity": "2",
+                "buyer_id": buyer_id, "seller_id": seller_id, "sequence": -(id - n), "timestamp_ns": 1_700_000_000_000_000_000u64,
+            });
+            crate::ingest::TradeRow::from_match(&serde_json::from_value(raw).unwrap(), 0)
+        };
+
+        let mut writer = crate::ingest::TradeWriter::new("trades").with_positions();
+        writer.write(&mut client, &[matched(1, "BTC_USDC", true), matched(2, "SOL_USDC", false)]).await.unwrap();
+
+        let rows = client.query("SELECT market, size FROM positions WHERE user_id = $1 ORDER BY market", &[&user]).await.unwrap();
+        let sizes: Vec<(String, i64)> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+        assert_eq!(sizes, [("BTC_USDC".to_string(), 2_000_000), ("SOL_USDC".to_string(), -2_000_000)]);
+
+        let users = vec![user, other];
+        client.execute("DELETE FROM trades WHERE buyer_id = ANY($1)", &[&users]).await.unwrap();
+        client.execute("DELETE FROM positions WHERE user_id = ANY($1)", &[&users]).await.unwrap();
+    }
+}
@@ -1,5 +1,13 @@
-use num_traits::ToPrimitive;
-use tokio_postgres::{NoTls, types::ToSql};
+mod candles;
+mod ingest;
+mod ledger;
+mod orders;
+mod partitions;
+#[cfg(test)]
+mod testing;
+
+use ingest::{BatchConfig, Consumer, Instance, DB_QUEUE, ORDER_EVENT_QUEUE, TradeRow, TradeWriter};
+use tokio_postgres::NoTls;
 use fred::prelude::*;
 use common_utils::MatchResult;
 use std::env;
@@ -13,94 +21,141 @@ async fn main() -> anyhow::Result<()> {
     let redis_url = env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
 
     // 2. Connect to Postgres
-    // tokio-postgres requires spawning the connection task separately
-    let (client, connection) = tokio_postgres::connect(&db_url, NoTls).await?;
+    let mut client = connect(&db_url).await?;
+    println!("✅ Connected to Postgres!");
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("❌ Postgres connection error: {}", e);
-        }
-    });
+    // Schema commands: `db-processor migrate [status]`
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        return run_migrate_cli(&mut client, &args[1..]).await;
+    }
 
-    println!("✅ Connected to Postgres!");
+    // 3. Bring the schema up to date (see libs/db-migrations)
+    // With DB_AUTO_MIGRATE=false the schema must already be current, e.g. migrated by a deploy step
+    let auto_migrate = env::var("DB_AUTO_MIGRATE").map(|v| v != "false").unwrap_or(true);
+    if auto_migrate {
+        db_migrations::migrate(&mut client).await?;
+    } else {
+        db_migrations::ensure_current(&client).await?;
+    }
+    println!("📊 Database schema at version {}.", db_migrations::latest_version());
 
-    // 3. Initialize Table (Self-Healing Schema)
-    // We use BIGINT (i64) for price/qty to store 6-decimal fixed point values.
-    // This is a high-frequency trading standard to avoid NUMERIC overhead.
-    client.batch_execute("
-        CREATE TABLE IF NOT EXISTS trades (
-            trade_id BIGINT PRIMARY KEY,
-            market VARCHAR(16) NOT NULL,
-            buyer_id VARCHAR(44) NOT NULL,
-            seller_id VARCHAR(44) NOT NULL,
-            price BIGINT NOT NULL,
-            quantity BIGINT NOT NULL,
-            timestamp BIGINT NOT NULL
-        );
-    ").await?;
-    println!("📊 Database schema verified.");
+    // Throughput check against a temporary table: `db-processor bench [rows] [batch_size]`
+    if args.first().map(String::as_str) == Some("bench") {
+        return ingest::run_bench(&mut client, &args[1..]).await;
+    }
+    // Rebuild candles from stored trades: `db-processor candles backfill <market> [from_ms] [to_ms]`
+    if args.first().map(String::as_str) == Some("candles") {
+        return candles::run_candles_cli(&mut client, &args[1..]).await;
+    }
+    // Recompute the position ledger from stored trades: `db-processor positions rebuild`
+    if args.first().map(String::as_str) == Some("positions") {
+        return ledger::run_positions_cli(&mut client, &args[1..]).await;
+    }
+    // Daily trade partitions and retention: `db-processor partitions <maintain|status>`
+    if args.first().map(String::as_str) == Some("partitions") {
+        return partitions::run_partitions_cli(&mut client, &args[1..]).await;
+    }
 
     // 4. Initialize Redis
     let config = RedisConfig::from_url(&redis_url)?;
     let redis = Builder::from_config(config).build()?;
     redis.init().await?;
-    println!("🚀 Historian is watching DB_QUEUE...");
+    let batch_config = BatchConfig::from_env();
+    let instance = Instance::from_env();
+    ingest::register(&redis, &instance).await?;
+    let requeued = ingest::recover_stale(&redis, &instance, &[DB_QUEUE, ORDER_EVENT_QUEUE]).await?;
+    if requeued > 0 {
+        println!("♻️ Requeued {} message(s) claimed by stopped db-processor instances", requeued);
+    }
+    tokio::spawn(ingest::keep_alive(redis.clone(), instance.clone(), &[DB_QUEUE, ORDER_EVENT_QUEUE]));
+    let consumer = Consumer::new(DB_QUEUE, &instance);
+    let mut writer = TradeWriter::new("trades").with_candles().with_positions();
+    tokio::spawn(orders::run(redis.clone(), Consumer::new(ORDER_EVENT_QUEUE, &instance), connect(&db_url).await?, batch_config));
+    tokio::spawn(partitions::run(connect(&db_url).await?, partitions::PartitionConfig::from_env()));
+    println!("🚀 Historian {} is watching {}...", instance.id, DB_QUEUE.name);
 
     loop {
-        // Pop from Redis
-        match redis.rpop::<Option<String>, _>("DB_QUEUE", None).await {
-            Ok(Some(raw_data)) => {
-                let m: MatchResult = match serde_json::from_str(&raw_data) {
-                    Ok(parsed) => parsed,
-                    Err(e) => {
-                        eprintln!("❌ Failed to parse match data: {}", e);
-                        continue;
-                    }
-                };
-
-                // Convert Decimal to i64 (multiply by 1,000,000 for 6 decimal places)
-                let p_i64 = (m.price * rust_decimal::Decimal::from(1_000_000))
-                    .to_i64()
-                    .unwrap_or(0);
-                let q_i64 = (m.quantity * rust_decimal::Decimal::from(1_000_000))
-                    .to_i64()
-                    .unwrap_or(0);
+        let Some(claimed) = ingest::claim(&redis, &consumer, batch_config).await else { continue };
 
-                let market = "SOL_USDC";
-                let trade_id_i64 = m.trade_id as i64;
-                let ts_millis = chrono::Utc::now().timestamp_millis();
-                
-                // Use an array of trait objects to mix types
-                let params: &[&(dyn ToSql + Sync)] = &[
-                    &trade_id_i64, 
-                    &market, 
-                    &m.buyer_id, 
-                    &m.seller_id, 
-                    &p_i64, 
-                    &q_i64, 
-                    &ts_millis
-                ];
-                
-                let res = client.execute(
-                    "INSERT INTO trades (trade_id, market, buyer_id, seller_id, price, quantity, timestamp) 
-                        VALUES ($1, $2, $3, $4, $5, $6, $7) 
-                        ON CONFLICT (trade_id) DO NOTHING",
-                    params
-                ).await;
-
-                match res {
-                    Ok(_) => println!("💾 Persisted Trade #{}", m.trade_id),
-                    Err(e) => eprintln!("❌ Database insert error: {}", e),
+        let received_ms = chrono::Utc::now().timestamp_millis();
+        let mut payloads = Vec::with_capacity(claimed.len());
+        let mut rows = Vec::with_capacity(claimed.len());
+        for raw in &claimed {
+            match serde_json::from_str::<MatchResult>(raw) {
+                Ok(m) => {
+                    payloads.push(raw.as_str());
+                    rows.push(TradeRow::from_match(&m, received_ms));
+                }
+                Err(e) => {
+                    eprintln!("❌ Failed to parse match data: {}", e);
+                    if let Err(e) = ingest::dead_letter(&redis, DB_QUEUE, raw, format!("unparseable: {}", e), 0).await {
+                        eprintln!("❌ Failed to dead-letter match data: {}", e);
+                    }
                 }
             }
-            Ok(None) => {
-                // Queue empty, wait 100ms
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        match writer.write(&mut client, &rows).await {
+            Ok(written) => {
+                ingest::alert_conflicts(&redis, &written).await;
+                // Only now is it safe to drop the messages from Redis
+                if let Err(e) = ingest::acknowledge(&redis, &consumer).await {
+                    eprintln!("❌ Failed to acknowledge batch: {}", e);
+                }
+                println!("💾 Persisted {} trade(s) ({} new)", rows.len(), written.inserted);
             }
             Err(e) => {
-                eprintln!("❌ Redis error: {}", e);
-                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                let Some(attempts) = ingest::write_failed(&redis, &consumer, batch_config, &e, rows.len()).await else { continue };
+                let mut settled = true;
+                for (payload, row) in payloads.iter().zip(&rows) {
+                    let result = match writer.write(&mut client, std::slice::from_ref(row)).await {
+                        Ok(written) => {
+                            ingest::alert_conflicts(&redis, &written).await;
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    settled = ingest::settle_alone(&redis, &consumer, payload, result, attempts).await;
+                    if !settled {
+                        break;
+                    }
+                }
+                if settled && let Err(e) = ingest::acknowledge(&redis, &consumer).await {
+                    eprintln!("❌ Failed to acknowledge batch: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn connect(db_url: &str) -> Result<tokio_postgres::Client, tokio_postgres::Error> {
+    // tokio-postgres requires spawning the connection task separately
+    let (client, connection) = tokio_postgres::connect(db_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("❌ Postgres connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// `db-processor migrate` applies pending migrations; `db-processor migrate status` lists them.
+async fn run_migrate_cli(client: &mut tokio_postgres::Client, args: &[String]) -> anyhow::Result<()> {
+    match args.first().map(String::as_str) {
+        None => {
+            let applied = db_migrations::migrate(client).await?;
+            println!("✅ Applied {} migration(s); schema at version {}", applied.len(), db_migrations::latest_version());
+        }
+        Some("status") => {
+            for (migration, applied_at) in db_migrations::status(client).await? {
+                match applied_at {
+                    Some(ts) => println!("✅ {} (applied at {})", migration.name, ts),
+                    None => println!("⏳ {} (pending)", migration.name),
+                }
             }
         }
+        _ => eprintln!("Usage: db-processor migrate [status]"),
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use common_utils::{OrderEvent, OrderEventType};
+use fred::prelude::*;
+use num_traits::ToPrimitive;
+use rust_decimal::Decimal;
+use tokio_postgres::Client;
+
+use crate::ingest::{self, BatchConfig, Consumer};
+
+/// One order event, flattened for both `order_events` (the event itself) and
+/// `orders` (the order's state after it). Amounts are 6-decimal fixed point.
+#[derive(Debug, Clone)]
+pub struct OrderEventRow {
+    pub order_id: i64,
+    pub event_type: &'static str,
+    pub status: &'static str,
+    pub user_id: String,
+    pub market: String,
+    pub side: String,
+    pub price: i64,
+    pub quantity: i64,
+    pub filled_quantity: i64,
+    /// Fill price for fills, otherwise the order's limit price
+    pub event_price: i64,
+    /// Filled, cancelled or placed quantity, depending on the event
+    pub event_quantity: i64,
+    pub trade_id: Option<i64>,
+    pub reason: Option<String>,
+    pub sequence: i64,
+    pub timestamp: i64,
+}
+
+impl OrderEventRow {
+    pub fn from_event(e: &OrderEvent) -> Self {
+        let event_quantity = match e.event_type {
+            OrderEventType::Filled => e.fill_quantity.unwrap_or_default(),
+            OrderEventType::Cancelled => e.remaining_quantity,
+            OrderEventType::Placed | OrderEventType::Rejected => e.quantity,
+        };
+        // Rejected orders can carry anything the client sent, so clip to the column widths
+        Self {
+            order_id: e.order_id as i64,
+            event_type: e.event_type.as_str(),
+            status: e.status.as_str(),
+            user_id: clip(&e.user_id, 44),
+            market: clip(&e.market, 16),
+            side: clip(&e.side, 4),
+            price: fixed(e.price),
+            quantity: fixed(e.quantity),
+            filled_quantity: fixed(e.quantity - e.remaining_quantity),
+            event_price: fixed(e.fill_price.unwrap_or(e.price)),
+            event_quantity: fixed(event_quantity),
+            trade_id: e.trade_id.map(|id| id as i64),
+            reason: e.reason.clone(),
+            sequence: e.sequence as i64,
+            timestamp: (e.timestamp_ns / 1_000_000) as i64,
+        }
+    }
+}
+
+fn fixed(value: Decimal) -> i64 {
+    (value * Decimal::from(1_000_000)).to_i64().unwrap_or(0)
+}
+
+fn clip(value: &str, max_chars: usize) -> String {
+    value.chars().take(max_chars).collect()
+}
+
+/// Appends the events to `order_events` and moves each order in `orders` to its
+/// latest state, in one statement. Events already recorded are skipped, and an
+/// order is only updated by an event newer than the one it reflects.
+/// Returns how many events were new.
+pub async fn write(client: &Client, rows: &[OrderEventRow]) -> Result<u64, tokio_postgres::Error> {
+    let column = |f: fn(&OrderEventRow) -> i64| rows.iter().map(f).collect::<Vec<i64>>();
+    let order_ids = column(|r| r.order_id);
+    let event_types: Vec<&str> = rows.iter().map(|r| r.event_type).collect();
+    let statuses: Vec<&str> = rows.iter().map(|r| r.status).collect();
+    let user_ids: Vec<&str> = rows.iter().map(|r| r.user_id.as_str()).collect();
+    let markets: Vec<&str> = rows.iter().map(|r| r.market.as_str()).collect();
+    let sides: Vec<&str> = rows.iter().map(|r| r.side.as_str()).collect();
+    let prices = column(|r| r.price);
+    let quantities = column(|r| r.quantity);
+    let filled = column(|r| r.filled_quantity);
+    let event_prices = column(|r| r.event_price);
+    let event_quantities = column(|r| r.event_quantity);
+    let trade_ids: Vec<Option<i64>> = rows.iter().map(|r| r.trade_id).collect();
+    let reasons: Vec<Option<&str>> = rows.iter().map(|r| r.reason.as_deref()).collect();
+    let sequences = column(|r| r.sequence);
+    let timestamps = column(|r| r.timestamp);
+
+    let row = client.query_one(
+        "WITH input AS (
+             SELECT * FROM UNNEST($1::BIGINT[], $2::VARCHAR[], $3::VARCHAR[], $4::VARCHAR[], $5::VARCHAR[], $6::VARCHAR[],
+                                  $7::BIGINT[], $8::BIGINT[], $9::BIGINT[], $10::BIGINT[], $11::BIGINT[], $12::BIGINT[],
+                                  $13::TEXT[], $14::BIGINT[], $15::BIGINT[])
+                 AS t(order_id, event_type, status, user_id, market, side, price, quantity, filled_quantity,
+                      event_price, event_quantity, trade_id, reason, sequence, timestamp)
+         ),
+         inserted AS (
+             INSERT INTO order_events (order_id, event_type, quantity, price, trade_id, reason, timestamp, sequence)
+             SELECT order_id, event_type, event_quantity, event_price, trade_id, reason, timestamp, sequence FROM input
+             ON CONFLICT (order_id, sequence) DO NOTHING
+             RETURNING order_id, sequence
+         ),
+         latest AS (
+             SELECT DISTINCT ON (order_id) input.*, min(input.timestamp) OVER (PARTITION BY order_id) AS first_seen
+             FROM input JOIN inserted USING (order_id, sequence)
+             ORDER BY order_id, sequence DESC
+         ),
+         updated AS (
+             INSERT INTO orders AS o (order_id, market, user_id, side, price, quantity, filled_quantity, status, created_at, updated_at, last_sequence)
+             SELECT order_id, market, user_id, side, price, quantity, filled_quantity, status, first_seen, timestamp, sequence FROM latest
+             ON CONFLICT (order_id) DO UPDATE SET
+                 filled_quantity = EXCLUDED.filled_quantity,
+                 status = EXCLUDED.status,
+                 updated_at = EXCLUDED.updated_at,
+                 last_sequence = EXCLUDED.last_sequence
+             -- An ID reused by an engine from before epoch order IDs is another order: keep this one
+             WHERE o.last_sequence < EXCLUDED.last_sequence AND o.user_id = EXCLUDED.user_id AND o.market = EXCLUDED.market
+         )
+         SELECT count(*) FROM inserted",
+        &[
+            &order_ids, &event_types, &statuses, &user_ids, &markets, &sides, &prices, &quantities, &filled,
+            &event_prices, &event_quantities, &trade_ids, &reasons, &sequences, &timestamps,
+        ],
+    ).await?;
+    Ok(row.get::<_, i64>(0) as u64)
+}
+
+/// Persists order events from `ORDER_EVENT_QUEUE` until the process exits. Uses
+/// its own connection so order history never holds up trade ingestion.
+pub async fn run(redis: RedisClient, consumer: Consumer, client: Client, config: BatchConfig) {
+    println!("🚀 Order history is watching {}...", consumer.queue.name);
+    loop {
+        let Some(claimed) = ingest::claim(&redis, &consumer, config).await else { continue };
+
+        let mut payloads = Vec::with_capacity(claimed.len());
+        let mut rows = Vec::with_capacity(claimed.len());
+        for raw in &claimed {
+            match serde_json::from_str::<OrderEvent>(raw) {
+                Ok(event) => {
+                    payloads.push(raw.as_str());
+                    rows.push(OrderEventRow::from_event(&event));
+                }
+                Err(e) => {
+                    eprintln!("❌ Failed to parse order event: {}", e);
+                    if let Err(e) = ingest::dead_letter(&redis, consumer.queue, raw, format!("unparseable: {}", e), 0).await {
+                        eprintln!("❌ Failed to dead-letter order event: {}", e);
+                    }
+                }
+            }
+        }
+
+        match write(&client, &rows).await {
+            Ok(inserted) => {
+                if let Err(e) = ingest::acknowledge(&redis, &consumer).await {
+                    eprintln!("❌ Failed to acknowledge order events: {}", e);
+                }
+                println!("📒 Persisted {} order event(s) ({} new)", rows.len(), inserted);
+            }
+            Err(e) => {
+                let Some(attempts) = ingest::write_failed(&redis, &consumer, config, &e, rows.len()).await else { continue };
+                let mut settled = true;
+                for (payload, row) in payloads.iter().zip(&rows) {
+                    let result = write(&client, std::slice::from_ref(row)).await.map(drop);
+                    settled = ingest::settle_alone(&redis, &consumer, payload, result, attempts).await;
+                    if !settled {
+                        break;
+                    }
+                }
+                if settled && let Err(e) = ingest::acknowledge(&redis, &consumer).await {
+                    eprintln!("❌ Failed to acknowledge order events: {}", e);
+                }
+            }
+        }
+    }
+}
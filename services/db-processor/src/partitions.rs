@@ -0,0 +1,260 @@
+use anyhow::{bail, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::TryStreamExt;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Duration;
+use tokio_postgres::{Client, GenericClient, IsolationLevel};
+
+use crate::ingest::COLUMNS;
+use crate::ledger;
+
+const DAY_MS: i64 = 86_400_000;
+const PREFIX: &str = "trades_p";
+/// How often the running processor creates and expires partitions
+pub const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Upkeep of `trades`' daily partitions (see migration 0013).
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    /// Days past today that get a partition ahead of time
+    pub days_ahead: i64,
+    /// Whole days of trades kept in Postgres before being archived; 0 keeps everything
+    pub retention_days: i64,
+    pub archive_dir: PathBuf,
+}
+
+impl PartitionConfig {
+    /// Reads TRADE_PARTITION_DAYS_AHEAD (7), TRADE_RETENTION_DAYS (0) and TRADE_ARCHIVE_DIR (archive/trades).
+    pub fn from_env() -> Self {
+        let days_ahead = std::env::var("TRADE_PARTITION_DAYS_AHEAD").ok().and_then(|v| v.parse().ok()).unwrap_or(7).max(1);
+        let retention_days = std::env::var("TRADE_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(0).max(0);
+        let archive_dir = std::env::var("TRADE_ARCHIVE_DIR").unwrap_or("archive/trades".into()).into();
+        Self { days_ahead, retention_days, archive_dir }
+    }
+}
+
+fn day_start(ms: i64) -> i64 {
+    ms - ms.rem_euclid(DAY_MS)
+}
+
+fn partition_name(day_start: i64) -> String {
+    let day = chrono::DateTime::from_timestamp_millis(day_start).expect("timestamp in range");
+    format!("{}{}", PREFIX, day.format("%Y%m%d"))
+}
+
+/// The first millisecond of a partition's day, from its name.
+fn partition_start(name: &str) -> Option<i64> {
+    let day = chrono::NaiveDate::parse_from_str(name.strip_prefix(PREFIX)?, "%Y%m%d").ok()?;
+    Some(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis())
+}
+
+/// Attached daily partitions as (name, day start), oldest first.
+async fn daily_partitions(client: &impl GenericClient) -> Result<Vec<(String, i64)>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT c.relname::TEXT FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = 'trades'::regclass ORDER BY c.relname",
+        &[],
+    ).await?;
+    Ok(rows
+        .iter()
+        .filter_map(|r| {
+            let name: String = r.get(0);
+            partition_start(&name).map(|start| (name, start))
+        })
+        .collect())
+}
+
+/// Trades before this time (Unix ms) have been archived out of `trades`; 0 if none have.
+pub async fn retention_horizon(client: &impl GenericClient) -> Result<i64, tokio_postgres::Error> {
+    client.query_one("SELECT COALESCE(MAX(range_end), 0) FROM trade_archives", &[]).await.map(|r| r.get(0))
+}
+
+/// Creates the partitions for today through `days_ahead` days from now that
+/// don't exist yet, and returns their names.
+pub async fn create_upcoming(client: &mut Client, now_ms: i64, days_ahead: i64) -> Result<Vec<String>, tokio_postgres::Error> {
+    let existing: HashSet<String> = daily_partitions(client).await?.into_iter().map(|(name, _)| name).collect();
+    let mut created = Vec::new();
+    for day in 0..=days_ahead {
+        let start = day_start(now_ms) + day * DAY_MS;
+        let name = partition_name(start);
+        if existing.contains(&name) {
+            continue;
+        }
+        let tx = client.transaction().await?;
+        tx.batch_execute(&format!("CREATE TABLE {} (LIKE trades INCLUDING DEFAULTS INCLUDING CONSTRAINTS)", name)).await?;
+        // Attaching fails while the default partition holds rows for the new range, so they move first
+        tx.execute(
+            &format!(
+                "WITH moved AS (DELETE FROM trades_default WHERE timestamp >= $1 AND timestamp < $2 RETURNING {cols})
+                 INSERT INTO {name} ({cols}) SELECT {cols} FROM moved",
+                cols = COLUMNS, name = name,
+            ),
+            &[&start, &(start + DAY_MS)],
+        ).await?;
+        tx.batch_execute(&format!("ALTER TABLE trades ATTACH PARTITION {} FOR VALUES FROM ({}) TO ({})", name, start, start + DAY_MS)).await?;
+        tx.commit().await?;
+        created.push(name);
+    }
+    Ok(created)
+}
+
+/// Archives and drops every partition that ended more than `retention_days`
+/// whole days before today. Returns (name, rows archived) for each.
+pub async fn archive_expired(client: &mut Client, now_ms: i64, config: &PartitionConfig) -> Result<Vec<(String, i64)>> {
+    if config.retention_days == 0 {
+        return Ok(Vec::new());
+    }
+    let cutoff = day_start(now_ms) - config.retention_days * DAY_MS;
+    let mut archived = Vec::new();
+    for (name, start) in daily_partitions(client).await? {
+        if start + DAY_MS > cutoff {
+            break;
+        }
+        let rows = archive_partition(client, &name, start, &config.archive_dir).await?;
+        archived.push((name, rows));
+    }
+    Ok(archived)
+}
+
+/// Writes a partition to `<dir>/<name>.csv.gz`, then detaches and drops it. The
+/// file is written from a read-only snapshot, off the async runtime, so ingestion
+/// isn't blocked meanwhile. The partition is only dropped if it still holds
+/// exactly the rows written; otherwise it stays, and the next run overwrites the file.
+async fn archive_partition(client: &mut Client, name: &str, start: i64, dir: &Path) -> Result<i64> {
+    let path = dir.join(format!("{}.csv.gz", name));
+    let partial = dir.join(format!("{}.csv.gz.partial", name));
+
+    // The snapshot keeps the count consistent with what COPY writes
+    let snapshot = client.build_transaction().isolation_level(IsolationLevel::RepeatableRead).read_only(true).start().await?;
+    let rows: i64 = snapshot.query_one(&format!("SELECT COUNT(*) FROM {}", name), &[]).await?.get(0);
+    let stream = snapshot.copy_out(&format!(
+        "COPY (SELECT {} FROM {} ORDER BY timestamp, trade_id) TO STDOUT WITH (FORMAT csv, HEADER)",
+        COLUMNS, name
+    )).await?;
+    let (chunks, received) = tokio::sync::mpsc::channel(16);
+    let writer = tokio::task::spawn_blocking({
+        let (dir, partial) = (dir.to_path_buf(), partial.clone());
+        move || write_gzip(&dir, &partial, received)
+    });
+    let mut stream = pin!(stream);
+    let copied: Result<(), tokio_postgres::Error> = async {
+        while let Some(chunk) = stream.try_next().await? {
+            if chunks.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }.await;
+    drop(chunks);
+    let written = writer.await?;
+    copied?;
+    written?;
+    snapshot.commit().await?;
+    tokio::fs::rename(&partial, &path).await?;
+
+    let tx = client.transaction().await?;
+    // Blocks late inserts so every row that gets dropped is in the file. Trades are
+    // only ever added, so an unchanged count means an unchanged partition
+    tx.batch_execute(&format!("LOCK TABLE {} IN EXCLUSIVE MODE", name)).await?;
+    let now_rows: i64 = tx.query_one(&format!("SELECT COUNT(*) FROM {}", name), &[]).await?.get(0);
+    if now_rows != rows {
+        bail!("{} gained {} trade(s) while it was being archived; it will be archived again next run", name, now_rows - rows);
+    }
+
+    // Every fill, settled or not yet, so the reconciler can keep matching them with settlements
+    tx.execute(
+        &format!(
+            "INSERT INTO archived_trades (trade_id, market, buyer_id, seller_id, quantity)
+             SELECT trade_id, market, buyer_id, seller_id, quantity FROM {} ON CONFLICT (trade_id) DO NOTHING",
+            name
+        ),
+        &[],
+    ).await?;
+    tx.execute(
+        "INSERT INTO trade_archives (partition_name, range_start, range_end, path, row_count, archived_at)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        &[&name, &start, &(start + DAY_MS), &path.display().to_string(), &rows, &chrono::Utc::now().timestamp_millis()],
+    ).await?;
+    // Positions can still be recomputed once these trades are gone
+    ledger::snapshot_partition(&tx, name).await?;
+    tx.batch_execute(&format!("ALTER TABLE trades DETACH PARTITION {name}; DROP TABLE {name}", name = name)).await?;
+    tx.commit().await?;
+    Ok(rows)
+}
+
+/// Gzips the CSV chunks from `chunks` into `partial` and syncs it to disk.
+fn write_gzip(dir: &Path, partial: &Path, mut chunks: tokio::sync::mpsc::Receiver<impl AsRef<[u8]>>) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut file = GzEncoder::new(std::fs::File::create(partial)?, Compression::default());
+    while let Some(chunk) = chunks.blocking_recv() {
+        file.write_all(chunk.as_ref())?;
+    }
+    file.finish()?.sync_all()
+}
+
+/// One maintenance pass: create upcoming partitions, then archive expired ones.
+pub async fn maintain(client: &mut Client, config: &PartitionConfig) -> Result<()> {
+    let now = chrono::Utc::now().timestamp_millis();
+    for name in create_upcoming(client, now, config.days_ahead).await? {
+        println!("🗂️ Created partition {}", name);
+    }
+    for (name, rows) in archive_expired(client, now, config).await? {
+        println!("📦 Archived {} ({} trades) to {}", name, rows, config.archive_dir.display());
+    }
+    // Only trades outside every daily partition end up here: far-future timestamps
+    // or late arrivals for days that were already archived
+    let stray: i64 = client.query_one("SELECT COUNT(*) FROM trades_default", &[]).await?.get(0);
+    if stray > 0 {
+        eprintln!("⚠️ {} trade(s) in trades_default fall outside the daily partitions", stray);
+    }
+    Ok(())
+}
+
+/// Runs `maintain` now and every MAINTENANCE_INTERVAL, on its own connection.
+pub async fn run(mut client: Client, config: PartitionConfig) {
+    loop {
+        if let Err(e) = maintain(&mut client, &config).await {
+            eprintln!("❌ Partition maintenance failed: {}", e);
+        }
+        tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+    }
+}
+
+/// `db-processor partitions maintain` runs one maintenance pass; `db-processor partitions status`
+/// lists the daily partitions and archived ones.
+pub async fn run_partitions_cli(client: &mut Client, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("maintain") => maintain(client, &PartitionConfig::from_env()).await?,
+        Some("status") => {
+            for (name, _) in daily_partitions(client).await? {
+                let rows: i64 = client.query_one(&format!("SELECT COUNT(*) FROM {}", name), &[]).await?.get(0);
+                println!("🗂️ {} ({} trades)", name, rows);
+            }
+            let archives = client.query("SELECT partition_name, row_count, path FROM trade_archives ORDER BY range_start", &[]).await?;
+            for row in archives {
+                println!("📦 {} ({} trades) archived to {}", row.get::<_, String>(0), row.get::<_, i64>(1), row.get::<_, String>(2));
+            }
+        }
+        _ => eprintln!("Usage: db-processor partitions <maintain|status>"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_names_round_trip_to_their_utc_day() {
+        // 2024-02-29T13:45:00Z
+        let ms = 1_709_214_300_000;
+        let name = partition_name(day_start(ms));
+        assert_eq!(name, "trades_p20240229");
+        assert_eq!(partition_start(&name), Some(day_start(ms)));
+        assert_eq!(partition_start("trades_default"), None);
+    }
+}
@@ -0,0 +1,34 @@
+use fred::prelude::*;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tokio_postgres::{Client, NoTls};
+
+/// A connection to TEST_DATABASE_URL, migrated to the latest schema. Tests that need
+/// Postgres are `#[ignore]`d so plain `cargo test` runs without one; `--ignored` runs
+/// them, and they fail rather than pass unchecked if the variable isn't set.
+pub async fn database() -> Client {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set for Postgres tests");
+    let (mut client, connection) = tokio_postgres::connect(&url, NoTls).await.expect("TEST_DATABASE_URL is reachable");
+    tokio::spawn(connection);
+    db_migrations::migrate(&mut client).await.expect("test database migrates");
+    client
+}
+
+/// Negative, so rows tests leave in shared tables never clash with real trade IDs.
+pub fn unique_trade_id() -> i64 {
+    static NEXT: AtomicI64 = AtomicI64::new(0);
+    let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() & 0xffff_ffff_ffff;
+    -(nanos * 1024 + NEXT.fetch_add(1, Ordering::Relaxed) % 1024) - 1
+}
+
+/// A connection to TEST_REDIS_URL (see `database`).
+pub async fn redis() -> RedisClient {
+    let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is set for Redis tests");
+    let redis = Builder::from_config(RedisConfig::from_url(&url).expect("TEST_REDIS_URL is valid")).build().unwrap();
+    redis.init().await.expect("TEST_REDIS_URL is reachable");
+    redis
+}
+
+/// A name no other test run uses, for keys tests create.
+pub fn unique_name(prefix: &str) -> &'static str {
+    Box::leak(format!("{}_{}", prefix, -unique_trade_id()).into_boxed_str())
+}
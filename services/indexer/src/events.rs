@@ -0,0 +1,117 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::Engine;
+use hybrid_perp_dex::events::{CollateralDeposited, EngineSignerRotated, TradeSettled};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// An event the program emitted, decoded from its `Program data:` log line.
+pub enum ProgramEvent {
+    TradeSettled(TradeSettled),
+    CollateralDeposited(CollateralDeposited),
+    EngineSignerRotated(EngineSignerRotated),
+}
+
+impl ProgramEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            ProgramEvent::TradeSettled(_) => "trade_settled",
+            ProgramEvent::CollateralDeposited(_) => "collateral_deposited",
+            ProgramEvent::EngineSignerRotated(_) => "engine_signer_rotated",
+        }
+    }
+
+    pub fn trade_id(&self) -> Option<u64> {
+        match self {
+            ProgramEvent::TradeSettled(e) => Some(e.trade_id),
+            _ => None,
+        }
+    }
+
+    /// The event's fields as stored in `program_events.data`. Amounts stay integers in
+    /// their on-chain units; pubkeys are base58 and markets their trimmed name.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ProgramEvent::TradeSettled(e) => json!({
+                "trade_id": e.trade_id,
+                "market": market_name(&e.market),
+                "buyer": e.buyer.to_string(),
+                "seller": e.seller.to_string(),
+                "price": e.price,
+                "quantity": e.quantity,
+                "buyer_nonce": e.buyer_nonce,
+                "seller_nonce": e.seller_nonce,
+            }),
+            ProgramEvent::CollateralDeposited(e) => json!({
+                "owner": e.owner.to_string(),
+                "amount": e.amount,
+                "collateral": e.collateral,
+            }),
+            ProgramEvent::EngineSignerRotated(e) => json!({
+                "current_signer": e.current_signer.to_string(),
+                "pending_signer": e.pending_signer.to_string(),
+                "activation_slot": e.activation_slot,
+            }),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let (discriminator, mut body) = data.split_at(8);
+        let event = match discriminator {
+            d if d == TradeSettled::DISCRIMINATOR => ProgramEvent::TradeSettled(TradeSettled::deserialize(&mut body).ok()?),
+            d if d == CollateralDeposited::DISCRIMINATOR => {
+                ProgramEvent::CollateralDeposited(CollateralDeposited::deserialize(&mut body).ok()?)
+            }
+            d if d == EngineSignerRotated::DISCRIMINATOR => {
+                ProgramEvent::EngineSignerRotated(EngineSignerRotated::deserialize(&mut body).ok()?)
+            }
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Events emitted by `program_id` itself, in log order. `Program data:` lines
+/// belong to whichever program is executing, so invocations are tracked to skip
+/// data logged by programs it calls or that call it.
+pub fn decode_logs(program_id: &Pubkey, logs: &[String]) -> Vec<ProgramEvent> {
+    let ours = program_id.to_string();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+    for line in logs {
+        if let Some(data) = line.strip_prefix(PROGRAM_DATA) {
+            if stack.last() == Some(&ours.as_str())
+                && let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(data)
+            {
+                match ProgramEvent::decode(&bytes) {
+                    Some(event) => events.push(event),
+                    None => eprintln!("⚠️ Skipping unrecognised event data: {}", data),
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(id), Some("invoke")) => stack.push(id),
+                (Some(_), Some("success" | "failed:")) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
+
+fn market_name(market: &[u8]) -> String {
+    String::from_utf8_lossy(market).trim_end_matches('\0').to_string()
+}
+
+/// The log line the program writes for `event`, as Anchor's `emit!` does.
+#[cfg(test)]
+pub fn program_data_log(event: &impl anchor_lang::Event) -> String {
+    format!("{}{}", PROGRAM_DATA, base64::engine::general_purpose::STANDARD.encode(event.data()))
+}
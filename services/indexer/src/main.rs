@@ -0,0 +1,49 @@
+mod events;
+mod rpc;
+mod store;
+mod sync;
+
+use rpc::RpcSource;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::time::Duration;
+use store::PgStore;
+use tokio_postgres::NoTls;
+use anyhow::Result;
+use dotenvy::dotenv;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let rpc_url = std::env::var("SOLANA_RPC_URL").unwrap_or("http://127.0.0.1:8899".into());
+    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
+    let program_id = Pubkey::from_str(&std::env::var("PROGRAM_ID")?)?;
+    // Where a first run starts backfilling from; later runs resume from the stored cursor
+    let start_slot: u64 = std::env::var("INDEXER_START_SLOT").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+    let poll_ms: u64 = std::env::var("INDEXER_POLL_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(2000);
+
+    // tokio-postgres requires spawning the connection task separately
+    let (client, connection) = tokio_postgres::connect(&db_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("❌ Postgres connection error: {}", e);
+        }
+    });
+    // db-processor owns migrations; the schema must already include program_events
+    db_migrations::ensure_current(&client).await?;
+
+    let source = RpcSource { rpc: RpcClient::new(rpc_url), program_id };
+    let mut store = PgStore { client, program_id: program_id.to_string() };
+    println!("🔎 Indexing events of {} from slot {}...", program_id, start_slot);
+
+    loop {
+        match sync::run_once(&source, &mut store, &program_id, start_slot).await {
+            Ok(0) => {}
+            Ok(n) => println!("📜 Indexed {} transaction(s)", n),
+            // Everything recorded so far is kept; the next poll resumes after it
+            Err(e) => eprintln!("❌ Indexing failed, retrying: {}", e),
+        }
+        tokio::time::sleep(Duration::from_millis(poll_ms)).await;
+    }
+}
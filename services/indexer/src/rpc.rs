@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::UiTransactionEncoding;
+use std::str::FromStr;
+
+use crate::sync::{ChainSource, ChainTransaction, SignatureInfo};
+
+/// Reads the program's transactions from an RPC node. Only finalized transactions
+/// are indexed, so nothing stored can be rolled back by a fork.
+pub struct RpcSource {
+    pub rpc: RpcClient,
+    pub program_id: Pubkey,
+}
+
+impl ChainSource for RpcSource {
+    async fn signatures(&self, before: Option<&str>, until: Option<&str>, limit: usize) -> Result<Vec<SignatureInfo>> {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before: before.map(Signature::from_str).transpose()?,
+            until: until.map(Signature::from_str).transpose()?,
+            limit: Some(limit),
+            commitment: Some(CommitmentConfig::finalized()),
+        };
+        let page = self.rpc.get_signatures_for_address_with_config(&self.program_id, config).await?;
+        Ok(page
+            .into_iter()
+            .map(|s| SignatureInfo { signature: s.signature, slot: s.slot, failed: s.err.is_some() })
+            .collect())
+    }
+
+    async fn transaction(&self, signature: &str) -> Result<ChainTransaction> {
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(CommitmentConfig::finalized()),
+            max_supported_transaction_version: Some(0),
+        };
+        let tx = self.rpc.get_transaction_with_config(&Signature::from_str(signature)?, config).await?;
+        let meta = tx.transaction.meta.ok_or_else(|| anyhow!("Transaction {} has no status meta", signature))?;
+        let logs = match meta.log_messages {
+            OptionSerializer::Some(logs) => logs,
+            _ => Vec::new(),
+        };
+        Ok(ChainTransaction { block_time: tx.block_time, logs })
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use tokio_postgres::Client;
+
+use crate::sync::{Cursor, EventStore, IndexedTransaction};
+
+/// Stores events in `program_events` and the cursor in `indexer_cursor`.
+pub struct PgStore {
+    pub client: Client,
+    pub program_id: String,
+}
+
+impl EventStore for PgStore {
+    async fn cursor(&self) -> Result<Option<Cursor>> {
+        let row = self
+            .client
+            .query_opt("SELECT slot, signature FROM indexer_cursor WHERE program_id = $1", &[&self.program_id])
+            .await?;
+        Ok(row.map(|r| Cursor { slot: r.get::<_, i64>(0) as u64, signature: r.get(1) }))
+    }
+
+    async fn record(&mut self, tx: &IndexedTransaction) -> Result<()> {
+        let slot = tx.slot as i64;
+        let db_tx = self.client.transaction().await?;
+        for (index, event) in tx.events.iter().enumerate() {
+            // Re-indexing after a lost cursor update finds the rows already there
+            db_tx.execute(
+                "INSERT INTO program_events (signature, event_index, slot, block_time, event_type, trade_id, data)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)
+                 ON CONFLICT (signature, event_index) DO NOTHING",
+                &[
+                    &tx.signature,
+                    &(index as i32),
+                    &slot,
+                    &tx.block_time,
+                    &event.event_type(),
+                    &event.trade_id().map(|id| id as i64),
+                    &event.to_json(),
+                ],
+            ).await?;
+        }
+        db_tx.execute(
+            "INSERT INTO indexer_cursor (program_id, slot, signature, updated_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (program_id) DO UPDATE SET slot = EXCLUDED.slot, signature = EXCLUDED.signature, updated_at = EXCLUDED.updated_at",
+            &[&self.program_id, &slot, &tx.signature, &chrono::Utc::now().timestamp_millis()],
+        ).await?;
+        db_tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::{decode_logs, ProgramEvent};
+
+/// Signatures requested per `getSignaturesForAddress` page (the RPC maximum).
+pub const PAGE_SIZE: usize = 1000;
+
+/// A transaction that touched the program, as listed by `getSignaturesForAddress`.
+#[derive(Debug, Clone)]
+pub struct SignatureInfo {
+    pub signature: String,
+    pub slot: u64,
+    pub failed: bool,
+}
+
+/// The parts of a fetched transaction the indexer needs.
+#[derive(Debug, Clone)]
+pub struct ChainTransaction {
+    pub block_time: Option<i64>,
+    pub logs: Vec<String>,
+}
+
+/// Read access to the chain: an RPC node in production, a stand-in in tests.
+pub trait ChainSource {
+    /// Up to `limit` signatures for the program, newest first, older than `before`
+    /// and newer than `until` when given (the `getSignaturesForAddress` contract).
+    async fn signatures(&self, before: Option<&str>, until: Option<&str>, limit: usize) -> Result<Vec<SignatureInfo>>;
+    async fn transaction(&self, signature: &str) -> Result<ChainTransaction>;
+}
+
+/// The newest transaction already processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub slot: u64,
+    pub signature: String,
+}
+
+/// A processed transaction and the events it emitted, possibly none.
+pub struct IndexedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub events: Vec<ProgramEvent>,
+}
+
+/// Where indexed events go. `record` must store the events and advance the cursor
+/// to the transaction atomically, so a restart neither skips nor repeats it.
+pub trait EventStore {
+    async fn cursor(&self) -> Result<Option<Cursor>>;
+    async fn record(&mut self, tx: &IndexedTransaction) -> Result<()>;
+}
+
+/// Indexes every transaction after the stored cursor, oldest first, and returns how
+/// many were processed. Without a cursor, history is backfilled down to `start_slot`.
+pub async fn run_once(
+    source: &impl ChainSource,
+    store: &mut impl EventStore,
+    program_id: &Pubkey,
+    start_slot: u64,
+) -> Result<usize> {
+    let cursor = store.cursor().await?;
+    let pending = pending_signatures(source, cursor.as_ref(), start_slot).await?;
+
+    for info in &pending {
+        // Failed transactions had their logs rolled back with everything else
+        let (block_time, events) = if info.failed {
+            (None, Vec::new())
+        } else {
+            let tx = source.transaction(&info.signature).await?;
+            (tx.block_time, decode_logs(program_id, &tx.logs))
+        };
+        store.record(&IndexedTransaction { signature: info.signature.clone(), slot: info.slot, block_time, events }).await?;
+    }
+    Ok(pending.len())
+}
+
+/// Pages backwards from the newest signature until the cursor (or `start_slot`)
+/// and returns what's new, oldest first. A first run over a long history holds all
+/// of its signatures in memory before indexing any; set a recent start slot to bound it.
+async fn pending_signatures(source: &impl ChainSource, cursor: Option<&Cursor>, start_slot: u64) -> Result<Vec<SignatureInfo>> {
+    let until = cursor.map(|c| c.signature.as_str());
+    let mut pending: Vec<SignatureInfo> = Vec::new();
+    loop {
+        let before = pending.last().map(|s| s.signature.clone());
+        // Nodes may return short pages, so only an empty one means there's no more
+        let page = source.signatures(before.as_deref(), until, PAGE_SIZE).await?;
+        let exhausted = page.is_empty();
+        let mut reached_start = false;
+        for info in page {
+            if info.slot < start_slot {
+                reached_start = true;
+                break;
+            }
+            pending.push(info);
+        }
+        if exhausted || reached_start {
+            break;
+        }
+    }
+    pending.reverse();
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::program_data_log;
+    use hybrid_perp_dex::events::{CollateralDeposited, TradeSettled};
+
+    /// Stands in for a validator's RPC: transactions in the order they landed.
+    struct LocalChain {
+        txs: Vec<(SignatureInfo, ChainTransaction)>,
+    }
+
+    impl LocalChain {
+        fn push(&mut self, slot: u64, failed: bool, logs: Vec<String>) {
+            let signature = format!("sig{}", self.txs.len());
+            self.txs.push((SignatureInfo { signature, slot, failed }, ChainTransaction { block_time: Some(slot as i64), logs }));
+        }
+    }
+
+    impl ChainSource for LocalChain {
+        async fn signatures(&self, before: Option<&str>, until: Option<&str>, limit: usize) -> Result<Vec<SignatureInfo>> {
+            let position = |sig: &str| self.txs.iter().position(|(s, _)| s.signature == sig).unwrap();
+            let end = before.map_or(self.txs.len(), position);
+            let start = until.map_or(0, |sig| position(sig) + 1);
+            // Short pages so paging is exercised without thousands of transactions
+            Ok(self.txs[start.min(end)..end].iter().rev().take(limit.min(2)).map(|(s, _)| s.clone()).collect())
+        }
+
+        async fn transaction(&self, signature: &str) -> Result<ChainTransaction> {
+            Ok(self.txs.iter().find(|(s, _)| s.signature == signature).unwrap().1.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryStore {
+        cursor: Option<Cursor>,
+        events: Vec<(String, u64, String)>,
+    }
+
+    impl EventStore for MemoryStore {
+        async fn cursor(&self) -> Result<Option<Cursor>> {
+            Ok(self.cursor.clone())
+        }
+
+        async fn record(&mut self, tx: &IndexedTransaction) -> Result<()> {
+            for event in &tx.events {
+                self.events.push((tx.signature.clone(), tx.slot, event.event_type().to_string()));
+            }
+            self.cursor = Some(Cursor { slot: tx.slot, signature: tx.signature.clone() });
+            Ok(())
+        }
+    }
+
+    fn invocation(program: &Pubkey, data: Vec<String>) -> Vec<String> {
+        let mut logs = vec![format!("Program {} invoke [1]", program)];
+        logs.extend(data);
+        logs.push(format!("Program {} success", program));
+        logs
+    }
+
+    fn trade(trade_id: u64) -> String {
+        program_data_log(&TradeSettled {
+            trade_id,
+            market: *b"SOL_USDC\0\0\0\0\0\0\0\0",
+            buyer: Pubkey::new_unique(),
+            seller: Pubkey::new_unique(),
+            price: 150_000_000,
+            quantity: 1_000_000,
+            buyer_nonce: 0,
+            seller_nonce: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn backfills_then_resumes_after_the_last_processed_transaction() {
+        let program = hybrid_perp_dex::id();
+        let other = Pubkey::new_unique();
+        let mut chain = LocalChain { txs: Vec::new() };
+        chain.push(5, false, invocation(&program, vec![trade(1)]));
+        // Before the start slot on a first run
+        chain.push(9, false, invocation(&program, vec![trade(2)]));
+        chain.push(10, false, invocation(&program, vec![trade(3), trade(4)]));
+        chain.push(11, true, invocation(&program, vec![trade(5)]));
+        // Data logged by another program in the same transaction isn't ours
+        let mut logs = invocation(&other, vec![trade(6)]);
+        logs.extend(invocation(&program, vec![program_data_log(&CollateralDeposited {
+            owner: Pubkey::new_unique(),
+            amount: 10,
+            collateral: 10,
+        })]));
+        chain.push(12, false, logs);
+
+        let mut store = MemoryStore::default();
+        assert_eq!(run_once(&chain, &mut store, &program, 9).await.unwrap(), 4);
+        let types: Vec<(u64, &str)> = store.events.iter().map(|(_, slot, t)| (*slot, t.as_str())).collect();
+        assert_eq!(types, [(9, "trade_settled"), (10, "trade_settled"), (10, "trade_settled"), (12, "collateral_deposited")]);
+        assert_eq!(store.cursor, Some(Cursor { slot: 12, signature: "sig4".into() }));
+
+        // Nothing new: nothing processed again
+        assert_eq!(run_once(&chain, &mut store, &program, 9).await.unwrap(), 0);
+
+        chain.push(13, false, invocation(&program, vec![trade(7)]));
+        chain.push(13, false, invocation(&program, vec![trade(8)]));
+        chain.push(14, false, invocation(&program, vec![trade(9)]));
+        assert_eq!(run_once(&chain, &mut store, &program, 9).await.unwrap(), 3);
+        assert_eq!(store.events.len(), 7);
+        assert_eq!(store.cursor, Some(Cursor { slot: 14, signature: "sig7".into() }));
+    }
+}
@@ -0,0 +1,62 @@
+use fred::prelude::*;
+
+/// What the engine needs from Redis: counters for sequence numbers and ID epochs,
+/// the queues downstream services consume and the ack channel. RedisClient in
+/// production, a stand-in in tests.
+pub trait Broker {
+    /// INCRBY: adds `by` to the counter at `key` and returns the new value.
+    async fn increment(&self, key: &str, by: u64) -> RedisResult<u64>;
+    /// LPUSH: consumers pop from the other end, so `queue` is FIFO.
+    async fn push(&self, queue: &str, payload: String);
+    async fn publish(&self, channel: &str, payload: String);
+}
+
+impl Broker for RedisClient {
+    async fn increment(&self, key: &str, by: u64) -> RedisResult<u64> {
+        self.incr_by(key, by as i64).await
+    }
+
+    async fn push(&self, queue: &str, payload: String) {
+        let _ = self.lpush::<i64, _, _>(queue, payload).await;
+    }
+
+    async fn publish(&self, channel: &str, payload: String) {
+        let _ = PubsubInterface::publish::<i64, _, _>(self, channel, payload).await;
+    }
+}
+
+/// Keeps counters, queues and published messages in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryBroker {
+    pub counters: std::cell::RefCell<std::collections::HashMap<String, u64>>,
+    // Oldest message first, for both queues and channels
+    pub messages: std::cell::RefCell<std::collections::HashMap<String, Vec<String>>>,
+}
+
+#[cfg(test)]
+impl MemoryBroker {
+    /// Takes everything sent to `key` so far, oldest first, decoded as `T`.
+    pub fn drain<T: serde::de::DeserializeOwned>(&self, key: &str) -> Vec<T> {
+        let messages = self.messages.borrow_mut().remove(key).unwrap_or_default();
+        messages.iter().map(|m| serde_json::from_str(m).unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+impl Broker for MemoryBroker {
+    async fn increment(&self, key: &str, by: u64) -> RedisResult<u64> {
+        let mut counters = self.counters.borrow_mut();
+        let value = counters.entry(key.to_string()).or_default();
+        *value += by;
+        Ok(*value)
+    }
+
+    async fn push(&self, queue: &str, payload: String) {
+        self.messages.borrow_mut().entry(queue.to_string()).or_default().push(payload);
+    }
+
+    async fn publish(&self, channel: &str, payload: String) {
+        self.push(channel, payload).await;
+    }
+}
@@ -1,84 +1,377 @@
-use std::collections::{BTreeMap, VecDeque};
-use common_utils::{Order, MatchResult};
+mod broker;
+mod sequencer;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use common_utils::{
+    CancelAck, DeadManSwitchAck, EngineAck, EngineCommand, MatchResult, Order, OrderAck, OrderEvent, OrderEventType, OrderStatus,
+};
 use fred::prelude::*;
 use rust_decimal::Decimal;
 use std::env;
 use dotenvy::dotenv;
 use anyhow::Result;
+use broker::Broker;
+use sequencer::{EpochIds, EventStamp, Sequencer};
 
 struct Engine {
+    market: String,
     bids: BTreeMap<Decimal, VecDeque<Order>>,
     asks: BTreeMap<Decimal, VecDeque<Order>>,
-    trade_counter: u64,
+    trade_ids: EpochIds,
+    order_ids: EpochIds,
+    // Quantity each resting order was placed with, for its lifecycle events
+    placed_quantities: HashMap<u64, Decimal>,
+    // Sequence number and timestamp for every match and ack
+    sequencer: Sequencer,
+    // Per-user deadlines after which all of the user's orders are cancelled
+    dead_man_switches: HashMap<String, Instant>,
 }
 
 impl Engine {
-    async fn match_order(&mut self, order: Order, redis: &RedisClient) {
-        if order.side == "BUY" {
-            self.process_buy(order, redis).await;
+    fn new(market: String, sequencer: Sequencer, trade_ids: EpochIds, order_ids: EpochIds) -> Self {
+        Engine {
+            market,
+            sequencer,
+            trade_ids,
+            order_ids,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            placed_quantities: HashMap::new(),
+            dead_man_switches: HashMap::new(),
+        }
+    }
+
+    async fn handle_command(&mut self, cmd: EngineCommand, redis: &impl Broker) {
+        match cmd {
+            EngineCommand::PlaceOrder(order) => {
+                // Orders pushed without a correlation ID are fire-and-forget
+                let wants_ack = order.request_id.is_some();
+                let ack = self.place_order(order, redis).await;
+                if wants_ack {
+                    Self::publish_ack(&EngineAck::Order(ack), redis).await;
+                }
+            }
+            EngineCommand::PlaceBatch { request_id, orders } => {
+                let rejection = orders
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, order)| self.validate(order).err().map(|reason| format!("Order {}: {}", i, reason)));
+
+                let mut acks = Vec::with_capacity(orders.len());
+                match rejection {
+                    Some(reason) => {
+                        println!("⛔ Batch Rejected: {}", reason);
+                        for order in orders {
+                            acks.push(self.reject(request_id.clone(), order, reason.clone(), redis).await);
+                        }
+                    }
+                    None => {
+                        for mut order in orders {
+                            order.request_id = Some(request_id.clone());
+                            acks.push(self.place_order(order, redis).await);
+                        }
+                    }
+                }
+                Self::publish_ack(&EngineAck::Batch { request_id, orders: acks }, redis).await;
+            }
+            EngineCommand::MassCancel { request_id, user_id, market, side } => {
+                let cancelled_order_ids = if market.as_ref().is_some_and(|m| *m != self.market) {
+                    Vec::new()
+                } else {
+                    self.cancel_where(|o| o.user_id == user_id && side.as_ref().is_none_or(|s| *s == o.side), "mass_cancel", redis)
+                        .await
+                };
+                println!("🧹 Cancelled {} orders for {}", cancelled_order_ids.len(), user_id);
+                let stamp = self.sequencer.stamp(redis).await;
+                let ack = CancelAck {
+                    request_id,
+                    cancelled_order_ids,
+                    sequence: stamp.sequence,
+                    timestamp_ns: stamp.timestamp_ns,
+                };
+                Self::publish_ack(&EngineAck::Cancel(ack), redis).await;
+            }
+            EngineCommand::ArmDeadManSwitch { request_id, user_id, timeout_ms } => {
+                let armed = timeout_ms > 0;
+                if armed {
+                    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+                    self.dead_man_switches.insert(user_id.clone(), deadline);
+                } else {
+                    self.dead_man_switches.remove(&user_id);
+                }
+                let stamp = self.sequencer.stamp(redis).await;
+                let ack = DeadManSwitchAck {
+                    request_id,
+                    user_id,
+                    armed,
+                    timeout_ms,
+                    sequence: stamp.sequence,
+                    timestamp_ns: stamp.timestamp_ns,
+                };
+                Self::publish_ack(&EngineAck::DeadManSwitch(ack), redis).await;
+            }
+        }
+    }
+
+    /// Cancels every order of users whose dead man's switch ran out, then disarms it.
+    async fn expire_dead_man_switches(&mut self, redis: &impl Broker) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .dead_man_switches
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(user_id, _)| user_id.clone())
+            .collect();
+
+        for user_id in expired {
+            self.dead_man_switches.remove(&user_id);
+            let cancelled = self.cancel_where(|o| o.user_id == user_id, "dead_man_switch", redis).await;
+            println!("⏰ Dead man's switch fired for {}: cancelled {} orders", user_id, cancelled.len());
+        }
+    }
+
+    async fn place_order(&mut self, mut order: Order, redis: &impl Broker) -> OrderAck {
+        let request_id = order.request_id.take().unwrap_or_default();
+
+        if let Err(reason) = self.validate(&order) {
+            println!("⛔ Order Rejected: {}", reason);
+            return self.reject(request_id, order, reason, redis).await;
+        }
+
+        order.order_id = self.order_ids.next(redis).await;
+        let order_id = order.order_id;
+        self.placed_quantities.insert(order_id, order.quantity);
+        let stamp = self.sequencer.stamp(redis).await;
+        let placed = Self::order_event(&order, order.quantity, OrderEventType::Placed, OrderStatus::Resting, stamp);
+        Self::publish_order_event(&placed, redis).await;
+
+        let (fills, remaining) = if order.side == "BUY" {
+            self.process_buy(order, redis).await
         } else {
-            self.process_sell(order, redis).await;
+            self.process_sell(order, redis).await
+        };
+
+        let status = if remaining.is_zero() {
+            OrderStatus::Filled
+        } else if fills.is_empty() {
+            OrderStatus::Resting
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+
+        // Stamped after its fills, so the ack sorts after the trades it reports
+        let stamp = self.sequencer.stamp(redis).await;
+        OrderAck {
+            request_id,
+            order_id: Some(order_id),
+            status,
+            fills,
+            remaining_quantity: remaining,
+            reason: None,
+            sequence: stamp.sequence,
+            timestamp_ns: stamp.timestamp_ns,
         }
     }
 
-    async fn process_buy(&mut self, mut buy_order: Order, redis: &RedisClient) {
+    /// Rejected orders still get an ID so they show up in the order history.
+    async fn reject(&mut self, request_id: String, mut order: Order, reason: String, redis: &impl Broker) -> OrderAck {
+        order.order_id = self.order_ids.next(redis).await;
+        let stamp = self.sequencer.stamp(redis).await;
+        let mut event = Self::order_event(&order, order.quantity, OrderEventType::Rejected, OrderStatus::Rejected, stamp);
+        event.reason = Some(reason.clone());
+        Self::publish_order_event(&event, redis).await;
+        Self::rejected(request_id, &order, reason, stamp)
+    }
+
+    fn rejected(request_id: String, order: &Order, reason: String, stamp: EventStamp) -> OrderAck {
+        OrderAck {
+            request_id,
+            order_id: Some(order.order_id),
+            status: OrderStatus::Rejected,
+            fills: Vec::new(),
+            remaining_quantity: order.quantity,
+            reason: Some(reason),
+            sequence: stamp.sequence,
+            timestamp_ns: stamp.timestamp_ns,
+        }
+    }
+
+    fn validate(&self, order: &Order) -> Result<(), String> {
+        if order.market != self.market {
+            return Err(format!("Unknown market '{}'", order.market));
+        }
+        if order.side != "BUY" && order.side != "SELL" {
+            return Err(format!("Unknown side '{}'", order.side));
+        }
+        if order.price <= Decimal::ZERO {
+            return Err("Price must be positive".into());
+        }
+        if order.quantity <= Decimal::ZERO {
+            return Err("Quantity must be positive".into());
+        }
+        Ok(())
+    }
+
+    /// Removes every resting order matching `pred` and returns their IDs.
+    async fn cancel_where(&mut self, pred: impl Fn(&Order) -> bool, reason: &str, redis: &impl Broker) -> Vec<u64> {
+        let mut cancelled = Vec::new();
+        for book in [&mut self.bids, &mut self.asks] {
+            book.retain(|_, level| {
+                level.retain(|o| {
+                    if pred(o) {
+                        cancelled.push(o.clone());
+                        false
+                    } else {
+                        true
+                    }
+                });
+                !level.is_empty()
+            });
+        }
+
+        let mut ids = Vec::with_capacity(cancelled.len());
+        for order in cancelled {
+            let placed = self.placed_quantities.remove(&order.order_id).unwrap_or(order.quantity);
+            let stamp = self.sequencer.stamp(redis).await;
+            let mut event = Self::order_event(&order, placed, OrderEventType::Cancelled, OrderStatus::Cancelled, stamp);
+            event.reason = Some(reason.to_string());
+            Self::publish_order_event(&event, redis).await;
+            ids.push(order.order_id);
+        }
+        ids
+    }
+
+    /// Returns the fills generated by the order and its unfilled quantity.
+    async fn process_buy(&mut self, mut buy_order: Order, redis: &impl Broker) -> (Vec<MatchResult>, Decimal) {
+        let mut fills = Vec::new();
         while let Some((&price, orders)) = self.asks.iter_mut().next() {
             if price > buy_order.price { break; }
             while let Some(mut ask) = orders.pop_front() {
                 let fill_qty = buy_order.quantity.min(ask.quantity);
-                self.trade_counter += 1;
+                let trade_id = self.trade_ids.next(redis).await;
+                let stamp = self.sequencer.stamp(redis).await;
                 let res = MatchResult {
-                    trade_id: self.trade_counter,
+                    trade_id,
+                    market: self.market.clone(),
                     price,
                     quantity: fill_qty,
                     buyer_id: buy_order.user_id.clone(),
                     seller_id: ask.user_id.clone(),
+                    sequence: stamp.sequence,
+                    timestamp_ns: stamp.timestamp_ns,
                 };
 
-                Self::broadcast_match(res, redis).await;
+                Self::broadcast_match(res.clone(), redis).await;
 
                 buy_order.quantity -= fill_qty;
+                ask.quantity -= fill_qty;
+                Self::publish_fill(&mut self.placed_quantities, &ask, &res, redis).await;
+                Self::publish_fill(&mut self.placed_quantities, &buy_order, &res, redis).await;
+                fills.push(res);
                 if !ask.quantity.is_zero() { orders.push_front(ask); }
                 if buy_order.quantity.is_zero() { break; }
             }
             if orders.is_empty() { self.asks.remove(&price); }
-            if buy_order.quantity.is_zero() { return; }
+            if buy_order.quantity.is_zero() { return (fills, Decimal::ZERO); }
         }
+        let remaining = buy_order.quantity;
         self.bids.entry(buy_order.price).or_default().push_back(buy_order);
+        (fills, remaining)
     }
 
-    async fn process_sell(&mut self, mut sell_order: Order, redis: &RedisClient) {
+    /// Returns the fills generated by the order and its unfilled quantity.
+    async fn process_sell(&mut self, mut sell_order: Order, redis: &impl Broker) -> (Vec<MatchResult>, Decimal) {
+        let mut fills = Vec::new();
         while let Some((&price, orders)) = self.bids.iter_mut().next_back() {
             if price < sell_order.price { break; }
             while let Some(mut bid) = orders.pop_front() {
                 let fill_qty = sell_order.quantity.min(bid.quantity);
-                self.trade_counter += 1;
+                let trade_id = self.trade_ids.next(redis).await;
+                let stamp = self.sequencer.stamp(redis).await;
                 let res = MatchResult {
-                    trade_id: self.trade_counter,
+                    trade_id,
+                    market: self.market.clone(),
                     price,
                     quantity: fill_qty,
                     buyer_id: bid.user_id.clone(),
                     seller_id: sell_order.user_id.clone(),
+                    sequence: stamp.sequence,
+                    timestamp_ns: stamp.timestamp_ns,
                 };
 
-                Self::broadcast_match(res, redis).await;
+                Self::broadcast_match(res.clone(), redis).await;
 
                 sell_order.quantity -= fill_qty;
+                bid.quantity -= fill_qty;
+                Self::publish_fill(&mut self.placed_quantities, &bid, &res, redis).await;
+                Self::publish_fill(&mut self.placed_quantities, &sell_order, &res, redis).await;
+                fills.push(res);
                 if !bid.quantity.is_zero() { orders.push_front(bid); }
                 if sell_order.quantity.is_zero() { break; }
             }
             if orders.is_empty() { self.bids.remove(&price); }
-            if sell_order.quantity.is_zero() { return; }
+            if sell_order.quantity.is_zero() { return (fills, Decimal::ZERO); }
         }
+        let remaining = sell_order.quantity;
         self.asks.entry(sell_order.price).or_default().push_back(sell_order);
+        (fills, remaining)
     }
 
     // FIX: Removed &self to avoid borrow checker error
-    async fn broadcast_match(res: MatchResult, redis: &RedisClient) {
+    async fn broadcast_match(res: MatchResult, redis: &impl Broker) {
         if let Ok(payload) = serde_json::to_string(&res) {
-            let _ = redis.lpush::<i64, _, _>("SETTLEMENT_QUEUE", payload.clone()).await;
-            let _ = redis.lpush::<i64, _, _>("DB_QUEUE", payload).await;
-            println!("🎯 Match Found: Trade #{}", res.trade_id);
+            redis.push("SETTLEMENT_QUEUE", payload.clone()).await;
+            redis.push("DB_QUEUE", payload).await;
+            println!("🎯 Match Found: Trade #{} (seq {})", res.trade_id, res.sequence);
+        }
+    }
+
+    /// Publishes `order`'s fill in `trade`; `order.quantity` must already be reduced by it.
+    async fn publish_fill(placed_quantities: &mut HashMap<u64, Decimal>, order: &Order, trade: &MatchResult, redis: &impl Broker) {
+        let placed = placed_quantities.get(&order.order_id).copied().unwrap_or(order.quantity);
+        let status = if order.quantity.is_zero() { OrderStatus::Filled } else { OrderStatus::PartiallyFilled };
+        let stamp = EventStamp { sequence: trade.sequence, timestamp_ns: trade.timestamp_ns };
+        let mut event = Self::order_event(order, placed, OrderEventType::Filled, status, stamp);
+        event.trade_id = Some(trade.trade_id);
+        event.fill_price = Some(trade.price);
+        event.fill_quantity = Some(trade.quantity);
+        Self::publish_order_event(&event, redis).await;
+        if order.quantity.is_zero() {
+            placed_quantities.remove(&order.order_id);
+        }
+    }
+
+    /// `order.quantity` is what's left of it; `placed` is what it was placed with.
+    fn order_event(order: &Order, placed: Decimal, event_type: OrderEventType, status: OrderStatus, stamp: EventStamp) -> OrderEvent {
+        OrderEvent {
+            order_id: order.order_id,
+            event_type,
+            status,
+            user_id: order.user_id.clone(),
+            market: order.market.clone(),
+            side: order.side.clone(),
+            price: order.price,
+            quantity: placed,
+            remaining_quantity: order.quantity,
+            trade_id: None,
+            fill_price: None,
+            fill_quantity: None,
+            reason: None,
+            sequence: stamp.sequence,
+            timestamp_ns: stamp.timestamp_ns,
+        }
+    }
+
+    async fn publish_order_event(event: &OrderEvent, redis: &impl Broker) {
+        if let Ok(payload) = serde_json::to_string(event) {
+            redis.push("ORDER_EVENT_QUEUE", payload).await;
+        }
+    }
+
+    async fn publish_ack(ack: &EngineAck, redis: &impl Broker) {
+        if let Ok(payload) = serde_json::to_string(ack) {
+            redis.publish("ORDER_ACKS", payload).await;
         }
     }
 }
@@ -87,15 +380,247 @@ impl Engine {
 async fn main() -> Result<()> {
     dotenv().ok();
     let redis_url = env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
-    let mut engine = Engine { bids: BTreeMap::new(), asks: BTreeMap::new(), trade_counter: 0 };
+    let market = env::var("MARKET").unwrap_or(common_utils::default_market());
+    // api-router routes each market's commands to its own queue, and user-wide ones to every queue
+    let queue = common_utils::order_queue(&market);
     let config = RedisConfig::from_url(&redis_url)?;
     let client = Builder::from_config(config).build()?;
     client.init().await?;
+    println!("🚀 Matching engine for {} is watching {}", market, queue);
+    let mut engine = Engine::new(
+        market,
+        Sequencer::new(),
+        EpochIds::start(&client).await?,
+        EpochIds::start(&client).await?,
+    );
     loop {
-        if let Ok(Some(data)) = client.rpop::<Option<String>, _>("ORDER_QUEUE", None).await {
-            if let Ok(order) = serde_json::from_str::<Order>(&data) {
-                engine.match_order(order, &client).await;
-            }
+        engine.expire_dead_man_switches(&client).await;
+        if let Ok(Some(data)) = client.rpop::<Option<String>, _>(&queue, None).await
+            && let Ok(cmd) = serde_json::from_str::<EngineCommand>(&data)
+        {
+            engine.handle_command(cmd, &client).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use broker::MemoryBroker;
+
+    const MARKET: &str = "SOL_USDC";
+
+    async fn new_engine(redis: &MemoryBroker) -> Engine {
+        engine_for(MARKET, redis).await
+    }
+
+    async fn engine_for(market: &str, redis: &MemoryBroker) -> Engine {
+        let sequencer = Sequencer::new();
+        let trade_ids = EpochIds::start(redis).await.unwrap();
+        Engine::new(market.into(), sequencer, trade_ids, EpochIds::start(redis).await.unwrap())
+    }
+
+    fn order(user_id: &str, side: &str, price: i64, quantity: i64) -> Order {
+        Order {
+            order_id: 0,
+            request_id: Some(format!("req-{}-{}-{}", user_id, side, price)),
+            user_id: user_id.into(),
+            market: MARKET.into(),
+            price: Decimal::from(price),
+            quantity: Decimal::from(quantity),
+            side: side.into(),
+        }
+    }
+
+    fn resting(engine: &Engine) -> usize {
+        engine.bids.values().chain(engine.asks.values()).map(VecDeque::len).sum()
+    }
+
+    fn order_acks(redis: &MemoryBroker) -> Vec<OrderAck> {
+        redis
+            .drain::<EngineAck>("ORDER_ACKS")
+            .into_iter()
+            .map(|ack| match ack {
+                EngineAck::Order(ack) => ack,
+                other => panic!("expected an order ack, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn orders_with_a_request_id_are_acked_after_their_fills() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("alice", "SELL", 100, 2)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 101, 2)), &redis).await;
+        let mut fire_and_forget = order("carol", "BUY", 90, 1);
+        fire_and_forget.request_id = None;
+        engine.handle_command(EngineCommand::PlaceOrder(fire_and_forget), &redis).await;
+
+        let acks = order_acks(&redis);
+        assert_eq!(acks.len(), 2);
+        assert_eq!((acks[0].request_id.as_str(), acks[0].status), ("req-alice-SELL-100", OrderStatus::Resting));
+        assert_eq!((acks[1].request_id.as_str(), acks[1].status), ("req-bob-BUY-101", OrderStatus::Filled));
+        // Filled at the resting order's price
+        let fill = &acks[1].fills[0];
+        assert_eq!((fill.price, fill.quantity, fill.buyer_id.as_str(), fill.seller_id.as_str()), (Decimal::from(100), Decimal::from(2), "bob", "alice"));
+        assert_eq!(fill.market, MARKET);
+        assert!(acks[1].remaining_quantity.is_zero());
+        assert!(acks[1].sequence > fill.sequence);
+        assert_eq!(redis.drain::<MatchResult>("SETTLEMENT_QUEUE").len(), 1);
+        assert_eq!(redis.drain::<MatchResult>("DB_QUEUE").len(), 1);
+        assert_eq!(resting(&engine), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_orders_are_rejected_with_an_id_and_a_reason() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        let invalid = [
+            (Order { market: "BTC_USDC".into(), ..order("alice", "BUY", 100, 1) }, "Unknown market 'BTC_USDC'"),
+            (order("alice", "HOLD", 100, 1), "Unknown side 'HOLD'"),
+            (order("alice", "BUY", 0, 1), "Price must be positive"),
+            (order("alice", "SELL", 100, -1), "Quantity must be positive"),
+        ];
+        for (order, _) in invalid.clone() {
+            engine.handle_command(EngineCommand::PlaceOrder(order), &redis).await;
+        }
+
+        let acks = order_acks(&redis);
+        let events = redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+        for ((ack, event), (_, reason)) in acks.iter().zip(&events).zip(&invalid) {
+            assert_eq!((ack.status, ack.reason.as_deref()), (OrderStatus::Rejected, Some(*reason)));
+            assert_eq!((event.event_type, event.reason.as_deref()), (OrderEventType::Rejected, Some(*reason)));
+            assert_eq!(ack.order_id, Some(event.order_id));
         }
+        assert_eq!((acks.len(), events.len()), (4, 4));
+        assert_eq!(resting(&engine), 0);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn a_batch_with_one_invalid_order_places_none_of_it() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        let batch = |orders| EngineCommand::PlaceBatch { request_id: "batch".into(), orders };
+
+        engine.handle_command(batch(vec![order("alice", "BUY", 99, 1), order("alice", "BUY", 0, 1)]), &redis).await;
+        let Some(EngineAck::Batch { orders, .. }) = redis.drain::<EngineAck>("ORDER_ACKS").pop() else { panic!("no batch ack") };
+        assert!(orders.iter().all(|o| o.status == OrderStatus::Rejected && o.reason.as_deref() == Some("Order 1: Price must be positive")));
+        assert_eq!(resting(&engine), 0);
+
+        engine.handle_command(batch(vec![order("alice", "BUY", 99, 1), order("alice", "SELL", 101, 1)]), &redis).await;
+        let Some(EngineAck::Batch { orders, .. }) = redis.drain::<EngineAck>("ORDER_ACKS").pop() else { panic!("no batch ack") };
+        assert!(orders.iter().all(|o| o.status == OrderStatus::Resting && o.request_id == "batch"));
+        assert_eq!(resting(&engine), 2);
+    }
+
+    #[tokio::test]
+    async fn mass_cancel_removes_only_the_users_orders_on_the_requested_side() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        for order in [order("alice", "BUY", 98, 1), order("alice", "BUY", 99, 1), order("alice", "SELL", 105, 1), order("bob", "BUY", 99, 1)] {
+            engine.handle_command(EngineCommand::PlaceOrder(order), &redis).await;
+        }
+        let cancel = |market: &str, side: Option<&str>| EngineCommand::MassCancel {
+            request_id: "cancel".into(),
+            user_id: "alice".into(),
+            market: Some(market.into()),
+            side: side.map(Into::into),
+        };
+        redis.drain::<EngineAck>("ORDER_ACKS");
+        redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+
+        // Routed to the BTC_USDC engine; ignored should it arrive here anyway
+        engine.handle_command(cancel("BTC_USDC", None), &redis).await;
+        engine.handle_command(cancel(MARKET, Some("BUY")), &redis).await;
+        let acks = redis.drain::<EngineAck>("ORDER_ACKS");
+        let [EngineAck::Cancel(other_market), EngineAck::Cancel(buys)] = &acks[..] else { panic!("expected two cancel acks") };
+        assert!(other_market.cancelled_order_ids.is_empty());
+        assert_eq!(buys.cancelled_order_ids.len(), 2);
+
+        let events = redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+        assert!(events.iter().all(|e| e.user_id == "alice" && e.side == "BUY" && e.status == OrderStatus::Cancelled && e.reason.as_deref() == Some("mass_cancel")));
+        assert_eq!(resting(&engine), 2);
+    }
+
+    #[tokio::test]
+    async fn dead_man_switch_cancels_the_users_orders_once_it_runs_out() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("alice", "BUY", 99, 1)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 99, 1)), &redis).await;
+        let arm = |user_id: &str, timeout_ms| EngineCommand::ArmDeadManSwitch { request_id: "dms".into(), user_id: user_id.into(), timeout_ms };
+        engine.handle_command(arm("alice", 20), &redis).await;
+        engine.handle_command(arm("bob", 20), &redis).await;
+        // Disarmed before it fires
+        engine.handle_command(arm("bob", 0), &redis).await;
+
+        engine.expire_dead_man_switches(&redis).await;
+        assert_eq!(resting(&engine), 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+        engine.expire_dead_man_switches(&redis).await;
+        let events = redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].user_id.as_str(), events[0].reason.as_deref()), ("alice", Some("dead_man_switch")));
+        assert!(engine.dead_man_switches.is_empty());
+        assert_eq!(resting(&engine), 1);
+    }
+
+    #[tokio::test]
+    async fn order_events_follow_an_order_from_placement_to_fill() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("alice", "SELL", 100, 3)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 100, 1)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("carol", "BUY", 100, 2)), &redis).await;
+
+        let events = redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE");
+        let alice: Vec<&OrderEvent> = events.iter().filter(|e| e.user_id == "alice").collect();
+        let steps: Vec<(OrderEventType, OrderStatus, Decimal)> = alice.iter().map(|e| (e.event_type, e.status, e.remaining_quantity)).collect();
+        assert_eq!(steps, [
+            (OrderEventType::Placed, OrderStatus::Resting, Decimal::from(3)),
+            (OrderEventType::Filled, OrderStatus::PartiallyFilled, Decimal::from(2)),
+            (OrderEventType::Filled, OrderStatus::Filled, Decimal::ZERO),
+        ]);
+        assert!(alice.iter().all(|e| e.quantity == Decimal::from(3) && e.order_id == alice[0].order_id));
+        assert!(alice.windows(2).all(|pair| pair[0].sequence < pair[1].sequence));
+        assert_eq!(alice[2].fill_quantity, Some(Decimal::from(2)));
+        assert!(engine.placed_quantities.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trade_ids_carry_the_engines_epoch() {
+        let redis = MemoryBroker::default();
+        let mut engine = new_engine(&redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("alice", "SELL", 100, 2)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 100, 1)), &redis).await;
+        engine.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 100, 1)), &redis).await;
+
+        // A restarted engine takes the next epoch
+        let mut restarted = new_engine(&redis).await;
+        restarted.handle_command(EngineCommand::PlaceOrder(order("alice", "SELL", 100, 1)), &redis).await;
+        restarted.handle_command(EngineCommand::PlaceOrder(order("bob", "BUY", 100, 1)), &redis).await;
+
+        let ids: Vec<u64> = redis.drain::<MatchResult>("DB_QUEUE").iter().map(|t| t.trade_id).collect();
+        // Each engine takes an epoch for trades, then one for orders
+        assert_eq!(ids, [1 << 40 | 1, 1 << 40 | 2, 3 << 40 | 1]);
+    }
+
+    #[tokio::test]
+    async fn order_ids_stay_unique_across_markets_and_restarts() {
+        let redis = MemoryBroker::default();
+        let mut sol = engine_for(MARKET, &redis).await;
+        let mut btc = engine_for("BTC_USDC", &redis).await;
+        // Before epochs, each of these was order 1
+        sol.handle_command(EngineCommand::PlaceOrder(order("alice", "BUY", 99, 1)), &redis).await;
+        btc.handle_command(EngineCommand::PlaceOrder(Order { market: "BTC_USDC".into(), ..order("bob", "BUY", 99, 1) }), &redis).await;
+        let mut restarted = engine_for(MARKET, &redis).await;
+        restarted.handle_command(EngineCommand::PlaceOrder(order("carol", "SELL", 101, 1)), &redis).await;
+        restarted.handle_command(EngineCommand::PlaceOrder(order("carol", "SELL", 0, 1)), &redis).await;
+
+        let ids: Vec<u64> = redis.drain::<OrderEvent>("ORDER_EVENT_QUEUE").iter().map(|e| e.order_id).collect();
+        assert_eq!(ids, [2 << 40 | 1, 4 << 40 | 1, 6 << 40 | 1, 6 << 40 | 2]);
+    }
+}
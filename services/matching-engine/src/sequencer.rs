@@ -0,0 +1,142 @@
+use fred::prelude::*;
+use crate::broker::Broker;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Redis counter holding the last sequence number handed out to any engine.
+pub const SEQUENCE_KEY: &str = "ENGINE_SEQUENCE";
+/// Redis counter holding the last ID epoch taken by any engine.
+pub const EPOCH_KEY: &str = "ENGINE_EPOCH";
+// Low bits of an ID count the IDs handed out within an epoch
+const ID_COUNTER_BITS: u32 = 40;
+
+/// When and in what order the engine produced an event. Every match and ack carries
+/// one as its `sequence` and `timestamp_ns`, so consumers can order and deduplicate
+/// events without relying on their own clocks. Both are zero only on events queued
+/// before the engine stamped them.
+#[derive(Debug, Clone, Copy)]
+pub struct EventStamp {
+    pub sequence: u64,
+    pub timestamp_ns: u64,
+}
+
+/// Stamps engine events with a sequence number and a nanosecond timestamp.
+///
+/// Every event takes the next number from one Redis counter shared by all
+/// engines, so sequence numbers are a single order across markets and keep
+/// increasing across restarts, without gaps. Timestamps never go backwards, even
+/// if the wall clock is stepped back.
+pub struct Sequencer {
+    last_timestamp_ns: u64,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Sequencer { last_timestamp_ns: 0 }
+    }
+
+    pub async fn stamp(&mut self, redis: &impl Broker) -> EventStamp {
+        let sequence = loop {
+            match redis.increment(SEQUENCE_KEY, 1).await {
+                Ok(sequence) => break sequence,
+                // Without a sequence the event can't be published; wait for Redis rather than reuse numbers
+                Err(e) => {
+                    eprintln!("❌ Failed to take a sequence number: {}", e);
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        };
+
+        let now_ns = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        self.last_timestamp_ns = self.last_timestamp_ns.max(now_ns);
+        EventStamp { sequence, timestamp_ns: self.last_timestamp_ns }
+    }
+}
+
+/// Hands out globally unique IDs: an epoch in the high bits and a counter in the
+/// low 40. Every engine start takes a new epoch from Redis, so IDs never repeat
+/// across restarts or between markets, and all of them are above the plain counters
+/// (1, 2, ...) engines used before epochs existed. Trade and order IDs each come
+/// from their own `EpochIds`.
+pub struct EpochIds {
+    epoch: u64,
+    next: u64,
+}
+
+impl EpochIds {
+    pub async fn start(redis: &impl Broker) -> RedisResult<Self> {
+        let mut ids = EpochIds { epoch: 0, next: 0 };
+        ids.new_epoch(redis).await?;
+        Ok(ids)
+    }
+
+    pub async fn next(&mut self, redis: &impl Broker) -> u64 {
+        if self.next >> ID_COUNTER_BITS != 0 {
+            // A trillion IDs into the epoch: take another rather than wrap around
+            while let Err(e) = self.new_epoch(redis).await {
+                eprintln!("❌ Failed to take a new ID epoch: {}", e);
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+        let id = self.epoch << ID_COUNTER_BITS | self.next;
+        self.next += 1;
+        id
+    }
+
+    async fn new_epoch(&mut self, redis: &impl Broker) -> RedisResult<()> {
+        self.epoch = redis.increment(EPOCH_KEY, 1).await?;
+        self.next = 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::MemoryBroker;
+
+    #[tokio::test]
+    async fn sequences_are_one_order_across_engines_and_restarts() {
+        let redis = MemoryBroker::default();
+        let (mut sol, mut btc) = (Sequencer::new(), Sequencer::new());
+        let mut stamps = Vec::new();
+        for _ in 0..3 {
+            stamps.push(sol.stamp(&redis).await.sequence);
+            stamps.push(btc.stamp(&redis).await.sequence);
+        }
+        // A restarted engine carries on from the shared counter, skipping nothing
+        let mut restarted = Sequencer::new();
+        stamps.push(restarted.stamp(&redis).await.sequence);
+        assert_eq!(stamps, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn timestamps_never_go_backwards() {
+        let redis = MemoryBroker::default();
+        let mut sequencer = Sequencer::new();
+        // As if the wall clock had been stepped back since the last event
+        sequencer.last_timestamp_ns = u64::MAX - 1;
+        assert_eq!(sequencer.stamp(&redis).await.timestamp_ns, u64::MAX - 1);
+    }
+
+    #[tokio::test]
+    async fn ids_put_the_epoch_above_a_40_bit_counter() {
+        let redis = MemoryBroker::default();
+        redis.increment(EPOCH_KEY, 6).await.unwrap();
+        let mut ids = EpochIds::start(&redis).await.unwrap();
+        assert_eq!(ids.next(&redis).await, 7 << 40 | 1);
+        assert_eq!(ids.next(&redis).await, 7 << 40 | 2);
+        // Above every ID handed out before epochs existed
+        assert!(ids.next(&redis).await > 1 << 40);
+    }
+
+    #[tokio::test]
+    async fn a_full_counter_rolls_over_to_a_new_epoch() {
+        let redis = MemoryBroker::default();
+        let mut ids = EpochIds::start(&redis).await.unwrap();
+        ids.next = (1 << ID_COUNTER_BITS) - 1;
+        assert_eq!(ids.next(&redis).await, 1 << 40 | 0xff_ffff_ffff);
+        // The next one would spill into the epoch bits
+        assert_eq!(ids.next(&redis).await, 2 << 40 | 1);
+        assert_eq!(redis.counters.borrow()[EPOCH_KEY], 2);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::retry::{now_ms, QueuedTrade, SETTLEMENT_QUEUE};
+use anyhow::Result;
+use common_utils::MatchResult;
+use fred::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Trades that failed permanently or ran out of retries, newest first.
+pub const SETTLEMENT_DLQ: &str = "SETTLEMENT_DLQ";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub trade: MatchResult,
+    pub reason: String,
+    pub attempts: u32,
+    pub failed_at_ms: u64,
+}
+
+pub async fn push(redis: &RedisClient, trade: QueuedTrade, reason: String) -> RedisResult<()> {
+    let entry = DeadLetter { trade: trade.trade, reason, attempts: trade.attempts, failed_at_ms: now_ms() };
+    redis.lpush::<(), _, _>(SETTLEMENT_DLQ, serde_json::to_string(&entry).expect("DeadLetter serializes")).await
+}
+
+/// `settlement-worker dlq <list | replay <trade_id>... | replay all>`
+pub async fn run_cli(redis: &RedisClient, args: &[String]) -> Result<()> {
+    let entries: Vec<String> = redis.lrange(SETTLEMENT_DLQ, 0, -1).await?;
+    let parsed = entries.iter().filter_map(|raw| Some((raw, serde_json::from_str::<DeadLetter>(raw).ok()?)));
+
+    match args.first().map(String::as_str) {
+        Some("list") => {
+            println!("{} dead-lettered trade(s)", entries.len());
+            for (_, entry) in parsed {
+                println!(
+                    "#{} {} -> {} {} @ {} | attempts {} | failed at {} | {}",
+                    entry.trade.trade_id,
+                    entry.trade.buyer_id,
+                    entry.trade.seller_id,
+                    entry.trade.quantity,
+                    entry.trade.price,
+                    entry.attempts,
+                    entry.failed_at_ms,
+                    entry.reason,
+                );
+            }
+        }
+        Some("replay") if args.len() > 1 => {
+            let all = args[1] == "all";
+            let ids: Vec<u64> = args[1..].iter().filter_map(|id| id.parse().ok()).collect();
+            let mut replayed = 0;
+            for (raw, entry) in parsed {
+                if !all && !ids.contains(&entry.trade.trade_id) {
+                    continue;
+                }
+                // Only requeue entries we actually took off the DLQ, in case two admins race
+                let removed: i64 = redis.lrem(SETTLEMENT_DLQ, 1, raw.clone()).await?;
+                if removed == 1 {
+                    let trade = QueuedTrade::new(entry.trade);
+                    redis.rpush::<(), _, _>(SETTLEMENT_QUEUE, serde_json::to_string(&trade)?).await?;
+                    println!("🔁 Replaying trade #{}", trade.trade.trade_id);
+                    replayed += 1;
+                }
+            }
+            println!("✅ {} trade(s) moved back to {}", replayed, SETTLEMENT_QUEUE);
+        }
+        _ => {
+            eprintln!("Usage: settlement-worker dlq list");
+            eprintln!("       settlement-worker dlq replay <trade_id>... | all");
+        }
+    }
+    Ok(())
+}
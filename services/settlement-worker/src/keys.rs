@@ -0,0 +1,85 @@
+use anyhow::{Context, Result, bail};
+use settlement_client::keystore::{self, ScryptParams};
+use settlement_client::remote_signer;
+use settlement_client::signer::{LocalSigner, SigningBackend};
+use solana_sdk::pubkey::Pubkey;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+/// `settlement-worker keystore encrypt <keypair.json> <keystore.json>`
+pub fn run_keystore_cli(args: &[String]) -> Result<()> {
+    let [cmd, input, output] = args else {
+        eprintln!("Usage: settlement-worker keystore encrypt <keypair.json> <keystore.json>");
+        return Ok(());
+    };
+    if cmd != "encrypt" {
+        bail!("unknown keystore command {}", cmd);
+    }
+
+    let keypair = keystore::read_keypair_file(Path::new(input))?;
+    // KEYSTORE_PASSWORD allows scripted setups; otherwise prompt without echo
+    let password = match std::env::var("KEYSTORE_PASSWORD") {
+        Ok(password) => Zeroizing::new(password),
+        Err(_) => {
+            let password = Zeroizing::new(rpassword::prompt_password("Keystore password: ")?);
+            let confirm = Zeroizing::new(rpassword::prompt_password("Repeat password: ")?);
+            if password != confirm {
+                bail!("passwords do not match");
+            }
+            password
+        }
+    };
+    let json = keystore::encrypt(&keypair, &password, ScryptParams::default())?;
+
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(output)
+        .with_context(|| format!("creating {}", output))?;
+    std::io::Write::write_all(&mut file, json.as_bytes())?;
+    println!("🔐 Wrote encrypted keystore to {}", output);
+    Ok(())
+}
+
+/// `settlement-worker signer <socket-path>`: holds the engine key (loaded like the
+/// worker's) and signs PROGRAM_ID's settlement messages for relayers started with
+/// ENGINE_SIGNER_SOCKET.
+pub async fn run_signer(args: &[String]) -> Result<()> {
+    let Some(socket) = args.first() else {
+        eprintln!("Usage: settlement-worker signer <socket-path>");
+        return Ok(());
+    };
+    let program_id = Pubkey::from_str(&std::env::var("PROGRAM_ID")?)?;
+    let backend: Arc<dyn SigningBackend> = Arc::new(LocalSigner::from_env()?);
+
+    // The socket is reachable as soon as it's bound, before its own mode can be
+    // changed, so only the owning user may be able to get to it at all
+    let socket = Path::new(socket);
+    private_directory(socket.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+
+    // A socket left behind by a previous run would make bind fail
+    if std::fs::symlink_metadata(socket).is_ok() {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+    println!("✍️ Engine signer {} for {} listening on {}", backend.pubkey(), program_id, socket.display());
+
+    tokio::task::spawn_blocking(move || remote_signer::serve(listener, backend, program_id)).await??;
+    Ok(())
+}
+
+/// Creates `dir` with mode 0700 if it's missing, and refuses to use it if other
+/// users can reach into it.
+fn private_directory(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+            .with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let metadata = std::fs::metadata(dir).with_context(|| format!("reading {}", dir.display()))?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        bail!("{} is accessible to other users (mode {:o}); restrict it to 0700", dir.display(), metadata.permissions().mode() & 0o777);
+    }
+    Ok(())
+}
@@ -1,10 +1,20 @@
-use settlement_client::{SettlementClient, signer::EngineSigner};
+mod dlq;
+mod keys;
+mod reconcile;
+mod retry;
+mod status;
+
+use settlement_client::{SettlementClient, failure::{Failure, classify}, fees::PriorityFeeConfig, keystore, nonces::NonceTracker, rotation::SignerRotation, schedule::group_by_accounts};
+use futures::stream::{self, StreamExt};
 use fred::prelude::*;
 use common_utils::MatchResult;
+use retry::{QueuedTrade, RetryPolicy, SETTLEMENT_QUEUE};
 use solana_sdk::pubkey::Pubkey;
 use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::signature::Keypair;
 use std::str::FromStr;
+use std::time::Duration;
+use std::sync::Arc;
+use status::StatusStore;
 use anyhow::Result;
 use dotenvy::dotenv;
 
@@ -12,59 +22,272 @@ use dotenvy::dotenv;
 async fn main() -> Result<()> {
     // 1. Setup Infrastructure
     dotenv().ok();
+
+    // Key management commands: `settlement-worker keystore ...` / `settlement-worker signer ...`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("keystore") => return keys::run_keystore_cli(&args[1..]),
+        Some("signer") => return keys::run_signer(&args[1..]).await,
+        _ => {}
+    }
+
     let redis_url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
     let rpc_url = std::env::var("SOLANA_RPC_URL").unwrap_or("http://127.0.0.1:8899".into());
-    let program_id = Pubkey::from_str(&std::env::var("PROGRAM_ID")?)?;
+    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
 
     // Initialize Redis
     let config = RedisConfig::from_url(&redis_url)?;
     let redis = Builder::from_config(config).build()?;
     redis.init().await?;
+
+    // Admin commands: `settlement-worker dlq ...`
+    if args.first().map(String::as_str) == Some("dlq") {
+        return dlq::run_cli(&redis, &args[1..]).await;
+    }
+
+    let program_id = Pubkey::from_str(&std::env::var("PROGRAM_ID")?)?;
     
     // Initialize Solana Client
-    // We assume the Relayer Key is stored as a hex string in the env
-    let relayer_key_hex = std::env::var("RELAYER_KEYPAIR_HEX")?;
-    let relayer_bytes = hex::decode(relayer_key_hex)?;
-    let relayer_fee_payer = Keypair::from_bytes(&relayer_bytes)?;
+    // Relayer key from RELAYER_KEYSTORE_PATH, RELAYER_KEYPAIR_PATH or hex RELAYER_KEYPAIR_HEX
+    let relayer_fee_payer = keystore::relayer_keypair_from_env()?;
+
+    // Per-trade settlement status, including every transaction signature before it's broadcast
+    let status = Arc::new(StatusStore::connect(&db_url).await?);
+
+    // Compare fills, settlements and on-chain margin accounts every RECONCILE_INTERVAL_SECS (0 disables)
+    let reconcile_secs: u64 = std::env::var("RECONCILE_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    if reconcile_secs > 0 {
+        let grace_secs: u64 = std::env::var("RECONCILE_GRACE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(120);
+        let reconciler = reconcile::Reconciler::connect(&db_url, rpc_url.clone(), program_id, Duration::from_secs(grace_secs)).await?;
+        tokio::spawn(reconciler.run(Duration::from_secs(reconcile_secs)));
+    }
+
+    let rpc = RpcClient::new(rpc_url.clone());
+    // Expected nonces of every margin account, advanced locally as settlements confirm
+    let nonces = NonceTracker::load(&rpc, &program_id).await?;
 
     let client = SettlementClient {
-        rpc: RpcClient::new(rpc_url),
+        rpc,
         relayer_fee_payer,
+        fees: PriorityFeeConfig::from_env(),
+        nonces,
+        submissions: Some(status.clone()),
+        markets: common_utils::markets_from_env(),
     };
 
-    let engine_signer = EngineSigner::from_env()?;
+    // Engine keys: ENGINE_* plus ENGINE_NEXT_* while a rotation is being rolled out
+    let signers = Arc::new(SignerRotation::from_env()?);
+    let engine_pubkey = signers.refresh(&client.rpc, &program_id).await?;
+    println!("🔑 Signing settlements as {}", engine_pubkey);
+    let refresh_secs: u64 = std::env::var("ENGINE_SIGNER_REFRESH_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10).max(1);
+    tokio::spawn(refresh_signers(signers.clone(), RpcClient::new(rpc_url), program_id, Duration::from_secs(refresh_secs)));
+
+    // Trades packed per transaction; above 1 they go through settle_trades_batch
+    let batch_size: usize = std::env::var("SETTLEMENT_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
+    // Groups of trades with disjoint accounts settled concurrently
+    let parallelism: usize = std::env::var("SETTLEMENT_PARALLELISM").ok().and_then(|v| v.parse().ok()).unwrap_or(4).max(1);
+    let retry_policy = RetryPolicy::from_env();
 
     println!("🚀 Settlement Relayer is live. Watching SETTLEMENT_QUEUE...");
 
     loop {
-        // 2. Pop match from Redis
-        if let Ok(Some(data)) = redis.rpop::<Option<String>, _>("SETTLEMENT_QUEUE", None).await {
-            let m: MatchResult = match serde_json::from_str(&data) {
-                Ok(parsed) => parsed,
-                Err(e) => {
-                    eprintln!("❌ Failed to parse match: {}", e);
-                    continue;
-                }
-            };
+        // 2. Pop matches from Redis, after releasing any retries whose backoff has passed
+        if let Err(e) = retry::promote_due(&redis).await {
+            eprintln!("❌ Failed to requeue due retries: {}", e);
+        }
 
-            println!("🔄 Processing Trade #{}...", m.trade_id);
+        let max_pending = batch_size * parallelism;
+        let mut pending: Vec<QueuedTrade> = Vec::with_capacity(max_pending);
+        while pending.len() < max_pending {
+            let Ok(Some(data)) = redis.rpop::<Option<String>, _>(SETTLEMENT_QUEUE, None).await else { break };
+            match serde_json::from_str(&data) {
+                Ok(parsed) => pending.push(parsed),
+                Err(e) => eprintln!("❌ Failed to parse match: {}", e),
+            }
+        }
 
-            // 3. Settle on Solana
-            // The client now handles: PDA derivation, msg reconstruction, signing, and broadcasting.
-            match client.settle_trade(&m, &engine_signer, &program_id).await {
+        if pending.is_empty() {
+            // Idle wait
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            continue;
+        }
+        for queued in &pending {
+            status.pending(queued.trade.trade_id, queued.attempts, None).await;
+        }
+        // Trades can't overtake an older one for the same account that's waiting out a backoff
+        let pending = retry::hold_back(&redis, pending, |trade| {
+            SettlementClient::margin_accounts(trade, &program_id).map(|(b, s)| vec![b, s]).unwrap_or_default()
+        }).await;
+
+        // 3. Settle on Solana
+        // Trades sharing a margin account stay in one group, in queue order; groups run concurrently
+        let groups = group_by_accounts(pending, |queued| {
+            SettlementClient::margin_accounts(&queued.trade, &program_id).map(|(b, s)| vec![b, s]).unwrap_or_default()
+        });
+        println!("🔄 Processing {} group(s) of independent trades...", groups.len());
+
+        let results: Vec<Vec<(QueuedTrade, Outcome)>> = stream::iter(groups)
+            .map(|group| settle_group(&client, &signers, &program_id, group, batch_size))
+            .buffer_unordered(parallelism)
+            .collect()
+            .await;
+
+        // 4. Retry, isolate or dead-letter whatever didn't settle
+        for group in results {
+            if let Err(e) = handle_failures(&redis, &status, &retry_policy, group).await {
+                eprintln!("❌ Failed to requeue unsettled trades: {}", e);
+            }
+        }
+    }
+}
+
+enum Outcome {
+    Settled,
+    Failed { failure: Failure, batched: bool },
+    // Not attempted because an earlier trade in the group failed
+    Skipped,
+}
+
+/// Settles one group of conflicting trades in order, stopping at the first failure
+/// so later trades for the same accounts are never settled ahead of an earlier one.
+async fn settle_group(
+    client: &SettlementClient,
+    signers: &SignerRotation,
+    program_id: &Pubkey,
+    group: Vec<QueuedTrade>,
+    batch_size: usize,
+) -> Vec<(QueuedTrade, Outcome)> {
+    let mut results: Vec<(QueuedTrade, Outcome)> = Vec::with_capacity(group.len());
+    let mut remaining = group.into_iter().peekable();
+
+    while let Some(first) = remaining.next() {
+        // Isolated trades go alone so a rejection can be pinned on them
+        let mut chunk = vec![first];
+        while batch_size > 1 && !chunk[0].isolated && chunk.len() < batch_size {
+            match remaining.next_if(|next| !next.isolated) {
+                Some(next) => chunk.push(next),
+                None => break,
+            }
+        }
+
+        // One key per transaction, even if a rotation switches keys meanwhile
+        let engine_signer = signers.active();
+        let failed = if chunk.len() == 1 {
+            let m = &chunk[0].trade;
+            println!("🔄 Processing Trade #{}...", m.trade_id);
+            match client.settle_trade(m, &engine_signer, program_id).await {
                 Ok(tx_sig) => {
                     println!("✅ Trade {} Settled! TX: {}", m.trade_id, tx_sig);
+                    results.push((chunk.pop().unwrap(), Outcome::Settled));
+                    false
                 }
                 Err(e) => {
                     eprintln!("❌ Settlement error for trade {}: {:?}", m.trade_id, e);
-                    // Re-queue for retry (optional)
-                    let _ = redis.lpush::<i64, _, _>("SETTLEMENT_QUEUE", data).await;
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    results.push((chunk.pop().unwrap(), Outcome::Failed { failure: classify(&e), batched: false }));
+                    true
                 }
             }
         } else {
-            // Idle wait
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            let matches: Vec<MatchResult> = chunk.iter().map(|q| q.trade.clone()).collect();
+            let outcomes = match client.settle_trades_batch(&matches, &engine_signer, program_id).await {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    eprintln!("❌ Failed to prepare batch: {:?}", e);
+                    let failure = classify(&e);
+                    results.extend(chunk.into_iter().map(|q| (q, Outcome::Failed { failure: failure.clone(), batched: true })));
+                    break;
+                }
+            };
+            let mut failed = false;
+            for outcome in outcomes {
+                let trades: Vec<QueuedTrade> = chunk.extract_if(.., |q| outcome.trade_ids.contains(&q.trade.trade_id)).collect();
+                match outcome.result {
+                    Ok(tx_sig) => {
+                        println!("✅ Trades {:?} Settled! TX: {}", outcome.trade_ids, tx_sig);
+                        results.extend(trades.into_iter().map(|q| (q, Outcome::Settled)));
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Settlement error for trades {:?}: {:?}", outcome.trade_ids, e);
+                        let failure = classify(&e);
+                        let batched = trades.len() > 1;
+                        results.extend(trades.into_iter().map(|q| (q, Outcome::Failed { failure: failure.clone(), batched })));
+                        failed = true;
+                    }
+                }
+            }
+            // The client stops at a failed transaction; what it didn't send waits behind it
+            results.extend(chunk.into_iter().map(|q| (q, Outcome::Skipped)));
+            failed
+        };
+
+        if failed {
+            break;
         }
     }
-}
\ No newline at end of file
+
+    results.extend(remaining.map(|q| (q, Outcome::Skipped)));
+    results
+}
+
+/// Follows engine signer rotations on chain, switching to a newly staged key
+/// while the old one is still accepted.
+async fn refresh_signers(signers: Arc<SignerRotation>, rpc: RpcClient, program_id: Pubkey, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = signers.refresh(&rpc, &program_id).await {
+            eprintln!("⚠️ Failed to refresh engine signer: {}", e);
+        }
+    }
+}
+
+/// Decides what happens to each unsettled trade of a group:
+/// - permanent failures of a single trade go to the DLQ with the program's reason
+/// - permanent failures of a batch are retried one trade per transaction, since the
+///   error doesn't say which trade caused it
+/// - transient failures back off exponentially until `max_attempts`, then go to the DLQ
+/// - skipped trades wait behind the failure they were queued after
+async fn handle_failures(
+    redis: &RedisClient,
+    status: &StatusStore,
+    policy: &RetryPolicy,
+    group: Vec<(QueuedTrade, Outcome)>,
+) -> RedisResult<()> {
+    let mut delay = Duration::ZERO;
+    let mut later: Vec<QueuedTrade> = Vec::new();
+
+    for (mut queued, outcome) in group {
+        let trade_id = queued.trade.trade_id;
+        match outcome {
+            Outcome::Settled => {}
+            Outcome::Failed { failure: Failure::Permanent(reason), batched: true } => {
+                status.pending(trade_id, queued.attempts, Some(&reason)).await;
+                queued.isolated = true;
+                later.push(queued);
+            }
+            Outcome::Failed { failure: Failure::Permanent(reason), batched: false } => {
+                eprintln!("💀 Trade {} dead-lettered: {}", queued.trade.trade_id, reason);
+                status.failed(trade_id, queued.attempts, &reason).await;
+                dlq::push(redis, queued, reason).await?;
+            }
+            Outcome::Failed { failure: Failure::Transient(reason), .. } => {
+                queued.attempts += 1;
+                if queued.attempts >= policy.max_attempts {
+                    eprintln!("💀 Trade {} dead-lettered after {} attempts: {}", queued.trade.trade_id, queued.attempts, reason);
+                    let reason = format!("Gave up after {} attempts: {}", policy.max_attempts, reason);
+                    status.failed(trade_id, queued.attempts, &reason).await;
+                    dlq::push(redis, queued, reason).await?;
+                } else {
+                    status.pending(trade_id, queued.attempts, Some(&reason)).await;
+                    delay = delay.max(policy.backoff(queued.attempts));
+                    later.push(queued);
+                }
+            }
+            Outcome::Skipped => later.push(queued),
+        }
+    }
+
+    if !later.is_empty() {
+        retry::schedule(redis, &later, delay).await?;
+    }
+    Ok(())
+}
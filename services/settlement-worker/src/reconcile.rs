@@ -0,0 +1,273 @@
+use anchor_lang::AccountDeserialize;
+use hybrid_perp_dex::state::{MarginAccount, MARKET_NAME_LEN};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_postgres::{Client, NoTls};
+
+use crate::retry::now_ms;
+
+// Trades settled before the market was signed have their positions under no name;
+// they were all SOL_USDC trades
+const UNNAMED_MARKET: [u8; MARKET_NAME_LEN] = [0u8; MARKET_NAME_LEN];
+// Most IDs listed per drift report
+const REPORT_LIMIT: i64 = 20;
+
+/// Periodically checks the engine's fills (`trades`) against confirmed settlements,
+/// the `TradeSettled` events the indexer recorded and the margin accounts on chain,
+/// and logs anything that doesn't line up.
+pub struct Reconciler {
+    db: Client,
+    rpc: RpcClient,
+    program_id: Pubkey,
+    // Fills younger than this are still expected to be in flight
+    grace: Duration,
+}
+
+/// An account whose on-chain position in a market isn't the sum of its confirmed
+/// fills there.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AccountDrift {
+    pub user_id: String,
+    pub market: String,
+    pub expected_size: i64,
+    // None when the margin account doesn't exist
+    pub onchain_size: Option<i64>,
+}
+
+impl Reconciler {
+    pub async fn connect(db_url: &str, rpc_url: String, program_id: Pubkey, grace: Duration) -> anyhow::Result<Self> {
+        let (db, connection) = tokio_postgres::connect(db_url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("❌ Postgres connection error: {}", e);
+            }
+        });
+        Ok(Self { db, rpc: RpcClient::new(rpc_url), program_id, grace })
+    }
+
+    pub async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.reconcile().await {
+                eprintln!("❌ Reconciliation failed: {}", e);
+            }
+        }
+    }
+
+    pub async fn reconcile(&self) -> anyhow::Result<()> {
+        let cutoff = now_ms().saturating_sub(self.grace.as_millis() as u64) as i64;
+
+        // 1. Fills the engine produced that never settled
+        let unsettled = self.db.query(
+            "SELECT t.trade_id, COALESCE(s.status, 'untracked') FROM trades t
+                LEFT JOIN settlements s USING (trade_id)
+                WHERE t.timestamp < $1 AND (s.status IS NULL OR s.status <> 'confirmed')
+                ORDER BY t.trade_id LIMIT $2",
+            &[&cutoff, &REPORT_LIMIT],
+        ).await?;
+        for row in &unsettled {
+            let (trade_id, status): (i64, String) = (row.get(0), row.get(1));
+            eprintln!("⚠️ Drift: trade #{} is not settled (status: {})", trade_id, status);
+        }
+
+        // 2. Settlements for fills the engine has no record of. Trades archived out of
+        // the database (see db-processor partitions) settled before the retention
+        // horizon plus the grace period, so older settlements aren't expected to match
+        let orphaned = self.db.query(
+            "SELECT s.trade_id FROM settlements s
+                LEFT JOIN trades t USING (trade_id)
+                WHERE s.status = 'confirmed' AND t.trade_id IS NULL AND s.updated_at < $1
+                    AND s.updated_at >= (SELECT COALESCE(MAX(range_end), 0) FROM trade_archives) + $3
+                ORDER BY s.trade_id LIMIT $2",
+            &[&cutoff, &REPORT_LIMIT, &(self.grace.as_millis() as i64)],
+        ).await?;
+        for row in &orphaned {
+            eprintln!("⚠️ Drift: trade #{} settled on chain but missing from trades", row.get::<_, i64>(0));
+        }
+
+        // 3. Confirmed settlements vs the TradeSettled events the indexer recorded, trade
+        // by trade and both ways. Only settlements in slots the indexer has processed
+        // can be checked, so this is skipped where it doesn't run for this program
+        let cursor = self.db.query_opt(
+            "SELECT slot FROM indexer_cursor WHERE program_id = $1",
+            &[&self.program_id.to_string()],
+        ).await?;
+        let (unapplied, unrecorded) = match cursor {
+            Some(row) => {
+                let indexed_slot: i64 = row.get(0);
+                let unapplied = self.db.query(
+                    "SELECT s.trade_id FROM settlements s
+                        WHERE s.status = 'confirmed' AND s.slot <= $1 AND NOT EXISTS (
+                            SELECT 1 FROM program_events e
+                                WHERE e.event_type = 'trade_settled' AND e.trade_id = s.trade_id
+                        )
+                        ORDER BY s.trade_id LIMIT $2",
+                    &[&indexed_slot, &REPORT_LIMIT],
+                ).await?;
+                for row in &unapplied {
+                    eprintln!("⚠️ Drift: trade #{} confirmed but no TradeSettled event was emitted", row.get::<_, i64>(0));
+                }
+                // Events are indexed independently of the worker confirming, so recent
+                // ones may not have a confirmed settlement yet
+                let unrecorded = self.db.query(
+                    "SELECT e.trade_id FROM program_events e
+                        LEFT JOIN settlements s ON s.trade_id = e.trade_id AND s.status = 'confirmed'
+                        WHERE e.event_type = 'trade_settled' AND s.trade_id IS NULL
+                            AND COALESCE(e.block_time * 1000, 0) < $1
+                        ORDER BY e.trade_id LIMIT $2",
+                    &[&cutoff, &REPORT_LIMIT],
+                ).await?;
+                for row in &unrecorded {
+                    eprintln!("⚠️ Drift: trade #{} settled on chain but its settlement isn't confirmed", row.get::<_, i64>(0));
+                }
+                (unapplied.len(), unrecorded.len())
+            }
+            None => (0, 0),
+        };
+
+        // 4. Margin account positions vs the sum of their confirmed fills per market,
+        // archived ones included (archived_fills has the totals of partitions archived
+        // before archived_trades, all from SOL_USDC)
+        let rows = self.db.query(
+            "WITH fills AS (
+                SELECT trade_id, market, buyer_id, seller_id, quantity FROM trades
+                UNION ALL
+                SELECT trade_id, market, buyer_id, seller_id, quantity FROM archived_trades
+            )
+            SELECT user_id, market, SUM(delta)::BIGINT FROM (
+                SELECT f.buyer_id AS user_id, f.market, f.quantity AS delta FROM fills f
+                    JOIN settlements s USING (trade_id) WHERE s.status = 'confirmed'
+                UNION ALL
+                SELECT f.seller_id, f.market, -f.quantity FROM fills f
+                    JOIN settlements s USING (trade_id) WHERE s.status = 'confirmed'
+                UNION ALL
+                SELECT user_id, 'SOL_USDC', size FROM archived_fills
+            ) fills GROUP BY user_id, market",
+            &[],
+        ).await?;
+
+        let mut by_user: BTreeMap<String, Vec<(String, i64)>> = BTreeMap::new();
+        for row in rows {
+            by_user.entry(row.get(0)).or_default().push((row.get(1), row.get(2)));
+        }
+        let mut expected = Vec::with_capacity(by_user.len());
+        for (user_id, sizes) in by_user {
+            let Ok(owner) = Pubkey::from_str(&user_id) else {
+                eprintln!("⚠️ Drift: fills recorded for invalid account {}", user_id);
+                continue;
+            };
+            let (margin, _) = Pubkey::find_program_address(&[b"margin_account", owner.as_ref()], &self.program_id);
+            expected.push((user_id, margin, sizes));
+        }
+
+        let mut drifted = 0;
+        for chunk in expected.chunks(100) {
+            let keys: Vec<Pubkey> = chunk.iter().map(|(_, margin, _)| *margin).collect();
+            let accounts = self.rpc.get_multiple_accounts(&keys).await?;
+            for ((user_id, _, want), account) in chunk.iter().zip(accounts) {
+                let onchain = account.and_then(|a| MarginAccount::try_deserialize(&mut a.data.as_slice()).ok());
+                for drift in account_drift(user_id, want, onchain.as_ref()) {
+                    eprintln!("⚠️ Drift: {:?}", drift);
+                    drifted += 1;
+                }
+            }
+        }
+
+        println!(
+            "🔎 Reconciled {} account(s): {} unsettled fill(s), {} orphaned settlement(s), {} settlement(s) without an event, {} event(s) without a settlement, {} drifted position(s)",
+            expected.len(),
+            unsettled.len(),
+            orphaned.len(),
+            unapplied,
+            unrecorded,
+            drifted
+        );
+        Ok(())
+    }
+}
+
+/// Compares an account's on-chain position in every market with its confirmed fills
+/// there (`expected`, by market name). Markets only one side knows of count as size 0
+/// on the other. The nonce isn't compared: it counts every nonce-consuming
+/// instruction, not just settled fills, so individual trades are checked against
+/// their TradeSettled events instead.
+pub fn account_drift(user_id: &str, expected: &[(String, i64)], onchain: Option<&MarginAccount>) -> Vec<AccountDrift> {
+    let mut sizes: BTreeMap<String, (i64, Option<i64>)> =
+        expected.iter().map(|(market, size)| (market.clone(), (*size, onchain.map(|_| 0)))).collect();
+    if let Some(account) = onchain {
+        for position in &account.positions[..account.position_count as usize] {
+            let market = if position.market == UNNAMED_MARKET {
+                common_utils::default_market()
+            } else {
+                String::from_utf8_lossy(&position.market).trim_end_matches('\0').to_string()
+            };
+            let (_, onchain_size) = sizes.entry(market).or_insert((0, Some(0)));
+            *onchain_size = onchain_size.map(|size| size + position.size);
+        }
+    }
+    sizes
+        .into_iter()
+        .filter(|(_, (expected_size, onchain_size))| *onchain_size != Some(*expected_size))
+        .map(|(market, (expected_size, onchain_size))| AccountDrift { user_id: user_id.to_string(), market, expected_size, onchain_size })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hybrid_perp_dex::state::{Position, MAX_POSITIONS};
+
+    const SOL_USDC: [u8; MARKET_NAME_LEN] = *b"SOL_USDC\0\0\0\0\0\0\0\0";
+    const BTC_USDC: [u8; MARKET_NAME_LEN] = *b"BTC_USDC\0\0\0\0\0\0\0\0";
+
+    fn margin_account(held: &[([u8; MARKET_NAME_LEN], i64)], nonce: u64) -> MarginAccount {
+        let mut positions = [Position::default(); MAX_POSITIONS];
+        for (position, &(market, size)) in positions.iter_mut().zip(held) {
+            *position = Position { market, size, avg_entry_price: 100_000_000 };
+        }
+        MarginAccount { owner: Pubkey::new_unique(), collateral: 0, positions, position_count: held.len() as u8, nonce, bump: 255 }
+    }
+
+    fn expected(sizes: &[(&str, i64)]) -> Vec<(String, i64)> {
+        sizes.iter().map(|(market, size)| (market.to_string(), *size)).collect()
+    }
+
+    #[test]
+    fn drift_is_reported_for_position_or_missing_account_but_not_nonce() {
+        let sol = expected(&[("SOL_USDC", 3_000_000)]);
+        assert_eq!(account_drift("alice", &sol, Some(&margin_account(&[(SOL_USDC, 3_000_000)], 2))), []);
+
+        let short = account_drift("alice", &sol, Some(&margin_account(&[(SOL_USDC, 2_000_000)], 2)));
+        assert_eq!(short[0].onchain_size, Some(2_000_000));
+
+        // Nonces consumed by anything other than a settled fill aren't drift
+        assert_eq!(account_drift("alice", &sol, Some(&margin_account(&[(SOL_USDC, 3_000_000)], 7))), []);
+        assert_eq!(account_drift("alice", &sol, None)[0].onchain_size, None);
+    }
+
+    #[test]
+    fn each_market_is_compared_with_its_own_position() {
+        let both = expected(&[("BTC_USDC", -1_000_000), ("SOL_USDC", 3_000_000)]);
+        let account = margin_account(&[(SOL_USDC, 3_000_000), (BTC_USDC, -1_000_000)], 0);
+        assert_eq!(account_drift("alice", &both, Some(&account)), []);
+
+        // Fills in one market don't make up for a missing position in another
+        let netted = margin_account(&[(SOL_USDC, 2_000_000)], 0);
+        let drift = account_drift("alice", &both, Some(&netted));
+        assert_eq!(drift.iter().map(|d| (d.market.as_str(), d.onchain_size)).collect::<Vec<_>>(), [
+            ("BTC_USDC", Some(0)),
+            ("SOL_USDC", Some(2_000_000)),
+        ]);
+
+        // A position with no recorded fills is drift too
+        let drift = account_drift("alice", &expected(&[("SOL_USDC", 3_000_000)]), Some(&account));
+        assert_eq!((drift[0].market.as_str(), drift[0].expected_size), ("BTC_USDC", 0));
+
+        // Positions settled before markets were signed count as SOL_USDC
+        let legacy = margin_account(&[(UNNAMED_MARKET, 1_000_000), (SOL_USDC, 2_000_000), (BTC_USDC, -1_000_000)], 0);
+        assert_eq!(account_drift("alice", &both, Some(&legacy)), []);
+    }
+}
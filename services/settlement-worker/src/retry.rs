@@ -0,0 +1,210 @@
+use common_utils::MatchResult;
+use fred::prelude::*;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SETTLEMENT_QUEUE: &str = "SETTLEMENT_QUEUE";
+/// Sorted set of trades waiting out a backoff, scored by the unix ms they're due.
+pub const SETTLEMENT_RETRY: &str = "SETTLEMENT_RETRY";
+
+/// A `SETTLEMENT_QUEUE` entry. The engine pushes bare `MatchResult`s; the retry
+/// bookkeeping is added by the worker when it puts a trade back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedTrade {
+    #[serde(flatten)]
+    pub trade: MatchResult,
+    #[serde(default)]
+    pub attempts: u32,
+    // Set after its batch was rejected, so the offending trade can be singled out
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub isolated: bool,
+}
+
+impl QueuedTrade {
+    pub fn new(trade: MatchResult) -> Self {
+        Self { trade, attempts: 0, isolated: false }
+    }
+}
+
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u64) -> u64 {
+            std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+        Self {
+            max_attempts: var("SETTLEMENT_MAX_ATTEMPTS", 5).max(1) as u32,
+            base: Duration::from_millis(var("SETTLEMENT_RETRY_BASE_MS", 500)),
+            max: Duration::from_millis(var("SETTLEMENT_RETRY_MAX_MS", 30_000)),
+        }
+    }
+
+    /// Delay before the next try after `attempts` failed ones: base, 2x base, 4x base...
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempts.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Parks `trades` until `delay` has passed. They come back in the given order.
+pub async fn schedule(redis: &RedisClient, trades: &[QueuedTrade], delay: Duration) -> RedisResult<()> {
+    let due = now_ms() + delay.as_millis() as u64;
+    for (i, trade) in trades.iter().enumerate() {
+        // Offset by a millisecond each so trades due together keep their order
+        park(redis, trade, (due + i as u64) as f64).await?;
+    }
+    Ok(())
+}
+
+async fn park(redis: &RedisClient, trade: &QueuedTrade, due: f64) -> RedisResult<()> {
+    let json = serde_json::to_string(trade).expect("QueuedTrade serializes");
+    redis.zadd::<(), _, _>(SETTLEMENT_RETRY, None, None, false, false, (due, json)).await
+}
+
+/// Parks the trades that touch an account with an older trade still waiting in
+/// `SETTLEMENT_RETRY`, so they can't settle ahead of it. Returns the rest, in order.
+/// If Redis fails, trades go ahead rather than being lost.
+pub async fn hold_back(
+    redis: &RedisClient,
+    trades: Vec<QueuedTrade>,
+    accounts: impl Fn(&MatchResult) -> Vec<Pubkey>,
+) -> Vec<QueuedTrade> {
+    let waiting: Vec<(String, f64)> = match redis.zrange(SETTLEMENT_RETRY, 0, -1, None, false, None, true).await {
+        Ok(waiting) => waiting,
+        Err(e) => {
+            eprintln!("❌ Failed to read pending retries: {}", e);
+            return trades;
+        }
+    };
+    if waiting.is_empty() {
+        return trades;
+    }
+    let waiting = waiting.into_iter().filter_map(|(json, due)| Some((serde_json::from_str(&json).ok()?, due)));
+    let (mut free, held) = split_blocked(waiting, trades, accounts);
+    for (trade, due) in held {
+        match park(redis, &trade, due).await {
+            Ok(()) => println!("⏸️ Trade {} waits behind a retry for the same account", trade.trade.trade_id),
+            Err(e) => {
+                eprintln!("❌ Failed to hold back trade {}: {}", trade.trade.trade_id, e);
+                free.push(trade);
+            }
+        }
+    }
+    free
+}
+
+/// Splits `trades` into those free to settle now and those blocked by an older
+/// trade in `waiting` (or by one held back before them), each with a due time
+/// just after the last trade it would otherwise overtake. Age is the engine's
+/// sequence number.
+fn split_blocked(
+    waiting: impl IntoIterator<Item = (QueuedTrade, f64)>,
+    trades: Vec<QueuedTrade>,
+    accounts: impl Fn(&MatchResult) -> Vec<Pubkey>,
+) -> (Vec<QueuedTrade>, Vec<(QueuedTrade, f64)>) {
+    // Sequence and due time of every trade waiting on each account
+    let mut waiting_on: HashMap<Pubkey, Vec<(u64, f64)>> = HashMap::new();
+    for (queued, due) in waiting {
+        for account in accounts(&queued.trade) {
+            waiting_on.entry(account).or_default().push((queued.trade.sequence, due));
+        }
+    }
+
+    let (mut free, mut held) = (Vec::new(), Vec::new());
+    for queued in trades {
+        let keys = accounts(&queued.trade);
+        let blocker = keys
+            .iter()
+            .filter_map(|k| waiting_on.get(k))
+            .flatten()
+            .filter(|(sequence, _)| *sequence < queued.trade.sequence)
+            .map(|(_, due)| *due)
+            .reduce(f64::max);
+        let Some(blocker) = blocker else {
+            free.push(queued);
+            continue;
+        };
+        // Half a millisecond: behind the blocker, ahead of whatever was scheduled after it
+        let due = blocker + 0.5;
+        for key in keys {
+            waiting_on.entry(key).or_default().push((queued.trade.sequence, due));
+        }
+        held.push((queued, due));
+    }
+    (free, held)
+}
+
+/// Moves trades whose backoff has elapsed back to the consuming end of the queue.
+pub async fn promote_due(redis: &RedisClient) -> RedisResult<()> {
+    let due: Vec<String> = redis.zrangebyscore(SETTLEMENT_RETRY, "-inf", now_ms() as f64, false, None).await?;
+    // Pushed newest first so the oldest is popped first
+    for json in due.into_iter().rev() {
+        let removed: i64 = redis.zrem(SETTLEMENT_RETRY, json.clone()).await?;
+        if removed == 1 {
+            redis.rpush::<(), _, _>(SETTLEMENT_QUEUE, json).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(sequence: u64, buyer: Pubkey, seller: Pubkey) -> QueuedTrade {
+        QueuedTrade::new(MatchResult {
+            trade_id: sequence,
+            market: "SOL_USDC".into(),
+            buyer_id: buyer.to_string(),
+            seller_id: seller.to_string(),
+            price: 1.into(),
+            quantity: 1.into(),
+            sequence,
+            timestamp_ns: 0,
+        })
+    }
+
+    fn accounts(m: &MatchResult) -> Vec<Pubkey> {
+        vec![m.buyer_id.parse().unwrap(), m.seller_id.parse().unwrap()]
+    }
+
+    fn ids(trades: &[QueuedTrade]) -> Vec<u64> {
+        trades.iter().map(|q| q.trade.trade_id).collect()
+    }
+
+    #[test]
+    fn trades_behind_a_retry_for_the_same_account_are_held_back() {
+        let [a, b, c, d, e] = std::array::from_fn(|_| Pubkey::new_unique());
+        let waiting = [(trade(1, a, b), 5_000.0)];
+        let trades = vec![trade(2, d, e), trade(3, b, c), trade(4, c, d), trade(5, a, e)];
+
+        let (free, held) = split_blocked(waiting, trades, accounts);
+        // 3 shares b with the retry; 4 shares c with 3, which is now held too.
+        // 5 only waits for the retry: 2 isn't held, so e is free
+        assert_eq!(ids(&free), [2]);
+        let held: Vec<(u64, f64)> = held.iter().map(|(q, due)| (q.trade.trade_id, *due)).collect();
+        assert_eq!(held, [(3, 5_000.5), (4, 5_001.0), (5, 5_000.5)]);
+    }
+
+    #[test]
+    fn a_retry_is_not_held_back_by_newer_trades_for_its_accounts() {
+        let [a, b] = std::array::from_fn(|_| Pubkey::new_unique());
+        // 2 was parked behind 1; 1 is back first and must not wait for 2
+        let waiting = [(trade(2, a, b), 5_000.5)];
+
+        let (free, held) = split_blocked(waiting, vec![trade(1, a, b)], accounts);
+        assert_eq!(ids(&free), [1]);
+        assert!(held.is_empty());
+    }
+}
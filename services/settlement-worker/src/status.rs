@@ -0,0 +1,108 @@
+use settlement_client::submission::{LogFuture, Submission, SubmissionLog};
+use solana_sdk::signature::Signature;
+use std::str::FromStr;
+use tokio_postgres::{Client, NoTls};
+
+use crate::retry::now_ms;
+
+/// Per-trade settlement status in Postgres:
+/// `pending` (queued or waiting to retry) -> `submitted` (with signature) -> `confirmed` (with slot),
+/// or `failed` once the trade is dead-lettered.
+///
+/// A confirmed settlement is final; a trade replayed from the DLQ after it actually
+/// landed is never downgraded. The last signature is kept through retries so the
+/// settlement client can check whether it landed before signing again.
+pub struct StatusStore {
+    client: Client,
+}
+
+impl StatusStore {
+    pub async fn connect(db_url: &str) -> anyhow::Result<Self> {
+        let (client, connection) = tokio_postgres::connect(db_url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("❌ Postgres connection error: {}", e);
+            }
+        });
+
+        // The settlements table comes from the shared migrations (`db-processor migrate`)
+        db_migrations::ensure_current(&client).await?;
+        Ok(Self { client })
+    }
+
+    pub async fn pending(&self, trade_id: u64, attempts: u32, error: Option<&str>) {
+        let res = self.client.execute(
+            "INSERT INTO settlements (trade_id, status, attempts, error, updated_at)
+                VALUES ($1, 'pending', $2, $3, $4)
+                ON CONFLICT (trade_id) DO UPDATE
+                SET status = 'pending', attempts = EXCLUDED.attempts, error = COALESCE(EXCLUDED.error, settlements.error), updated_at = EXCLUDED.updated_at
+                WHERE settlements.status <> 'confirmed'",
+            &[&(trade_id as i64), &(attempts as i32), &error, &(now_ms() as i64)],
+        ).await;
+        if let Err(e) = res {
+            eprintln!("❌ Failed to record pending settlement for {}: {}", trade_id, e);
+        }
+    }
+
+    pub async fn failed(&self, trade_id: u64, attempts: u32, error: &str) {
+        let res = self.client.execute(
+            "UPDATE settlements SET status = 'failed', attempts = $2, error = $3, updated_at = $4
+                WHERE trade_id = $1 AND status <> 'confirmed'",
+            &[&(trade_id as i64), &(attempts as i32), &error, &(now_ms() as i64)],
+        ).await;
+        if let Err(e) = res {
+            eprintln!("❌ Failed to record failed settlement for {}: {}", trade_id, e);
+        }
+    }
+}
+
+impl SubmissionLog for StatusStore {
+    fn submitted<'a>(&'a self, trade_ids: &'a [u64], signature: &'a Signature, last_valid_block_height: u64) -> LogFuture<'a, ()> {
+        Box::pin(async move {
+            self.client.execute(
+                "INSERT INTO settlements (trade_id, status, signature, last_valid_block_height, updated_at)
+                    SELECT id, 'submitted', $2, $3, $4 FROM UNNEST($1::BIGINT[]) AS id
+                    ON CONFLICT (trade_id) DO UPDATE
+                    SET status = 'submitted', signature = EXCLUDED.signature,
+                        last_valid_block_height = EXCLUDED.last_valid_block_height, updated_at = EXCLUDED.updated_at
+                    WHERE settlements.status <> 'confirmed'",
+                &[&ids(trade_ids), &signature.to_string(), &(last_valid_block_height as i64), &(now_ms() as i64)],
+            ).await?;
+            Ok(())
+        })
+    }
+
+    fn confirmed<'a>(&'a self, trade_ids: &'a [u64], signature: &'a Signature, slot: u64) -> LogFuture<'a, ()> {
+        Box::pin(async move {
+            self.client.execute(
+                "UPDATE settlements SET status = 'confirmed', signature = $2, slot = $3, error = NULL, updated_at = $4
+                    WHERE trade_id = ANY($1)",
+                &[&ids(trade_ids), &signature.to_string(), &(slot as i64), &(now_ms() as i64)],
+            ).await?;
+            Ok(())
+        })
+    }
+
+    fn submissions<'a>(&'a self, trade_ids: &'a [u64]) -> LogFuture<'a, Vec<Submission>> {
+        Box::pin(async move {
+            let rows = self.client.query(
+                "SELECT trade_id, signature, last_valid_block_height FROM settlements
+                    WHERE trade_id = ANY($1) AND signature IS NOT NULL AND last_valid_block_height IS NOT NULL",
+                &[&ids(trade_ids)],
+            ).await?;
+            rows.iter()
+                .map(|row| {
+                    Ok(Submission {
+                        trade_id: row.get::<_, i64>(0) as u64,
+                        signature: Signature::from_str(row.get(1))?,
+                        last_valid_block_height: row.get::<_, i64>(2) as u64,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+fn ids(trade_ids: &[u64]) -> Vec<i64> {
+    trade_ids.iter().map(|id| *id as i64).collect()
+}